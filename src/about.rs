@@ -0,0 +1,113 @@
+use serde::{Deserialize, Serialize};
+
+use crate::instructions::{self, InstructionClass, INSTRUCTIONS};
+use crate::lexer::{KEYWORDS, TASK_ATTRIBUTES, TokenType};
+use crate::options::{DEFAULT_MAX_OUTPUT_SIZE, DEFAULT_NAME_LIMIT, DEFAULT_TAG_NAME_LIMIT};
+use crate::parse::PERIOD_LOWER_BOUND;
+use crate::schema::{Artifact, SchemaVersion};
+
+/// Versions of the LogText language this compiler accepts
+const LANGUAGE_VERSIONS: [&str; 1] = ["1"];
+
+/// Languages the compiler can generate
+const TARGETS: [&str; 1] = ["python"];
+
+/// Machine readable description of what the compiler supports
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct About {
+    #[serde(flatten)]
+    schema: SchemaVersion,
+    version: String,
+    language_versions: Vec<String>,
+    keywords: Vec<String>,
+    input_instructions: Vec<String>,
+    output_instructions: Vec<String>,
+    instructions: Vec<Instruction>,
+    task_attributes: Vec<String>,
+    targets: Vec<String>,
+    limits: Limits
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+struct Instruction {
+    keyword: String,
+    operands: Vec<String>
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+struct Limits {
+    tag_name_length: usize,
+    name_length: usize,
+    minimum_period: u32,
+    max_output_size: usize
+}
+
+/// Looks up the keyword spelling of each token type
+fn keywords_of(token_types: &[TokenType]) -> Vec<String> {
+    KEYWORDS.iter()
+            .filter(|(_, token_type)| token_types.contains(token_type))
+            .map(|(keyword, _)| keyword.to_string())
+            .collect()
+}
+
+impl About {
+    pub fn new() -> About {
+        About {
+            schema: SchemaVersion::of(Artifact::About),
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            language_versions: LANGUAGE_VERSIONS.iter().map(|version| version.to_string()).collect(),
+            keywords: KEYWORDS.iter().map(|(keyword, _)| keyword.to_string()).collect(),
+            input_instructions: instructions::keywords(InstructionClass::Input),
+            output_instructions: instructions::keywords(InstructionClass::Output),
+            instructions: INSTRUCTIONS.iter().map(|spec| Instruction {
+                keyword: spec.keyword.to_string(),
+                operands: spec.operands.iter().map(|operand| operand.name().to_string()).collect()
+            }).collect(),
+            task_attributes: keywords_of(&TASK_ATTRIBUTES),
+            targets: TARGETS.iter().map(|target| target.to_string()).collect(),
+            limits: Limits {
+                tag_name_length: DEFAULT_TAG_NAME_LIMIT,
+                name_length: DEFAULT_NAME_LIMIT,
+                minimum_period: PERIOD_LOWER_BOUND,
+                max_output_size: DEFAULT_MAX_OUTPUT_SIZE
+            }
+        }
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).unwrap()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::lexer::{Lexer, Token};
+
+    #[test]
+    fn test_about_round_trip() {
+        let about = About::new();
+        let parsed: About = serde_json::from_str(&about.to_json()).unwrap();
+        assert_eq!(about, parsed);
+    }
+
+    #[test]
+    fn test_about_keywords() {
+        let json: serde_json::Value = serde_json::from_str(&About::new().to_json()).unwrap();
+        let keywords = json["keywords"].as_array().unwrap();
+
+        // Every keyword the lexer recognizes must be listed
+        for (keyword, token_type) in KEYWORDS {
+            let mut lexer = Lexer::new(keyword.to_string());
+            assert_eq!(token_type, *lexer.get_token().get_type());
+            assert_eq!(Some(token_type), Token::is_keyword(keyword));
+            assert!(keywords.contains(&serde_json::Value::from(keyword)), "{}", keyword);
+        }
+        assert_eq!(KEYWORDS.len(), keywords.len());
+
+        assert_eq!(json["input_instructions"], serde_json::json!(["XIC", "XIO", "EQU", "NEQ", "GRT", "LES", "ONS", "LBL"]));
+        assert_eq!(json["instructions"][5], serde_json::json!({"keyword": "JSR", "operands": ["routine"]}));
+        assert_eq!(json["task_attributes"], serde_json::json!(["PERIOD", "EVENT", "CONTINUOUS"]));
+        assert_eq!(json["limits"]["minimum_period"], 20);
+    }
+}
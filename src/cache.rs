@@ -0,0 +1,265 @@
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use std::process;
+
+use serde::{Deserialize, Serialize};
+
+use crate::header;
+use crate::options::CompilerOptions;
+use crate::schema::{Artifact, SchemaVersion};
+use crate::stats::Metrics;
+
+const OUTPUT_FILE: &str = "output";
+const DIAGNOSTICS_FILE: &str = "diagnostics.json";
+
+/// Whether a build may take its output from the cache and whether it stores its output there
+#[derive(Debug, Clone, Copy, Default, PartialEq, clap::ValueEnum)]
+pub enum CacheMode {
+    /// Only use outputs that are already cached
+    Read,
+    /// Always compile, storing the output for later builds
+    Write,
+    /// Use cached outputs and store the ones that aren't cached yet
+    #[default]
+    Readwrite,
+    /// Ignore the cache
+    Off
+}
+
+impl CacheMode {
+    fn reads(&self) -> bool {
+        matches!(self, CacheMode::Read | CacheMode::Readwrite)
+    }
+
+    fn writes(&self) -> bool {
+        matches!(self, CacheMode::Write | CacheMode::Readwrite)
+    }
+}
+
+/// What a build reported besides its output, so that a build served from the cache can report the same
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct Diagnostics {
+    #[serde(flatten)]
+    pub schema: SchemaVersion,
+
+    /// Key of the entry, so that an entry moved to another key isn't served
+    pub key: String,
+
+    /// Hash of the output, so that a truncated or corrupted output isn't served
+    pub output_hash: String,
+
+    pub warnings: Vec<String>,
+
+    /// Name and version of the program, if it declared them
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub program: Option<(String, String)>,
+
+    pub metrics: Metrics,
+    pub routine_sizes: Vec<(String, usize)>
+}
+
+/// Output of a build along with its diagnostics
+pub struct Entry {
+    pub output: String,
+    pub diagnostics: Diagnostics
+}
+
+impl Entry {
+    pub fn new(key: &str, output: String, warnings: Vec<String>, program: Option<(String, String)>,
+               metrics: Metrics, routine_sizes: Vec<(String, usize)>) -> Entry {
+        let diagnostics = Diagnostics {
+            schema: SchemaVersion::of(Artifact::CacheDiagnostics),
+            key: key.to_string(),
+            output_hash: format!("{:016x}", header::hash(&output)),
+            warnings,
+            program,
+            metrics,
+            routine_sizes
+        };
+        Entry { output, diagnostics }
+    }
+}
+
+/// Result of looking up a key
+pub enum Lookup {
+    Hit(Box<Entry>),
+    Miss,
+    /// There is an entry, but it can't be trusted for the given reason
+    Damaged(String)
+}
+
+/// Key of a build, from everything its output depends on. `extra` holds anything outside of the
+/// source and the options, like the header template
+pub fn key(source_code: &str, options: &CompilerOptions, extra: &str) -> String {
+    let fingerprint = format!("{}\0{}\0{:?}\0{}\0{}\0{:?}\0{:?}\0{:?}\0{:?}\0{:?}\0{}\0{:?}\0{}\0{}",
+                              env!("CARGO_PKG_VERSION"),
+                              source_code,
+                              options.scheduler_tick,
+                              options.deny_warnings,
+                              options.strict,
+                              options.max_output_size,
+                              options.tag_name_limit,
+                              options.name_limit,
+                              options.policy.as_ref().map(|policy| policy.hash()),
+                              options.tag_order,
+                              options.legacy_tag_declarations,
+                              options.index_bounds,
+                              extra.len(),
+                              extra);
+    format!("{:016x}", header::hash(&fingerprint))
+}
+
+/// Directory of outputs of earlier builds, one directory per key. Entries are never removed,
+/// which is left to whatever manages the directory
+pub struct Cache {
+    directory: PathBuf,
+    mode: CacheMode
+}
+
+impl Cache {
+    pub fn new(directory: &str, mode: CacheMode) -> Cache {
+        Cache { directory: PathBuf::from(directory), mode }
+    }
+
+    /// Directory the build stores its output in, unless it doesn't store it at all
+    pub fn entry_to_write(&self, key: &str) -> Option<PathBuf> {
+        let hit = matches!(self.read(key), Lookup::Hit(_));
+        (self.mode.writes() && !hit).then(|| self.directory.join(key))
+    }
+
+    pub fn read(&self, key: &str) -> Lookup {
+        let entry_directory = self.directory.join(key);
+        if !self.mode.reads() || !entry_directory.is_dir() {
+            return Lookup::Miss;
+        }
+
+        let diagnostics = match fs::read_to_string(entry_directory.join(DIAGNOSTICS_FILE)) {
+            Ok(text) => text,
+            Err(error) => return Lookup::Damaged(format!("its diagnostics can't be read: {}", error))
+        };
+        let diagnostics: Diagnostics = match serde_json::from_str(&diagnostics) {
+            Ok(diagnostics) => diagnostics,
+            Err(error) => return Lookup::Damaged(format!("its diagnostics are invalid: {}", error))
+        };
+        if diagnostics.schema != SchemaVersion::of(Artifact::CacheDiagnostics) || diagnostics.key != key {
+            return Lookup::Damaged("its diagnostics are of another entry".to_string());
+        }
+
+        let output = match fs::read_to_string(entry_directory.join(OUTPUT_FILE)) {
+            Ok(output) => output,
+            Err(error) => return Lookup::Damaged(format!("its output can't be read: {}", error))
+        };
+        if format!("{:016x}", header::hash(&output)) != diagnostics.output_hash {
+            return Lookup::Damaged("its output doesn't match its hash".to_string());
+        }
+        Lookup::Hit(Box::new(Entry { output, diagnostics }))
+    }
+
+    /// Stores an entry under its key, replacing any entry already there. The entry is written
+    /// next to the others and moved into place, so that other builds never see half of it
+    pub fn write(&self, entry: &Entry) -> io::Result<()> {
+        if !self.mode.writes() {
+            return Ok(());
+        }
+
+        let key = &entry.diagnostics.key;
+        let staging = self.directory.join(format!("{}.{}.tmp", key, process::id()));
+        fs::create_dir_all(&staging)?;
+        let staged = fs::write(staging.join(OUTPUT_FILE), &entry.output)
+            .and_then(|_| fs::write(staging.join(DIAGNOSTICS_FILE), serde_json::to_string_pretty(&entry.diagnostics).unwrap()));
+        if let Err(error) = staged {
+            let _ = fs::remove_dir_all(&staging);
+            return Err(error);
+        }
+
+        let entry_directory = self.directory.join(key);
+        if entry_directory.exists() {
+            fs::remove_dir_all(&entry_directory)?;
+        }
+        if let Err(error) = fs::rename(&staging, &entry_directory) {
+            let _ = fs::remove_dir_all(&staging);
+            // Another build storing the same key at the same time got there first
+            if !entry_directory.is_dir() {
+                return Err(error);
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn cache(name: &str, mode: CacheMode) -> Cache {
+        let directory = std::env::temp_dir().join(format!("logtext_cache_{}", name));
+        let _ = fs::remove_dir_all(&directory);
+        Cache::new(directory.to_str().unwrap(), mode)
+    }
+
+    fn entry(key: &str) -> Entry {
+        Entry::new(key, "# Compiled by LogTextCompiler\n".to_string(), vec!["TEMP motor hides the tag motor within its rung".to_string()],
+                   Some(("Cell".to_string(), "1.0".to_string())), Metrics::default(), vec![("Main".to_string(), 10)])
+    }
+
+    #[test]
+    fn test_key() {
+        let options = CompilerOptions::default();
+        let key = key("TAG a = FALSE", &options, "");
+        assert_eq!(key, super::key("TAG a = FALSE", &options, ""));
+        assert_ne!(key, super::key("TAG a = TRUE", &options, ""));
+        assert_ne!(key, super::key("TAG a = FALSE", &CompilerOptions { strict: true, ..Default::default() }, ""));
+        assert_ne!(key, super::key("TAG a = FALSE", &options, "# {program}"));
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let cache = cache("round_trip", CacheMode::Readwrite);
+        assert!(matches!(cache.read("0123"), Lookup::Miss));
+
+        cache.write(&entry("0123")).unwrap();
+        match cache.read("0123") {
+            Lookup::Hit(hit) => {
+                assert_eq!(entry("0123").output, hit.output);
+                assert_eq!(entry("0123").diagnostics, hit.diagnostics);
+            },
+            _ => panic!("Stored entry wasn't found")
+        }
+
+        // Storing the same key again replaces the entry
+        cache.write(&entry("0123")).unwrap();
+        assert!(matches!(cache.read("0123"), Lookup::Hit(_)));
+    }
+
+    #[test]
+    fn test_damaged() {
+        let cache = cache("damaged", CacheMode::Readwrite);
+        cache.write(&entry("0123")).unwrap();
+        fs::write(cache.directory.join("0123").join(OUTPUT_FILE), "# Compiled").unwrap();
+        assert!(matches!(cache.read("0123"), Lookup::Damaged(reason) if reason == "its output doesn't match its hash"));
+
+        cache.write(&entry("0123")).unwrap();
+        fs::write(cache.directory.join("0123").join(DIAGNOSTICS_FILE), "{\"key\": ").unwrap();
+        assert!(matches!(cache.read("0123"), Lookup::Damaged(reason) if reason.starts_with("its diagnostics are invalid")));
+
+        // An entry copied to another key is of another build
+        cache.write(&entry("0123")).unwrap();
+        fs::rename(cache.directory.join("0123"), cache.directory.join("4567")).unwrap();
+        assert!(matches!(cache.read("4567"), Lookup::Damaged(reason) if reason == "its diagnostics are of another entry"));
+    }
+
+    #[test]
+    fn test_modes() {
+        let cache = cache("modes", CacheMode::Read);
+        cache.write(&entry("0123")).unwrap();
+        assert!(!cache.directory.join("0123").exists());
+
+        let cache = Cache { mode: CacheMode::Write, ..cache };
+        cache.write(&entry("0123")).unwrap();
+        assert!(matches!(cache.read("0123"), Lookup::Miss));
+
+        let cache = Cache { mode: CacheMode::Off, ..cache };
+        assert!(matches!(cache.read("0123"), Lookup::Miss));
+    }
+}
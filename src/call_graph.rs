@@ -0,0 +1,206 @@
+/// A JSR from one routine to another
+#[derive(Debug, Clone)]
+struct RoutineCall {
+    task: String,
+    caller: String,
+    callee: String
+}
+
+/// Tracks which routines each task can reach through JSR
+#[derive(Default)]
+pub struct CallGraph {
+    tasks: Vec<String>,
+    routines: Vec<(String, String)>,
+    entries: Vec<(String, String)>,
+    calls: Vec<RoutineCall>,
+    current_routine: String
+}
+
+impl CallGraph {
+    pub fn new() -> CallGraph {
+        CallGraph::default()
+    }
+
+    pub fn start_task(&mut self, name: &str) {
+        self.tasks.push(name.to_string());
+    }
+
+    pub fn start_routine(&mut self, name: &str) {
+        let task = self.tasks.last().cloned().unwrap_or_default();
+        self.routines.push((task, name.to_string()));
+        self.current_routine = name.to_string();
+    }
+
+    /// Sets the routine the current task starts with, which is Main unless told otherwise
+    pub fn set_entry(&mut self, routine: &str) {
+        let task = self.tasks.last().cloned().unwrap_or_default();
+        self.entries.push((task, routine.to_string()));
+    }
+
+    pub fn entry(&self, task: &str) -> (String, String) {
+        let routine = self.entries.iter()
+                                  .find(|(owner, _)| owner == task)
+                                  .map(|(_, routine)| routine.clone())
+                                  .unwrap_or_else(|| "Main".to_string());
+        (task.to_string(), routine)
+    }
+
+    pub fn add_call(&mut self, callee: &str) {
+        let task = self.tasks.last().cloned().unwrap_or_default();
+        self.calls.push(RoutineCall { task, caller: self.current_routine.clone(), callee: callee.to_string() });
+    }
+
+    /// Finds the routine a JSR refers to, preferring one defined in the calling task
+    pub fn resolve(&self, task: &str, callee: &str) -> Option<(String, String)> {
+        self.routines.iter()
+                     .find(|(owner, routine)| owner == task && routine == callee)
+                     .or_else(|| self.routines.iter().find(|(_, routine)| routine == callee))
+                     .cloned()
+    }
+
+    /// Routines reachable from the entry routine of `task`, as (owning task, routine) pairs
+    fn reachable(&self, task: &str) -> Vec<(String, String)> {
+        let mut reached = vec![self.entry(task)];
+        let mut index = 0;
+        while index < reached.len() {
+            let (owner, routine) = reached[index].clone();
+            for call in self.calls.iter().filter(|call| call.task == owner && call.caller == routine) {
+                if let Some(callee) = self.resolve(&call.task, &call.callee) {
+                    if !reached.contains(&callee) {
+                        reached.push(callee);
+                    }
+                }
+            }
+            index += 1;
+        }
+        reached
+    }
+
+    /// Longest chain of JSRs starting from the entry routine of each task
+    pub fn jsr_depths(&self) -> Vec<(String, usize)> {
+        self.tasks.iter()
+                  .map(|task| {
+                      let mut path = vec![self.entry(task)];
+                      (task.clone(), self.depth(&mut path))
+                  })
+                  .collect()
+    }
+
+    fn depth(&self, path: &mut Vec<(String, String)>) -> usize {
+        let (owner, routine) = path.last().unwrap().clone();
+        let mut deepest = 0;
+        for call in self.calls.iter().filter(|call| call.task == owner && call.caller == routine) {
+            // Don't follow recursive calls around forever
+            if let Some(callee) = self.resolve(&call.task, &call.callee).filter(|callee| !path.contains(callee)) {
+                path.push(callee);
+                deepest = deepest.max(1 + self.depth(path));
+                path.pop();
+            }
+        }
+        deepest
+    }
+
+    /// Every routine reachable from more than one task, along with the tasks that reach it
+    pub fn shared_routines(&self) -> Vec<(String, Vec<String>)> {
+        let reachable = self.tasks.iter()
+                                  .map(|task| (task, self.reachable(task)))
+                                  .collect::<Vec<_>>();
+
+        let mut shared = Vec::new();
+        for routine in &self.routines {
+            let tasks = reachable.iter()
+                                 .filter(|(_, reached)| reached.contains(routine))
+                                 .map(|(task, _)| task.to_string())
+                                 .collect::<Vec<String>>();
+            if tasks.len() > 1 {
+                shared.push((routine.1.clone(), tasks));
+            }
+        }
+        shared
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_shared_routines() {
+        let mut call_graph = CallGraph::new();
+
+        call_graph.start_task("Periodic");
+        call_graph.start_routine("Main");
+        call_graph.add_call("Helper");
+        call_graph.start_routine("Helper");
+        call_graph.add_call("Valve");
+        call_graph.start_routine("Valve");
+        call_graph.start_routine("Unused");
+
+        call_graph.start_task("OnAlarm");
+        call_graph.start_routine("Main");
+        call_graph.add_call("Helper");
+
+        call_graph.start_task("Other");
+        call_graph.start_routine("Main");
+        call_graph.add_call("Unused");
+
+        let expected = vec![
+            ("Helper".to_string(), vec!["Periodic".to_string(), "OnAlarm".to_string()]),
+            ("Valve".to_string(), vec!["Periodic".to_string(), "OnAlarm".to_string()])
+        ];
+        assert_eq!(expected, call_graph.shared_routines());
+    }
+
+    #[test]
+    fn test_jsr_depths() {
+        let mut call_graph = CallGraph::new();
+
+        call_graph.start_task("Deep");
+        call_graph.start_routine("Main");
+        call_graph.add_call("First");
+        call_graph.add_call("Second");
+        call_graph.start_routine("First");
+        call_graph.start_routine("Second");
+        call_graph.add_call("Third");
+        call_graph.start_routine("Third");
+        call_graph.add_call("Second");
+
+        call_graph.start_task("Flat");
+        call_graph.start_routine("Main");
+
+        assert_eq!(vec![("Deep".to_string(), 2), ("Flat".to_string(), 0)], call_graph.jsr_depths());
+    }
+
+    #[test]
+    fn test_entry_routine() {
+        let mut call_graph = CallGraph::new();
+
+        call_graph.start_task("Periodic");
+        call_graph.start_routine("Startup");
+        call_graph.add_call("Helper");
+        call_graph.start_routine("Helper");
+        call_graph.set_entry("Startup");
+
+        call_graph.start_task("OnAlarm");
+        call_graph.start_routine("Main");
+        call_graph.add_call("Helper");
+
+        let expected = vec![("Helper".to_string(), vec!["Periodic".to_string(), "OnAlarm".to_string()])];
+        assert_eq!(expected, call_graph.shared_routines());
+    }
+
+    #[test]
+    fn test_local_routines_not_shared() {
+        let mut call_graph = CallGraph::new();
+
+        // Both tasks have their own Helper, so each JSR stays within its task
+        for task in ["First", "Second"] {
+            call_graph.start_task(task);
+            call_graph.start_routine("Main");
+            call_graph.add_call("Helper");
+            call_graph.start_routine("Helper");
+        }
+
+        assert!(call_graph.shared_routines().is_empty());
+    }
+}
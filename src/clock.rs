@@ -0,0 +1,91 @@
+use std::env;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Where every time written out by the compiler comes from
+pub trait Clock {
+    /// Seconds since the Unix epoch
+    fn now(&self) -> u64;
+}
+
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> u64 {
+        SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs()
+    }
+}
+
+/// Clock stopped at one moment, so that builds and tests give the same output every time
+pub struct FixedClock(pub u64);
+
+impl Clock for FixedClock {
+    fn now(&self) -> u64 {
+        self.0
+    }
+}
+
+/// Clock for a build. Reproducible builds have no time unless SOURCE_DATE_EPOCH pins one
+pub fn build_clock(reproducible: bool) -> Option<Box<dyn Clock>> {
+    match env::var("SOURCE_DATE_EPOCH") {
+        Ok(epoch) => {
+            let seconds = epoch.trim().parse().unwrap_or_else(|_| panic!("SOURCE_DATE_EPOCH must be a whole number of seconds, not {}", epoch));
+            Some(Box::new(FixedClock(seconds)))
+        },
+        Err(_) if reproducible => None,
+        Err(_) => Some(Box::new(SystemClock))
+    }
+}
+
+/// Time in UTC as RFC 3339, e.g. 2024-03-05T14:07:09Z
+pub fn timestamp(seconds: u64) -> String {
+    let time_of_day = seconds % 86400;
+    format!("{}T{:02}:{:02}:{:02}Z", date(seconds), time_of_day / 3600, time_of_day / 60 % 60, time_of_day % 60)
+}
+
+/// Date in UTC as YYYY-MM-DD
+pub fn date(seconds: u64) -> String {
+    let (year, month, day) = civil_from_days((seconds / 86400) as i64);
+    format!("{:04}-{:02}-{:02}", year, month, day)
+}
+
+/// Converts days since 1970-01-01 into a (year, month, day) date
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    // Shift the epoch to 0000-03-01 so leap days fall at the end of the year
+    let days = days + 719468;
+    let era = days.div_euclid(146097);
+    let day_of_era = days.rem_euclid(146097);
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let day = (day_of_year - (153 * shifted_month + 2) / 5 + 1) as u32;
+    let month = if shifted_month < 10 { shifted_month + 3 } else { shifted_month - 9 } as u32;
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_civil_from_days() {
+        assert_eq!((1970, 1, 1), civil_from_days(0));
+        assert_eq!((2000, 2, 29), civil_from_days(11016));
+        assert_eq!((2024, 12, 31), civil_from_days(20088));
+    }
+
+    #[test]
+    fn test_timestamp() {
+        assert_eq!("1970-01-01T00:00:00Z", timestamp(0));
+        assert_eq!("2000-02-29T01:01:01Z", timestamp(951782400 + 3661));
+        assert_eq!("2024-12-31T23:59:59Z", timestamp(1735689599));
+        assert_eq!("2024-12-31", date(1735689599));
+    }
+
+    #[test]
+    fn test_fixed_clock() {
+        let clock: Box<dyn Clock> = Box::new(FixedClock(1735689599));
+        assert_eq!(timestamp(clock.now()), timestamp(clock.now()));
+        assert_eq!(1735689599, clock.now());
+    }
+}
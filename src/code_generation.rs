@@ -1,18 +1,144 @@
 
+use std::collections::{HashMap, HashSet};
+
 use crate::lexer::TokenType;
+use crate::instructions::{self, InstructionClass, OperandKind};
+use crate::options::IndexBounds;
+
+const PYTHON_KEYWORDS: [&str; 35] = ["False", "None", "True", "and", "as", "assert", "async", "await", "break",
+                                     "class", "continue", "def", "del", "elif", "else", "except", "finally",
+                                     "for", "from", "global", "if", "import", "in", "is", "lambda", "nonlocal",
+                                     "not", "or", "pass", "raise", "return", "try", "while", "with", "yield"];
+
+/// Turns a LogText name into a valid Python identifier. LogText names can't
+/// contain underscores, so the suffix never collides with another name.
+/// Elements of tag arrays become list indexing and bits of timers dictionary lookups
+fn mangle(name: &str) -> String {
+    match name.split_once('.') {
+        Some((tag, index)) if index.chars().all(|character| character.is_ascii_digit()) => format!("{}[{}]", mangle(tag), index),
+        Some((tag, bit)) => format!("{}[\"{}\"]", mangle(tag), bit),
+        None if PYTHON_KEYWORDS.contains(&name) => format!("{}_", name),
+        None => name.to_string()
+    }
+}
+
+/// Python assignment creating a tag. Tag arrays are lists with every element set to the initial value
+pub fn tag_declaration(name: &str, length: usize, initial_value: bool) -> String {
+    let value = if initial_value { "True" } else { "False" };
+    match length {
+        0 => format!("{} = {}", mangle(name), value),
+        length => format!("{} = [{}] * {}", mangle(name), value, length)
+    }
+}
+
+/// Python assignment creating an INT tag
+pub fn int_declaration(name: &str, value: i64) -> String {
+    format!("{} = {}", mangle(name), value)
+}
+
+/// INT declaration in the format of earlier releases
+pub fn legacy_int_declaration(name: &str, value: i64) -> String {
+    format!("INT {} {}", name, value)
+}
+
+/// Python assignment creating a timer. ACC counts milliseconds up to PRE, and START is when it began counting
+pub fn timer_declaration(name: &str, preset: u32) -> String {
+    format!("{} = {{\"PRE\": {}, \"ACC\": 0, \"DN\": False, \"START\": None}}", mangle(name), preset)
+}
+
+/// Timer declaration in the format of earlier releases
+pub fn legacy_timer_declaration(name: &str, preset: u32) -> String {
+    format!("TIMER {} {}", name, preset)
+}
+
+/// Lines that move a timer towards its preset, leaving DN as `done` says once it gets there
+fn timer_counting(timer: &str, done: &str) -> Vec<String> {
+    vec![
+        format!("{}[\"START\"] = {}[\"START\"] if {}[\"START\"] is not None else time.monotonic()", timer, timer, timer),
+        format!("{}[\"ACC\"] = min(int((time.monotonic() - {}[\"START\"]) * 1000), {}[\"PRE\"])", timer, timer, timer),
+        format!("{}[\"DN\"] = {}", timer, done)
+    ]
+}
+
+/// Python assignment creating a counter. CU and CD hold whether the rungs of its CTU and CTD
+/// were true on the previous scan, so that only rising edges are counted
+pub fn counter_declaration(name: &str, preset: u32) -> String {
+    format!("{} = {{\"PRE\": {}, \"ACC\": 0, \"DN\": False, \"CU\": False, \"CD\": False}}", mangle(name), preset)
+}
+
+/// Counter declaration in the format of earlier releases
+pub fn legacy_counter_declaration(name: &str, preset: u32) -> String {
+    format!("COUNTER {} {}", name, preset)
+}
+
+/// Lines that count once per rising edge of the rung, with `edge` the bit remembering the last scan
+fn counter_counting(counter: &str, edge: &str, operator: &str) -> Vec<String> {
+    vec![
+        format!("{}[\"ACC\"] {}= 0 if {}[\"{}\"] else 1", counter, operator, counter, edge),
+        format!("{}[\"{}\"] = True", counter, edge),
+        format!("{}[\"DN\"] = {}[\"ACC\"] >= {}[\"PRE\"]", counter, counter, counter)
+    ]
+}
 
-const INPUT_INSTRUCTIONS: [TokenType; 2] = [TokenType::Xic, TokenType::Xio];
-const OUTPUT_INSTRUCTIONS: [TokenType; 6] = [TokenType::Ote, TokenType::Otl, TokenType::Otu, TokenType::Jsr, TokenType::Ret, TokenType::Emit];
+/// Lines that stop a timer and set its DN bit
+fn timer_reset(timer: &str, done: bool) -> Vec<String> {
+    vec![
+        format!("{}[\"START\"] = None", timer),
+        format!("{}[\"ACC\"] = 0", timer),
+        format!("{}[\"DN\"] = {}", timer, if done { "True" } else { "False" })
+    ]
+}
+
+/// Tag declaration in the format of earlier releases, for tools that still parse it
+pub fn legacy_tag_declaration(name: &str, length: usize, initial_value: bool) -> String {
+    let value = if initial_value { "TRUE" } else { "FALSE" };
+    match length {
+        0 => format!("TAG {} {}", name, value),
+        length => format!("TAG_ARRAY {} {} {}", length, name, value)
+    }
+}
+
+/// A BST whose legs are still being generated
+struct Branch {
+    /// Rung condition before the branch, which every leg starts from
+    outer: String,
+    /// OR of the legs so far
+    group: String,
+    leg: String
+}
 
 #[derive(Default)]
 pub struct CodeGenerator {
     current_code_block: String,
+    task_name: String,
+    entry_point: Option<String>,
+    block_routines: HashSet<String>,
     indentation_level: usize,
     current_rung_name: String,
+    current_rung_prefix: String,
+    rung_temps: HashMap<String, String>,
     rung_number: u32,
     output_instruction_flag: bool,
     if_block_instructions: Vec<String>,
-    else_block_instructions: Vec<String>
+    else_block_instructions: Vec<String>,
+    epilogue_instructions: Vec<String>,
+    one_shot_count: u32,
+    branches: Vec<Branch>,
+    branch_count: u32,
+    routine_sizes: Vec<(String, usize)>,
+    routine_open: bool,
+    routine_body_start: usize,
+    written_tags: Vec<String>,
+    written_tag_set: HashSet<String>,
+    uses_time: bool,
+    /// Length of each tag array, for indexes only known as the program runs
+    arrays: HashMap<String, usize>,
+    index_bounds: IndexBounds,
+    /// Bounds checks of the indexes used by the instruction being added
+    index_checks: Vec<String>,
+    /// Whether the routine has been turned into a loop over its labels
+    uses_labels: bool,
+    rung_jumps: bool
 }
 
 impl CodeGenerator {
@@ -20,47 +146,166 @@ impl CodeGenerator {
         CodeGenerator::default()
     }
 
+    pub fn set_index_bounds(&mut self, index_bounds: IndexBounds) {
+        self.index_bounds = index_bounds;
+    }
+
     fn add_to_code_block(&mut self, code: &str) {
+        let start_size = self.current_code_block.len();
         for _ in 0..self.indentation_level {
             self.current_code_block += "\t";
         }
         self.current_code_block += code;
         self.current_code_block += "\n";
+
+        // Attribute the generated code to the routine it belongs to
+        if self.routine_open {
+            if let Some((_, size)) = self.routine_sizes.last_mut() {
+                *size += self.current_code_block.len() - start_size;
+            }
+        }
+    }
+
+    /// Size in bytes of the code block that hasn't been finished yet
+    pub fn code_block_size(&self) -> usize {
+        self.current_code_block.len()
+    }
+
+    /// Size in bytes of the code generated for each routine, in the order they were defined
+    pub fn routine_sizes(&self) -> &[(String, usize)] {
+        &self.routine_sizes
+    }
+
+    /// Name of the Python function generated for a routine. Every task has its own Main, so functions
+    /// are prefixed with their task. LogText names can't contain underscores, so the prefix can't collide
+    fn routine_function(&self, routine_name: &str) -> String {
+        if self.task_name.is_empty() {
+            mangle(routine_name)
+        } else {
+            format!("{}__{}", self.task_name, routine_name)
+        }
+    }
+
+    pub fn start_task(&mut self, task_name: &str) {
+        self.task_name = task_name.to_string();
+    }
+
+    /// Sets the routine that the finished code block calls, in place of Main
+    pub fn set_entry_point(&mut self, routine_name: &str) {
+        self.entry_point = Some(routine_name.to_string());
     }
 
     pub fn finish_code_block(&mut self) -> String {
-        // Add entry point of task
-        self.add_to_code_block("Main()");
+        // Add entry point of task, as long as this block defines it
+        let entry_point = self.entry_point.take().unwrap_or_else(|| "Main".to_string());
+        if self.block_routines.contains(&entry_point) {
+            self.add_to_code_block(&format!("{}()", self.routine_function(&entry_point)));
+        }
 
         // Trim off the last new line character
-        let code_block = self.current_code_block[0..self.current_code_block.len() - 1].to_owned();
+        let code_block = self.current_code_block.strip_suffix('\n').unwrap_or_default().to_owned();
         self.current_code_block = String::new();
         self.indentation_level = 0;
+        self.block_routines.clear();
         code_block
     }
 
     pub fn start_routine(&mut self, routine_name: &str) {
-        self.add_to_code_block(format!("def {}():", routine_name).as_str());
+        self.routine_sizes.push((routine_name.to_string(), 0));
+        self.block_routines.insert(routine_name.to_string());
+        self.routine_open = true;
+        self.add_to_code_block(format!("def {}():", self.routine_function(routine_name)).as_str());
         self.indentation_level += 1;
+        self.routine_body_start = self.current_code_block.len();
+        self.written_tags.clear();
+        self.written_tag_set.clear();
+        self.uses_time = false;
+        self.uses_labels = false;
     }
 
     pub fn end_routine(&mut self) {
+        // Leave the loop once the last label has run
+        if self.uses_labels {
+            self.indentation_level -= 1;
+            self.add_to_code_block("return");
+            self.indentation_level -= 1;
+        }
+
         // If we don't have any rungs, we need to add a pass
         if self.rung_number == 0 {
             self.add_to_code_block("pass");
         }
+
+        // Timers measure how long they've been counting
+        if self.uses_time {
+            self.insert_at_body_start(&format!("{}import time\n", "\t".repeat(self.indentation_level)));
+        }
+
+        // Assigning to a tag would otherwise create a local variable, leaving the tag itself unchanged
+        if !self.written_tags.is_empty() {
+            self.insert_at_body_start(&format!("{}global {}\n", "\t".repeat(self.indentation_level), self.written_tags.join(", ")));
+        }
         self.indentation_level -= 1;
         self.rung_number = 0;
+        self.routine_open = false;
+    }
+
+    /// Turns the routine so far into the first section of a loop, in which each label starts a section.
+    /// A JMP picks the section the next pass of the loop starts with, and each section falls through to the next
+    fn use_labels(&mut self) {
+        if self.uses_labels {
+            return;
+        }
+        self.uses_labels = true;
+
+        let indentation = "\t".repeat(self.indentation_level);
+        let body = self.current_code_block.split_off(self.routine_body_start);
+        let prologue = format!("{0}step_label = \"\"\n{0}jump_label = \"\"\n{0}while True:\n{0}\tif step_label == \"\":\n", indentation);
+        let body = body.lines().map(|line| format!("\t\t{}\n", line)).collect::<String>();
+        self.indentation_level += 2;
+
+        self.current_code_block += &prologue;
+        self.current_code_block += &body;
+        if let Some((_, size)) = self.routine_sizes.last_mut() {
+            *size += prologue.len() + 2 * body.lines().count();
+        }
+    }
+
+    /// Starts the section of a label with the rung that was just started
+    fn start_label(&mut self, label: &str) {
+        self.use_labels();
+
+        // The label goes before its rung, which is only the line setting the rung condition so far
+        let rung_start = self.current_code_block.trim_end_matches('\n').rfind('\n').map_or(0, |index| index + 1);
+        let rung = self.current_code_block.split_off(rung_start);
+        if let Some((_, size)) = self.routine_sizes.last_mut() {
+            *size -= rung.len();
+        }
+
+        self.add_to_code_block(&format!("step_label = \"{}\"", label));
+        self.indentation_level -= 1;
+        self.add_to_code_block(&format!("if step_label == \"{}\":", label));
+        self.indentation_level += 1;
+        self.add_to_code_block(rung.trim());
+    }
+
+    fn insert_at_body_start(&mut self, code: &str) {
+        self.current_code_block.insert_str(self.routine_body_start, code);
+        if let Some((_, size)) = self.routine_sizes.last_mut() {
+            *size += code.len();
+        }
     }
 
     pub fn start_rung(&mut self, rung_name: &str) {
-        let editted_rung_name;
-        if rung_name.is_empty() {
-            editted_rung_name = format!("rung_{}_entry", self.rung_number);
+        let rung_prefix = if rung_name.is_empty() {
+            format!("rung_{}", self.rung_number)
         } else {
-            editted_rung_name = format!("rung_{}_entry", rung_name);
-        }
+            format!("rung_{}", rung_name)
+        };
+        let editted_rung_name = format!("{}_entry", rung_prefix);
         self.rung_number += 1;
+        self.current_rung_prefix = rung_prefix;
+        self.rung_temps.clear();
 
         self.add_to_code_block(format!("{} = True", editted_rung_name).as_str());
         self.current_rung_name = editted_rung_name;
@@ -90,24 +335,119 @@ impl CodeGenerator {
             self.indentation_level -= 1;
         }
 
+        for instruction in std::mem::take(&mut self.epilogue_instructions) {
+            self.add_to_code_block(&instruction);
+        }
+
+        // Jumps are taken once the whole rung is done
+        if self.rung_jumps {
+            self.add_to_code_block("if jump_label:");
+            self.indentation_level += 1;
+            self.add_to_code_block("step_label, jump_label = jump_label, \"\"");
+            self.add_to_code_block("continue");
+            self.indentation_level -= 1;
+            self.rung_jumps = false;
+        }
+        self.one_shot_count = 0;
+        self.branch_count = 0;
         self.output_instruction_flag = false;
     }
 
-    fn add_input_instruction(&mut self, instruction: &TokenType, target: &str) {
+    /// Opens a branch. Until it's closed, input instructions act on the leg being generated
+    pub fn start_branch(&mut self) {
+        let group = format!("{}_branch_{}", self.current_rung_prefix, self.branch_count);
+        let leg = format!("{}_leg", group);
+        self.branch_count += 1;
+
+        self.add_to_code_block(&format!("{} = False", group));
+        self.add_to_code_block(&format!("{} = {}", leg, self.current_rung_name));
+        let outer = std::mem::replace(&mut self.current_rung_name, leg.clone());
+        self.branches.push(Branch { outer, group, leg });
+    }
+
+    pub fn next_branch_leg(&mut self) {
+        let branch = self.branches.last().expect("NXB outside of a branch");
+        let code = [format!("{} |= {}", branch.group, branch.leg), format!("{} = {}", branch.leg, branch.outer)];
+        for line in code {
+            self.add_to_code_block(&line);
+        }
+    }
+
+    pub fn end_branch(&mut self) {
+        let branch = self.branches.pop().expect("BND outside of a branch");
+        self.add_to_code_block(&format!("{} |= {}", branch.group, branch.leg));
+        self.add_to_code_block(&format!("{} = {}", branch.outer, branch.group));
+        self.current_rung_name = branch.outer;
+    }
+
+    /// Declares a tag local to the current rung. It gets a name of its own, so that
+    /// rungs can reuse the same temp name without sharing the value
+    pub fn declare_temp(&mut self, name: &str) {
+        let temp_name = format!("{}_temp_{}", self.current_rung_prefix, name);
+        self.add_to_code_block(&format!("{} = False", temp_name));
+        self.rung_temps.insert(name.to_string(), temp_name);
+    }
+
+    pub fn declare_array(&mut self, name: &str, length: usize) {
+        self.arrays.insert(name.to_string(), length);
+    }
+
+    /// Python expression of an operand. An element of a tag array indexed by an INT tag is kept
+    /// in bounds by clamping the index or by checking it first
+    fn operand_expression(&mut self, operand: &str) -> String {
+        let (tag, index) = match operand.split_once('.') {
+            Some((tag, index)) if self.arrays.contains_key(tag) && !index.chars().all(|character| character.is_ascii_digit()) => (tag, index),
+            _ => return mangle(operand)
+        };
+
+        let last = self.arrays[tag] - 1;
+        let (array, index) = (mangle(tag), mangle(index));
+        match self.index_bounds {
+            IndexBounds::Clamp => format!("{}[min(max({}, 0), {})]", array, index, last),
+            IndexBounds::Raise => {
+                let check = format!("if not 0 <= {} <= {}: raise IndexError(\"Index {} of {} is out of bounds\")", index, last, index, array);
+                if !self.index_checks.contains(&check) {
+                    self.index_checks.push(check);
+                }
+                format!("{}[{}]", array, index)
+            }
+        }
+    }
+
+    fn add_input_instruction(&mut self, instruction: &TokenType, operands: &[String]) {
         if self.output_instruction_flag {
             panic!("Input instruction {:?} appears after an output instruction", instruction);
         }
 
-        if instruction == &TokenType::Xic {
-            self.add_to_code_block(format!("{} &= {}", self.current_rung_name, target).as_str());
-        } else if instruction == &TokenType::Xio {
-            self.add_to_code_block(format!("{} &= not {}", self.current_rung_name, target).as_str());
-        } else {
-            unreachable!("Missing input instruction");
+        if *instruction == TokenType::Lbl {
+            self.start_label(&operands[0]);
+            return;
+        }
+
+        // The rung condition so far is kept for the storage bit, which is only updated once the rung is done
+        if *instruction == TokenType::Ons {
+            let edge = format!("{}_ons_{}", self.current_rung_prefix, self.one_shot_count);
+            self.one_shot_count += 1;
+            self.add_to_code_block(&format!("{} = {}", edge, self.current_rung_name));
+            self.add_to_code_block(&format!("{} &= not {}", self.current_rung_name, operands[0]));
+            self.epilogue_instructions.push(format!("{} = {}", operands[0], edge));
+            return;
         }
+
+        let condition = match *instruction {
+            TokenType::Xic => operands[0].clone(),
+            TokenType::Xio => format!("not {}", operands[0]),
+            TokenType::Equ => format!("({} == {})", operands[0], operands[1]),
+            TokenType::Neq => format!("({} != {})", operands[0], operands[1]),
+            TokenType::Grt => format!("({} > {})", operands[0], operands[1]),
+            TokenType::Les => format!("({} < {})", operands[0], operands[1]),
+            _ => unreachable!("Missing input instruction")
+        };
+        self.add_to_code_block(format!("{} &= {}", self.current_rung_name, condition).as_str());
     }
 
-    fn add_output_instruction(&mut self, instruction: &TokenType, target: &str) {
+    fn add_output_instruction(&mut self, instruction: &TokenType, operands: &[String]) {
+        let target = operands.last().map(String::as_str).unwrap_or_default();
 
         match *instruction {
             TokenType::Ret => {
@@ -129,6 +469,39 @@ impl CodeGenerator {
             TokenType::Emit => {
                 self.if_block_instructions.insert(0, format!("EmitEvent('{}')", target));
             },
+            TokenType::Jmp => {
+                self.use_labels();
+                self.if_block_instructions.insert(0, format!("jump_label = \"{}\"", target));
+                self.rung_jumps = true;
+            },
+            TokenType::Ton => {
+                self.add_block_instructions(timer_counting(target, &format!("{}[\"ACC\"] >= {}[\"PRE\"]", target, target)),
+                                            timer_reset(target, false));
+                self.uses_time = true;
+            },
+            TokenType::Tof => {
+                self.add_block_instructions(timer_reset(target, true),
+                                            timer_counting(target, &format!("{}[\"DN\"] and {}[\"ACC\"] < {}[\"PRE\"]", target, target, target)));
+                self.uses_time = true;
+            },
+            TokenType::Ctu => {
+                self.add_block_instructions(counter_counting(target, "CU", "+"), vec![format!("{}[\"CU\"] = False", target)]);
+            },
+            TokenType::Ctd => {
+                self.add_block_instructions(counter_counting(target, "CD", "-"), vec![format!("{}[\"CD\"] = False", target)]);
+            },
+            TokenType::Res => {
+                self.add_block_instructions(vec![format!("{}[\"ACC\"] = 0", target), format!("{}[\"DN\"] = False", target)], Vec::new());
+            },
+            TokenType::Mov => {
+                self.if_block_instructions.insert(0, format!("{} = {}", target, operands[0]));
+            },
+            TokenType::Add => {
+                self.if_block_instructions.insert(0, format!("{} = {} + {}", target, operands[0], operands[1]));
+            },
+            TokenType::Sub => {
+                self.if_block_instructions.insert(0, format!("{} = {} - {}", target, operands[0], operands[1]));
+            },
             _ => {
                 unreachable!("Missing output instruction");
             }
@@ -136,13 +509,63 @@ impl CodeGenerator {
         self.output_instruction_flag = true;
     }
 
-    pub fn add_instruction(&mut self, instruction: TokenType, target: &str) {
-        if INPUT_INSTRUCTIONS.contains(&instruction) {
-            self.add_input_instruction(&instruction, target);
-        } else if OUTPUT_INSTRUCTIONS.contains(&instruction) {
-            self.add_output_instruction(&instruction, target);
-        } else {
-            panic!("Invalid instruction {:?}", instruction);
+    fn add_block_instructions(&mut self, if_block: Vec<String>, else_block: Vec<String>) {
+        // Instructions are taken off the end of the blocks, so each line goes in front of the last
+        for line in if_block {
+            self.if_block_instructions.insert(0, line);
+        }
+        for line in else_block {
+            self.else_block_instructions.insert(0, line);
+        }
+    }
+
+    /// Adds an instruction along with all of its operands. An instruction that writes a tag writes its last operand
+    pub fn add_instruction(&mut self, instruction: TokenType, operands: &[&str]) {
+        let spec = instructions::spec(instruction).unwrap_or_else(|| panic!("Invalid instruction {:?}", instruction));
+
+        let writes_temp = operands.last().is_some_and(|operand| self.rung_temps.contains_key(*operand));
+
+        // Event names and labels are emitted as strings, so only tags and routines need mangling
+        let operands = operands.iter().zip(spec.operands).map(|(operand, kind)| {
+            match self.rung_temps.get(*operand) {
+                _ if matches!(kind, OperandKind::Event | OperandKind::Label) => operand.to_string(),
+                Some(temp_name) => temp_name.clone(),
+                None if *kind == OperandKind::Routine => self.routine_function(operand),
+                None => self.operand_expression(operand)
+            }
+        }).collect::<Vec<String>>();
+        let index_checks = std::mem::take(&mut self.index_checks);
+
+        // Assigning to an element of a tag array doesn't create a local, so only whole tags need declaring global.
+        // Temps stay local
+        let target = operands.last().cloned().unwrap_or_default();
+        if spec.writes_tag() && !writes_temp && !target.contains('[') && self.written_tag_set.insert(target.clone()) {
+            self.written_tags.push(target.clone());
+        }
+
+        match spec.class {
+            InstructionClass::Input => {
+                for check in &index_checks {
+                    self.add_to_code_block(check);
+                }
+                self.add_input_instruction(&instruction, &operands);
+            },
+            InstructionClass::Output => {
+                let (if_size, else_size) = (self.if_block_instructions.len(), self.else_block_instructions.len());
+                self.add_output_instruction(&instruction, &operands);
+
+                // The new lines are at the front of the blocks, and the checks go right before them
+                let if_added = self.if_block_instructions.len() - if_size;
+                let else_added = self.else_block_instructions.len() - else_size;
+                for check in &index_checks {
+                    if if_added > 0 {
+                        self.if_block_instructions.insert(if_added, check.clone());
+                    }
+                    if else_added > 0 {
+                        self.else_block_instructions.insert(else_added, check.clone());
+                    }
+                }
+            }
         }
     }
 }
@@ -157,22 +580,23 @@ mod test {
 
         code_generator.start_routine("Main");
         code_generator.start_rung("firstRung");
-        code_generator.add_instruction(TokenType::Xio, "MyTag1");
-        code_generator.add_instruction(TokenType::Xic, "MyTag2");
-        code_generator.add_instruction(TokenType::Otl, "MyTag3");
-        code_generator.add_instruction(TokenType::Otu, "MyTag4");
-        code_generator.add_instruction(TokenType::Ote, "MyTag5");
-        code_generator.add_instruction(TokenType::Jsr, "otherRoutine");
+        code_generator.add_instruction(TokenType::Xio, &["MyTag1"]);
+        code_generator.add_instruction(TokenType::Xic, &["MyTag2"]);
+        code_generator.add_instruction(TokenType::Otl, &["MyTag3"]);
+        code_generator.add_instruction(TokenType::Otu, &["MyTag4"]);
+        code_generator.add_instruction(TokenType::Ote, &["MyTag5"]);
+        code_generator.add_instruction(TokenType::Jsr, &["otherRoutine"]);
         code_generator.end_rung();
         code_generator.end_routine();
 
         code_generator.start_routine("otherRoutine");
         code_generator.start_rung("");
-        code_generator.add_instruction(TokenType::Ret, "");
+        code_generator.add_instruction(TokenType::Ret, &[""]);
         code_generator.end_rung();
         code_generator.end_routine();
 
         let expected_output = "def Main():
+\tglobal MyTag3, MyTag4, MyTag5
 \trung_firstRung_entry = True
 \trung_firstRung_entry &= not MyTag1
 \trung_firstRung_entry &= MyTag2
@@ -199,9 +623,249 @@ Main()";
 
         code_generator.start_routine("Main");
         code_generator.start_rung("firstRung");
-        code_generator.add_input_instruction(&TokenType::Xic, "MyTag");
-        code_generator.add_output_instruction(&TokenType::Ote, "MyTag");
-        code_generator.add_input_instruction(&TokenType::Xic, "MyTag");
+        code_generator.add_input_instruction(&TokenType::Xic, &["MyTag".to_string()]);
+        code_generator.add_output_instruction(&TokenType::Ote, &["MyTag".to_string()]);
+        code_generator.add_input_instruction(&TokenType::Xic, &["MyTag".to_string()]);
+    }
+
+    #[test]
+    fn test_mangled_names() {
+        let mut code_generator = CodeGenerator::new();
+
+        code_generator.start_routine("Main");
+        code_generator.start_rung("");
+        code_generator.add_instruction(TokenType::Xic, &["if"]);
+        code_generator.add_instruction(TokenType::Ote, &["None.2"]);
+        code_generator.add_instruction(TokenType::Jsr, &["pass"]);
+        code_generator.add_instruction(TokenType::Emit, &["class"]);
+        code_generator.end_rung();
+        code_generator.end_routine();
+        code_generator.start_routine("pass");
+        code_generator.end_routine();
+
+        let expected_output = "def Main():
+\trung_0_entry = True
+\trung_0_entry &= if_
+\tif rung_0_entry:
+\t\tNone_[2] = True
+\t\tpass_()
+\t\tEmitEvent('class')
+\telse:
+\t\tNone_[2] = False
+def pass_():
+\tpass
+Main()";
+        assert_eq!(expected_output, code_generator.finish_code_block());
+    }
+
+    #[test]
+    fn test_routine_sizes() {
+        let mut code_generator = CodeGenerator::new();
+
+        code_generator.start_routine("Main");
+        code_generator.start_rung("");
+        code_generator.add_instruction(TokenType::Ote, &["MyTag"]);
+        code_generator.end_rung();
+        code_generator.end_routine();
+        code_generator.start_routine("other");
+        code_generator.end_routine();
+
+        let expected_sizes = [("Main".to_string(), 103), ("other".to_string(), 19)];
+        assert_eq!(expected_sizes, code_generator.routine_sizes());
+        assert_eq!(122, code_generator.code_block_size());
+
+        // The entry point call doesn't belong to any routine
+        code_generator.finish_code_block();
+        assert_eq!(expected_sizes, code_generator.routine_sizes());
+    }
+
+    #[test]
+    fn test_global_tags() {
+        let mut code_generator = CodeGenerator::new();
+
+        code_generator.start_routine("Main");
+        code_generator.start_rung("");
+        code_generator.add_instruction(TokenType::Xic, &["start"]);
+        code_generator.add_instruction(TokenType::Otl, &["motor"]);
+        code_generator.add_instruction(TokenType::Ote, &["lights.2"]);
+        code_generator.add_instruction(TokenType::Jsr, &["stop"]);
+        code_generator.end_rung();
+        code_generator.start_rung("");
+        code_generator.add_instruction(TokenType::Otu, &["motor"]);
+        code_generator.add_instruction(TokenType::Ote, &["lambda"]);
+        code_generator.end_rung();
+        code_generator.end_routine();
+        code_generator.start_routine("stop");
+        code_generator.start_rung("");
+        code_generator.add_instruction(TokenType::Xic, &["motor"]);
+        code_generator.end_rung();
+        code_generator.end_routine();
+
+        // Tags that are only read, and elements of tag arrays, don't need declaring
+        let code_block = code_generator.finish_code_block();
+        let globals = code_block.lines().filter(|line| line.contains("global")).collect::<Vec<&str>>();
+        assert_eq!(vec!["\tglobal motor, lambda_"], globals);
+        assert!(code_block.starts_with("def Main():\n\tglobal motor, lambda_\n\trung_0_entry = True\n"));
+        assert!(code_block.contains("def stop():\n\trung_0_entry = True\n"));
+    }
+
+    #[test]
+    fn test_tag_declarations() {
+        assert_eq!("motor = True", tag_declaration("motor", 0, true));
+        assert_eq!("pass_ = False", tag_declaration("pass", 0, false));
+        assert_eq!("TAG pass FALSE", legacy_tag_declaration("pass", 0, false));
+    }
+
+    #[test]
+    fn test_tag_arrays() {
+        assert_eq!("lights = [False] * 4", tag_declaration("lights", 4, false));
+        assert_eq!("in_ = [True] * 2", tag_declaration("in", 2, true));
+        assert_eq!("TAG_ARRAY 2 in TRUE", legacy_tag_declaration("in", 2, true));
+
+        let mut code_generator = CodeGenerator::new();
+        code_generator.start_routine("Main");
+        code_generator.start_rung("");
+        code_generator.add_instruction(TokenType::Xic, &["lights.0"]);
+        code_generator.add_instruction(TokenType::Otl, &["lights.2"]);
+        code_generator.end_rung();
+        code_generator.end_routine();
+
+        let expected_output = "def Main():
+\trung_0_entry = True
+\trung_0_entry &= lights[0]
+\tif rung_0_entry:
+\t\tlights[2] = True
+Main()";
+        assert_eq!(expected_output, code_generator.finish_code_block());
+    }
+
+    #[test]
+    fn test_tag_array_tag_index() {
+        let mut code_generator = CodeGenerator::new();
+        code_generator.declare_array("lights", 4);
+        code_generator.start_routine("Main");
+        code_generator.start_rung("");
+        code_generator.add_instruction(TokenType::Xic, &["lights.cursor"]);
+        code_generator.add_instruction(TokenType::Ote, &["lights.next"]);
+        code_generator.end_rung();
+        code_generator.end_routine();
+
+        let expected_output = "def Main():
+\trung_0_entry = True
+\tif not 0 <= cursor <= 3: raise IndexError(\"Index cursor of lights is out of bounds\")
+\trung_0_entry &= lights[cursor]
+\tif rung_0_entry:
+\t\tif not 0 <= next <= 3: raise IndexError(\"Index next of lights is out of bounds\")
+\t\tlights[next] = True
+\telse:
+\t\tif not 0 <= next <= 3: raise IndexError(\"Index next of lights is out of bounds\")
+\t\tlights[next] = False
+Main()";
+        assert_eq!(expected_output, code_generator.finish_code_block());
+
+        let mut code_generator = CodeGenerator::new();
+        code_generator.set_index_bounds(IndexBounds::Clamp);
+        code_generator.declare_array("lights", 4);
+        code_generator.start_routine("Main");
+        code_generator.start_rung("");
+        code_generator.add_instruction(TokenType::Xic, &["lights.cursor"]);
+        code_generator.add_instruction(TokenType::Otl, &["lights.2"]);
+        code_generator.end_rung();
+        code_generator.end_routine();
+
+        let expected_output = "def Main():
+\trung_0_entry = True
+\trung_0_entry &= lights[min(max(cursor, 0), 3)]
+\tif rung_0_entry:
+\t\tlights[2] = True
+Main()";
+        assert_eq!(expected_output, code_generator.finish_code_block());
+    }
+
+    #[test]
+    fn test_jumps() {
+        let mut code_generator = CodeGenerator::new();
+        code_generator.start_routine("Main");
+        code_generator.start_rung("");
+        code_generator.add_instruction(TokenType::Xic, &["stop"]);
+        code_generator.add_instruction(TokenType::Jmp, &["skip"]);
+        code_generator.end_rung();
+        code_generator.start_rung("");
+        code_generator.add_instruction(TokenType::Ote, &["motor"]);
+        code_generator.end_rung();
+        code_generator.start_rung("");
+        code_generator.add_instruction(TokenType::Lbl, &["skip"]);
+        code_generator.add_instruction(TokenType::Otl, &["done"]);
+        code_generator.end_rung();
+        code_generator.end_routine();
+
+        // The rungs between the JMP and its label are skipped by starting the next pass at the label
+        let expected_output = "def Main():
+\tglobal motor, done
+\tstep_label = \"\"
+\tjump_label = \"\"
+\twhile True:
+\t\tif step_label == \"\":
+\t\t\trung_0_entry = True
+\t\t\trung_0_entry &= stop
+\t\t\tif rung_0_entry:
+\t\t\t\tjump_label = \"skip\"
+\t\t\tif jump_label:
+\t\t\t\tstep_label, jump_label = jump_label, \"\"
+\t\t\t\tcontinue
+\t\t\trung_1_entry = True
+\t\t\tif rung_1_entry:
+\t\t\t\tmotor = True
+\t\t\telse:
+\t\t\t\tmotor = False
+\t\t\tstep_label = \"skip\"
+\t\tif step_label == \"skip\":
+\t\t\trung_2_entry = True
+\t\t\tif rung_2_entry:
+\t\t\t\tdone = True
+\t\treturn
+Main()";
+        assert_eq!(expected_output, code_generator.finish_code_block());
+        assert_eq!(code_generator.routine_sizes()[0].1, expected_output.len() - "Main()".len());
+    }
+
+    #[test]
+    fn test_rung_temps() {
+        let mut code_generator = CodeGenerator::new();
+
+        code_generator.start_routine("Main");
+        code_generator.start_rung("");
+        code_generator.declare_temp("scratch");
+        code_generator.add_instruction(TokenType::Xic, &["scratch"]);
+        code_generator.add_instruction(TokenType::Ote, &["scratch"]);
+        code_generator.end_rung();
+        code_generator.start_rung("second");
+        code_generator.declare_temp("scratch");
+        code_generator.add_instruction(TokenType::Otl, &["scratch"]);
+        code_generator.end_rung();
+        code_generator.start_rung("");
+        code_generator.add_instruction(TokenType::Otl, &["scratch"]);
+        code_generator.end_rung();
+        code_generator.end_routine();
+
+        let expected_output = "def Main():
+\tglobal scratch
+\trung_0_entry = True
+\trung_0_temp_scratch = False
+\trung_0_entry &= rung_0_temp_scratch
+\tif rung_0_entry:
+\t\trung_0_temp_scratch = True
+\telse:
+\t\trung_0_temp_scratch = False
+\trung_second_entry = True
+\trung_second_temp_scratch = False
+\tif rung_second_entry:
+\t\trung_second_temp_scratch = True
+\trung_2_entry = True
+\tif rung_2_entry:
+\t\tscratch = True
+Main()";
+        assert_eq!(expected_output, code_generator.finish_code_block());
     }
 
     #[test]
@@ -213,4 +877,320 @@ Main()";
 
         assert_eq!(code_generator.finish_code_block(), "def Main():\n\tpass\nMain()");
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_entry_routine() {
+        let mut code_generator = CodeGenerator::new();
+
+        code_generator.start_routine("import");
+        code_generator.end_routine();
+
+        code_generator.set_entry_point("import");
+        assert_eq!(code_generator.finish_code_block(), "def import_():\n\tpass\nimport_()");
+    }
+
+    #[test]
+    fn test_no_entry_point() {
+        let mut code_generator = CodeGenerator::new();
+
+        code_generator.start_routine("Helper");
+        code_generator.end_routine();
+        assert_eq!(code_generator.finish_code_block(), "def Helper():\n\tpass");
+
+        // The entry point only applies to the block it was set for
+        code_generator.set_entry_point("Helper");
+        assert_eq!(code_generator.finish_code_block(), "");
+        code_generator.start_routine("Main");
+        code_generator.end_routine();
+        assert_eq!(code_generator.finish_code_block(), "def Main():\n\tpass\nMain()");
+    }
+
+    #[test]
+    fn test_task_namespacing() {
+        let mut code_generator = CodeGenerator::new();
+        let mut code_blocks = Vec::new();
+        for task in ["task1", "task2"] {
+            code_generator.start_task(task);
+            code_generator.start_routine("Main");
+            code_generator.start_rung("");
+            code_generator.add_instruction(TokenType::Jsr, &["Helper"]);
+            code_generator.end_rung();
+            code_generator.end_routine();
+            code_generator.start_routine("Helper");
+            code_generator.end_routine();
+            code_blocks.push(code_generator.finish_code_block());
+        }
+
+        let expected_output = "def task2__Main():
+\trung_0_entry = True
+\tif rung_0_entry:
+\t\ttask2__Helper()
+def task2__Helper():
+\tpass
+task2__Main()";
+        assert_eq!(expected_output, code_blocks[1]);
+
+        let functions = code_blocks.iter()
+                                   .flat_map(|code_block| code_block.lines())
+                                   .filter_map(|line| line.strip_prefix("def "))
+                                   .collect::<HashSet<&str>>();
+        assert_eq!(HashSet::from(["task1__Main():", "task1__Helper():", "task2__Main():", "task2__Helper():"]), functions);
+    }
+
+    #[test]
+    fn test_timers() {
+        assert_eq!("delay = {\"PRE\": 500, \"ACC\": 0, \"DN\": False, \"START\": None}", timer_declaration("delay", 500));
+        assert_eq!("TIMER delay 500", legacy_timer_declaration("delay", 500));
+        assert_eq!("delay[\"DN\"]", mangle("delay.DN"));
+
+        let mut code_generator = CodeGenerator::new();
+        code_generator.start_routine("Main");
+        code_generator.start_rung("");
+        code_generator.add_instruction(TokenType::Xic, &["delay.DN"]);
+        code_generator.add_instruction(TokenType::Ton, &["delay"]);
+        code_generator.end_rung();
+        code_generator.end_routine();
+
+        let expected_output = "def Main():
+\timport time
+\trung_0_entry = True
+\trung_0_entry &= delay[\"DN\"]
+\tif rung_0_entry:
+\t\tdelay[\"START\"] = delay[\"START\"] if delay[\"START\"] is not None else time.monotonic()
+\t\tdelay[\"ACC\"] = min(int((time.monotonic() - delay[\"START\"]) * 1000), delay[\"PRE\"])
+\t\tdelay[\"DN\"] = delay[\"ACC\"] >= delay[\"PRE\"]
+\telse:
+\t\tdelay[\"START\"] = None
+\t\tdelay[\"ACC\"] = 0
+\t\tdelay[\"DN\"] = False
+Main()";
+        assert_eq!(expected_output, code_generator.finish_code_block());
+    }
+
+    #[test]
+    fn test_counters() {
+        assert_eq!("parts = {\"PRE\": 10, \"ACC\": 0, \"DN\": False, \"CU\": False, \"CD\": False}", counter_declaration("parts", 10));
+        assert_eq!("COUNTER parts 10", legacy_counter_declaration("parts", 10));
+
+        let mut code_generator = CodeGenerator::new();
+        code_generator.start_routine("Main");
+        code_generator.start_rung("");
+        code_generator.add_instruction(TokenType::Xic, &["sensor"]);
+        code_generator.add_instruction(TokenType::Ctu, &["parts"]);
+        code_generator.end_rung();
+        code_generator.start_rung("");
+        code_generator.add_instruction(TokenType::Xic, &["parts.DN"]);
+        code_generator.add_instruction(TokenType::Ctd, &["parts"]);
+        code_generator.end_rung();
+        code_generator.start_rung("");
+        code_generator.add_instruction(TokenType::Xic, &["reset"]);
+        code_generator.add_instruction(TokenType::Res, &["parts"]);
+        code_generator.end_rung();
+        code_generator.end_routine();
+
+        let expected_output = "def Main():
+\trung_0_entry = True
+\trung_0_entry &= sensor
+\tif rung_0_entry:
+\t\tparts[\"ACC\"] += 0 if parts[\"CU\"] else 1
+\t\tparts[\"CU\"] = True
+\t\tparts[\"DN\"] = parts[\"ACC\"] >= parts[\"PRE\"]
+\telse:
+\t\tparts[\"CU\"] = False
+\trung_1_entry = True
+\trung_1_entry &= parts[\"DN\"]
+\tif rung_1_entry:
+\t\tparts[\"ACC\"] -= 0 if parts[\"CD\"] else 1
+\t\tparts[\"CD\"] = True
+\t\tparts[\"DN\"] = parts[\"ACC\"] >= parts[\"PRE\"]
+\telse:
+\t\tparts[\"CD\"] = False
+\trung_2_entry = True
+\trung_2_entry &= reset
+\tif rung_2_entry:
+\t\tparts[\"ACC\"] = 0
+\t\tparts[\"DN\"] = False
+Main()";
+        assert_eq!(expected_output, code_generator.finish_code_block());
+    }
+
+    #[test]
+    fn test_arithmetic() {
+        assert_eq!("count = 3", int_declaration("count", 3));
+        assert_eq!("INT count 3", legacy_int_declaration("count", 3));
+
+        let mut code_generator = CodeGenerator::new();
+        code_generator.start_routine("Main");
+        code_generator.start_rung("");
+        code_generator.add_instruction(TokenType::Xic, &["start"]);
+        code_generator.add_instruction(TokenType::Mov, &["5", "count"]);
+        code_generator.add_instruction(TokenType::Add, &["count", "1", "total"]);
+        code_generator.add_instruction(TokenType::Sub, &["total", "count", "total"]);
+        code_generator.end_rung();
+        code_generator.end_routine();
+
+        let expected_output = "def Main():
+\tglobal count, total
+\trung_0_entry = True
+\trung_0_entry &= start
+\tif rung_0_entry:
+\t\tcount = 5
+\t\ttotal = count + 1
+\t\ttotal = total - count
+Main()";
+        assert_eq!(expected_output, code_generator.finish_code_block());
+    }
+
+    #[test]
+    fn test_comparisons() {
+        let mut code_generator = CodeGenerator::new();
+        code_generator.start_routine("Main");
+        code_generator.start_rung("");
+        code_generator.add_instruction(TokenType::Equ, &["count", "limit"]);
+        code_generator.add_instruction(TokenType::Neq, &["count", "0"]);
+        code_generator.add_instruction(TokenType::Grt, &["count", "3"]);
+        code_generator.add_instruction(TokenType::Les, &["1", "limit"]);
+        code_generator.add_instruction(TokenType::Ote, &["full"]);
+        code_generator.end_rung();
+        code_generator.end_routine();
+
+        let expected_output = "def Main():
+\tglobal full
+\trung_0_entry = True
+\trung_0_entry &= (count == limit)
+\trung_0_entry &= (count != 0)
+\trung_0_entry &= (count > 3)
+\trung_0_entry &= (1 < limit)
+\tif rung_0_entry:
+\t\tfull = True
+\telse:
+\t\tfull = False
+Main()";
+        assert_eq!(expected_output, code_generator.finish_code_block());
+    }
+
+    #[test]
+    #[should_panic(expected = "Input instruction Grt appears after an output instruction")]
+    fn test_comparison_after_output() {
+        let mut code_generator = CodeGenerator::new();
+        code_generator.start_routine("Main");
+        code_generator.start_rung("");
+        code_generator.add_instruction(TokenType::Ote, &["full"]);
+        code_generator.add_instruction(TokenType::Grt, &["count", "3"]);
+    }
+
+    #[test]
+    fn test_one_shot() {
+        let mut code_generator = CodeGenerator::new();
+        code_generator.start_routine("Main");
+        code_generator.start_rung("");
+        code_generator.add_instruction(TokenType::Xic, &["start"]);
+        code_generator.add_instruction(TokenType::Ons, &["pulse"]);
+        code_generator.add_instruction(TokenType::Ote, &["motor"]);
+        code_generator.end_rung();
+        code_generator.start_rung("");
+        code_generator.add_instruction(TokenType::Ons, &["flags.1"]);
+        code_generator.end_rung();
+        code_generator.end_routine();
+
+        // The storage bits are written after the outputs, once the rung is done with them
+        let expected_output = "def Main():
+\tglobal pulse, motor
+\trung_0_entry = True
+\trung_0_entry &= start
+\trung_0_ons_0 = rung_0_entry
+\trung_0_entry &= not pulse
+\tif rung_0_entry:
+\t\tmotor = True
+\telse:
+\t\tmotor = False
+\tpulse = rung_0_ons_0
+\trung_1_entry = True
+\trung_1_ons_0 = rung_1_entry
+\trung_1_entry &= not flags[1]
+\tflags[1] = rung_1_ons_0
+Main()";
+        assert_eq!(expected_output, code_generator.finish_code_block());
+    }
+
+    #[test]
+    fn test_branches() {
+        let mut code_generator = CodeGenerator::new();
+        code_generator.start_routine("Main");
+        code_generator.start_rung("");
+        code_generator.add_instruction(TokenType::Xic, &["auto"]);
+        code_generator.start_branch();
+        code_generator.add_instruction(TokenType::Xic, &["start"]);
+        code_generator.next_branch_leg();
+        code_generator.add_instruction(TokenType::Xic, &["motor"]);
+        code_generator.end_branch();
+        code_generator.add_instruction(TokenType::Ote, &["motor"]);
+        code_generator.end_rung();
+        code_generator.end_routine();
+
+        // Every leg starts from the condition before the branch
+        let expected_output = "def Main():
+\tglobal motor
+\trung_0_entry = True
+\trung_0_entry &= auto
+\trung_0_branch_0 = False
+\trung_0_branch_0_leg = rung_0_entry
+\trung_0_branch_0_leg &= start
+\trung_0_branch_0 |= rung_0_branch_0_leg
+\trung_0_branch_0_leg = rung_0_entry
+\trung_0_branch_0_leg &= motor
+\trung_0_branch_0 |= rung_0_branch_0_leg
+\trung_0_entry = rung_0_branch_0
+\tif rung_0_entry:
+\t\tmotor = True
+\telse:
+\t\tmotor = False
+Main()";
+        assert_eq!(expected_output, code_generator.finish_code_block());
+    }
+
+    #[test]
+    fn test_nested_branches() {
+        let mut code_generator = CodeGenerator::new();
+        code_generator.start_routine("Main");
+        code_generator.start_rung("pump");
+        code_generator.start_branch();
+        code_generator.add_instruction(TokenType::Xic, &["a"]);
+        code_generator.start_branch();
+        code_generator.add_instruction(TokenType::Xic, &["b"]);
+        code_generator.next_branch_leg();
+        code_generator.add_instruction(TokenType::Xio, &["c"]);
+        code_generator.end_branch();
+        code_generator.next_branch_leg();
+        code_generator.add_instruction(TokenType::Xic, &["d"]);
+        code_generator.end_branch();
+        code_generator.add_instruction(TokenType::Otl, &["e"]);
+        code_generator.end_rung();
+        code_generator.end_routine();
+
+        // (a and (b or not c)) or d
+        let expected_output = "def Main():
+\tglobal e
+\trung_pump_entry = True
+\trung_pump_branch_0 = False
+\trung_pump_branch_0_leg = rung_pump_entry
+\trung_pump_branch_0_leg &= a
+\trung_pump_branch_1 = False
+\trung_pump_branch_1_leg = rung_pump_branch_0_leg
+\trung_pump_branch_1_leg &= b
+\trung_pump_branch_1 |= rung_pump_branch_1_leg
+\trung_pump_branch_1_leg = rung_pump_branch_0_leg
+\trung_pump_branch_1_leg &= not c
+\trung_pump_branch_1 |= rung_pump_branch_1_leg
+\trung_pump_branch_0_leg = rung_pump_branch_1
+\trung_pump_branch_0 |= rung_pump_branch_0_leg
+\trung_pump_branch_0_leg = rung_pump_entry
+\trung_pump_branch_0_leg &= d
+\trung_pump_branch_0 |= rung_pump_branch_0_leg
+\trung_pump_entry = rung_pump_branch_0
+\tif rung_pump_entry:
+\t\te = True
+Main()";
+        assert_eq!(expected_output, code_generator.finish_code_block());
+    }
+}
@@ -0,0 +1,148 @@
+use crate::instructions::{self, InstructionClass, INSTRUCTIONS};
+use crate::lexer::{KEYWORDS, TASK_ATTRIBUTES, TokenType};
+use crate::options::DEFAULT_TAG_NAME_LIMIT;
+use crate::parse::PERIOD_LOWER_BOUND;
+
+/// Program every instruction example is placed into, in place of {rung}
+const EXAMPLE_CONTEXT: &str = "TAG start = FALSE
+TAG stop = FALSE
+TAG motor = FALSE
+TAG pulse = FALSE
+TIMER delay = 500
+COUNTER parts = 10
+INT count = 0
+
+TASK<PERIOD=100> MainTask
+    ROUTINE Main
+{rung}
+        RUNG
+            LBL skip
+        ENDRUNG
+    ENDROUTINE
+    ROUTINE Helper
+    ENDROUTINE
+ENDTASK
+
+TASK<EVENT=done> DoneTask
+    ROUTINE Main
+    ENDROUTINE
+ENDTASK";
+
+/// Shown where the examples go in the program they're placed into
+const EXAMPLE_PLACEHOLDER: &str = "# Example goes here";
+
+/// Describes the keywords that aren't instructions
+fn keyword_description(token_type: TokenType) -> String {
+    match token_type {
+        TokenType::Program => "Declares the name of the program. Written PROGRAM name VERSION 1.2.3 as the first statement".to_string(),
+        TokenType::Version => "Dotted version number of the program, following its name in the PROGRAM statement".to_string(),
+        TokenType::Tag => format!("Declares a tag and its initial value, e.g. TAG motor = FALSE. TAG[n] declares an array of n tags. \
+                                   Tag names are at most {} characters", DEFAULT_TAG_NAME_LIMIT),
+        TokenType::True | TokenType::False => "Initial value of a tag".to_string(),
+        TokenType::Task => "Starts a task, which is scheduled according to its type, e.g. TASK<PERIOD=100> MainTask".to_string(),
+        TokenType::EndTask => "Ends a task".to_string(),
+        TokenType::Period => format!("Runs the task every n milliseconds, with n at least {}", PERIOD_LOWER_BOUND),
+        TokenType::Event => "Runs the task whenever the named event is emitted. Events may be qualified with a namespace, \
+                             e.g. EVENT=cell3.jamEvt".to_string(),
+        TokenType::Continuous => "Runs the task over and over".to_string(),
+        TokenType::Routine => "Starts a routine. Each task starts with its routine called Main unless another is marked ENTRY".to_string(),
+        TokenType::Entry => "Makes a routine the one its task starts with in place of Main, e.g. ROUTINE<ENTRY> Startup".to_string(),
+        TokenType::EndRoutine => "Ends a routine".to_string(),
+        TokenType::Rung => "Starts a rung, which may be given a name. Input instructions come before output instructions".to_string(),
+        TokenType::EndRung => "Ends a rung".to_string(),
+        TokenType::Timer => "Declares a timer and its preset in milliseconds, e.g. TIMER delay = 500. \
+                              XIC delay.DN examines its done bit".to_string(),
+        TokenType::Int => "Declares an integer tag and its initial value, e.g. INT count = 0. \
+                            MOV, ADD and SUB take INT tags or whole numbers and write to an INT tag".to_string(),
+        TokenType::Counter => "Declares a counter and its preset, e.g. COUNTER parts = 10. \
+                                XIC parts.DN examines its done bit, which is on while the count is at least the preset".to_string(),
+        TokenType::Bst => "Opens a branch, whose legs are ORed together into the rung condition. \
+                            Branches come before the output instructions of a rung and may be nested".to_string(),
+        TokenType::Nxb => "Starts the next leg of the innermost open branch".to_string(),
+        TokenType::Bnd => "Closes the innermost open branch".to_string(),
+        TokenType::Temp => "Declares a scratch tag that only exists in its rung and starts every scan FALSE, e.g. TEMP scratch".to_string(),
+        TokenType::EventSpace => "Starts a block of tasks whose unqualified event names, declared or emitted, \
+                                  belong to the given namespace, e.g. EVENTSPACE cell3".to_string(),
+        TokenType::EndEventSpace => "Ends an EVENTSPACE block".to_string(),
+        _ => unreachable!("Missing description of {:?}", token_type)
+    }
+}
+
+/// Places the body of a rung into a complete program
+pub fn example_program(rung_body: &str) -> String {
+    EXAMPLE_CONTEXT.replace("{rung}", &indent_rung(rung_body, "        "))
+}
+
+fn indent_rung(rung_body: &str, indentation: &str) -> String {
+    let mut rung = format!("{}RUNG\n", indentation);
+    for line in rung_body.lines() {
+        rung += &format!("{}    {}\n", indentation, line);
+    }
+    rung + indentation + "ENDRUNG"
+}
+
+/// Language reference generated from the compiler's own tables
+pub fn markdown() -> String {
+    let mut reference = String::from("# LogText language reference\n\n");
+    reference += &format!("Generated by LogTextCompiler {}.\n\n", env!("CARGO_PKG_VERSION"));
+
+    reference += "## Instructions\n\n";
+    reference += "The examples show the body of a rung from this program:\n\n";
+    reference += &format!("```\n{}\n```\n\n", example_program(EXAMPLE_PLACEHOLDER));
+    for spec in &INSTRUCTIONS {
+        let class = match spec.class {
+            InstructionClass::Input => "Input",
+            InstructionClass::Output => "Output"
+        };
+        let operands = match spec.operands {
+            [] => "none".to_string(),
+            operands => operands.iter().map(|operand| operand.name()).collect::<Vec<&str>>().join(", ")
+        };
+
+        reference += &format!("### {}\n\n{}.\n\n", spec.keyword, spec.description);
+        reference += &format!("- Class: {}\n- Operands: {}\n\n", class, operands);
+        reference += &format!("```\n{}\n```\n\n", indent_rung(spec.example, ""));
+    }
+
+    reference += "## Task types\n\n";
+    for (keyword, token_type) in KEYWORDS.iter().filter(|(_, token_type)| TASK_ATTRIBUTES.contains(token_type)) {
+        reference += &format!("- `{}`: {}\n", keyword, keyword_description(*token_type));
+    }
+
+    reference += "\n## Other keywords\n\n";
+    for (keyword, token_type) in KEYWORDS {
+        if instructions::spec(token_type).is_none() && !TASK_ATTRIBUTES.contains(&token_type) {
+            reference += &format!("- `{}`: {}\n", keyword, keyword_description(token_type));
+        }
+    }
+    reference
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::emitter::Emitter;
+    use crate::lexer::Lexer;
+    use crate::options::CompilerOptions;
+    use crate::parse::Parser;
+
+    #[test]
+    fn test_examples_compile() {
+        let examples = INSTRUCTIONS.iter().map(|spec| spec.example).chain([EXAMPLE_PLACEHOLDER]);
+        for example in examples {
+            let mut par = Parser::new(Lexer::new(example_program(example)), Emitter::new("test.out"), CompilerOptions::default());
+            par.program().unwrap();
+        }
+    }
+
+    #[test]
+    fn test_markdown_keywords() {
+        let reference = markdown();
+        for (keyword, _) in KEYWORDS {
+            assert!(reference.contains(&format!("`{}`", keyword)) || reference.contains(&format!("### {}\n", keyword)),
+                    "{} is not documented", keyword);
+        }
+        assert!(reference.contains("### JSR\n\nJump to subroutine: runs the routine when the rung is true.\n\n\
+                                    - Class: Output\n- Operands: routine\n\n```\nRUNG\n    XIC start\n    JSR Helper\nENDRUNG\n```"));
+    }
+}
@@ -1,21 +1,69 @@
+use std::cmp::Ordering;
 use std::fs::OpenOptions;
-use std::io::Write;
+use std::io::{self, Write};
 use std::path::Path;
 
+use crate::options::TagOrder;
+
+/// Output path that writes the compiled code to stdout instead of a file
+pub const STDOUT_PATH: &str = "-";
+
+/// First line of every compiled output
+const OUTPUT_HEADER: &str = "# Compiled by LogTextCompiler";
+
+/// Determines whether the given text is the output of a previous compilation
+/// rather than source code
+pub fn is_compiled_output(text: &str) -> bool {
+    if text.starts_with(OUTPUT_HEADER) {
+        return true;
+    }
+
+    // Source code can't contain braces, so a task header followed by one only
+    // appears in compiled output
+    let lines = text.lines().collect::<Vec<&str>>();
+    lines.windows(2).any(|pair| pair[0].starts_with("TASK ") && pair[1].trim() == "{")
+}
+
+/// Compares names ignoring case, then by their exact spelling so that the order never depends on the locale
+fn compare_names(first: &str, second: &str) -> Ordering {
+    first.to_lowercase().cmp(&second.to_lowercase()).then_with(|| first.cmp(second))
+}
+
+struct TagDeclaration {
+    name: String,
+    array: bool,
+    line: String
+}
+
 /// Class responsible for outputting compiled code
 pub struct Emitter<'a> {
     full_path: &'a std::path::Path,
-    compiled_code: String
+    header: String,
+    compiled_code: String,
+
+    // Tag declarations are output together where the first one was made
+    tags: Vec<TagDeclaration>,
+    tags_position: usize,
+    tags_size: usize
 }
 
 impl<'a> Emitter<'a> {
     pub fn new(full_path: &'a str) -> Emitter<'a> {
         Emitter {
             full_path: Path::new(full_path),
-            compiled_code: String::new()
+            header: format!("{} {}\n", OUTPUT_HEADER, env!("CARGO_PKG_VERSION")),
+            compiled_code: String::new(),
+            tags: Vec::new(),
+            tags_position: 0,
+            tags_size: 0
         }
     }
 
+    /// Adds to the comments at the top of the output, which can be done at any point
+    pub fn add_header(&mut self, chunk: &str) {
+        self.header += chunk;
+    }
+
     pub fn emit(&mut self, chunk: &str) {
         self.compiled_code += chunk;
     }
@@ -25,15 +73,115 @@ impl<'a> Emitter<'a> {
         self.compiled_code += "\n";
     }
 
+    pub fn emit_tag(&mut self, name: &str, array: bool, line: &str) {
+        if self.tags.is_empty() {
+            self.tags_position = self.compiled_code.len();
+        }
+        self.tags.push(TagDeclaration { name: name.to_string(), array, line: format!("{}\n", line) });
+        self.tags_size += line.len() + 1;
+    }
+
+    pub fn sort_tags(&mut self, order: TagOrder) {
+        // Sorting is stable, so redeclared tags keep their order
+        match order {
+            TagOrder::Source => (),
+            TagOrder::Name => self.tags.sort_by(|first, second| compare_names(&first.name, &second.name)),
+            TagOrder::TypeThenName => self.tags.sort_by(|first, second| {
+                first.array.cmp(&second.array).then_with(|| compare_names(&first.name, &second.name))
+            })
+        }
+    }
+
+    pub fn size(&self) -> usize {
+        self.header.len() + self.compiled_code.len() + self.tags_size
+    }
+
+    pub fn contents(&self) -> String {
+        let tags = self.tags.iter().map(|tag| tag.line.as_str()).collect::<String>();
+        let (before_tags, after_tags) = self.compiled_code.split_at(self.tags_position);
+        format!("{}{}{}{}", self.header, before_tags, tags, after_tags)
+    }
+
     pub fn write_file(&self) {
-        let mut file = OpenOptions::new()
-            .write(true)
-            .create(true)
-            .open(&*self.full_path)
-            .expect("Couldn't open file");
-
-        if let Err(why) = file.write_all(self.compiled_code.as_bytes()) {
-            panic!("Couldn't write to {}: {}", self.full_path.display(), why);
+        write_output(self.full_path, &self.contents());
+    }
+}
+
+/// Writes compiled code to a file, or to stdout for STDOUT_PATH
+pub fn write_output(full_path: &Path, contents: &str) {
+    if full_path == Path::new(STDOUT_PATH) {
+        if let Err(why) = io::stdout().write_all(contents.as_bytes()) {
+            panic!("Couldn't write to stdout: {}", why);
         }
+        return;
+    }
+
+    let mut file = OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open(full_path)
+        .expect("Couldn't open file");
+
+    if let Err(why) = file.write_all(contents.as_bytes()) {
+        panic!("Couldn't write to {}: {}", full_path.display(), why);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_is_compiled_output() {
+        let mut emitter = Emitter::new("test.out");
+        emitter.emit_line("TAG myTag FALSE");
+        emitter.emit_line("TASK PERIOD 100 myTask");
+        emitter.emit_line("{");
+        assert!(is_compiled_output(&emitter.contents()));
+
+        // Output without the header is still recognized by its shape
+        assert!(is_compiled_output(&emitter.compiled_code));
+
+        let source_code = "TASK<PERIOD=100> myTask\n    ROUTINE Main\n    ENDROUTINE\nENDTASK";
+        assert!(!is_compiled_output(source_code));
+
+        let garbage = String::from_utf8_lossy(&[0x7f, 0x45, 0x4c, 0x46, 0x02, 0x01, 0xff, 0x00, 0x7b, 0x0a]);
+        assert!(!is_compiled_output(&garbage));
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_write_file_truncates() {
+        let path = std::env::temp_dir().join("logtext_emitter_truncate.out");
+        let path = path.to_str().unwrap();
+
+        let mut emitter = Emitter::new(path);
+        emitter.emit_line(&"TAG longTag FALSE\n".repeat(100));
+        emitter.write_file();
+
+        // Recompiling into a smaller output must not leave the end of the previous one behind
+        let mut emitter = Emitter::new(path);
+        emitter.emit_line("TAG short TRUE");
+        emitter.write_file();
+        assert_eq!(emitter.contents(), std::fs::read_to_string(path).unwrap());
+    }
+
+    #[test]
+    fn test_sort_tags() {
+        let tag_section = |order| {
+            let mut emitter = Emitter::new("test.out");
+            emitter.emit_line("PROGRAM Cell VERSION 1");
+            emitter.emit_tag("motor", false, "TAG motor FALSE");
+            emitter.emit_tag("Alarms", true, "Alarms = [False] * 4");
+            emitter.emit_tag("alarm", false, "TAG alarm TRUE");
+            emitter.emit_tag("Alarm", false, "TAG Alarm FALSE");
+            emitter.emit_line("TASK CONTINUOUS task");
+            emitter.sort_tags(order);
+            emitter.contents().lines().skip(2).take(4).collect::<Vec<&str>>().join("\n")
+        };
+
+        assert_eq!("TAG motor FALSE\nAlarms = [False] * 4\nTAG alarm TRUE\nTAG Alarm FALSE", tag_section(TagOrder::Source));
+        assert_eq!("TAG Alarm FALSE\nTAG alarm TRUE\nAlarms = [False] * 4\nTAG motor FALSE", tag_section(TagOrder::Name));
+        assert_eq!("TAG Alarm FALSE\nTAG alarm TRUE\nTAG motor FALSE\nAlarms = [False] * 4", tag_section(TagOrder::TypeThenName));
+    }
+}
@@ -0,0 +1,194 @@
+/// Text encodings source files can be read in
+#[derive(Debug, Clone, Copy, PartialEq, clap::ValueEnum)]
+pub enum Encoding {
+    Utf8,
+    Utf16le,
+    Utf16be,
+    Windows1252
+}
+
+impl Encoding {
+    fn name(&self) -> &'static str {
+        match self {
+            Encoding::Utf8 => "UTF-8",
+            Encoding::Utf16le => "UTF-16LE",
+            Encoding::Utf16be => "UTF-16BE",
+            Encoding::Windows1252 => "Windows-1252"
+        }
+    }
+}
+
+/// Source code transcoded to UTF-8 along with what it was transcoded from
+#[derive(Debug)]
+pub struct Decoded {
+    pub text: String,
+    pub encoding: Encoding,
+    pub byte_order_mark: bool
+}
+
+impl Decoded {
+    /// Describes the encoding when it isn't plain UTF-8, so that it can be migrated deliberately
+    pub fn note(&self) -> Option<String> {
+        match (self.encoding, self.byte_order_mark) {
+            (Encoding::Utf8, false) => None,
+            (encoding, false) => Some(format!("Source is encoded as {}", encoding.name())),
+            (encoding, true) => Some(format!("Source is encoded as {} with a byte order mark", encoding.name()))
+        }
+    }
+}
+
+/// Characters 0x80 to 0x9F of Windows-1252, which is Latin-1 everywhere else.
+/// The five unassigned bytes are None
+const WINDOWS_1252_HIGH: [Option<char>; 32] = [
+    Some('€'), None, Some('‚'), Some('ƒ'), Some('„'), Some('…'), Some('†'), Some('‡'),
+    Some('ˆ'), Some('‰'), Some('Š'), Some('‹'), Some('Œ'), None, Some('Ž'), None,
+    None, Some('‘'), Some('’'), Some('“'), Some('”'), Some('•'), Some('–'), Some('—'),
+    Some('˜'), Some('™'), Some('š'), Some('›'), Some('œ'), None, Some('ž'), Some('Ÿ')
+];
+
+/// Reads the bytes of a source file as text. The encoding is detected from the byte order
+/// mark when there is one, then UTF-8 is tried before falling back to Windows-1252
+pub fn decode(bytes: &[u8], forced: Option<Encoding>) -> Result<Decoded, String> {
+    let (encoding, byte_order_mark) = match forced {
+        Some(encoding) => (encoding, has_byte_order_mark(bytes, encoding)),
+        None if bytes.starts_with(&[0xEF, 0xBB, 0xBF]) => (Encoding::Utf8, true),
+        None if bytes.starts_with(&[0xFF, 0xFE]) => (Encoding::Utf16le, true),
+        None if bytes.starts_with(&[0xFE, 0xFF]) => (Encoding::Utf16be, true),
+        None if std::str::from_utf8(bytes).is_ok() => (Encoding::Utf8, false),
+        None => (Encoding::Windows1252, false)
+    };
+
+    let offset = if !byte_order_mark { 0 } else if encoding == Encoding::Utf8 { 3 } else { 2 };
+    let text = match encoding {
+        Encoding::Utf8 => decode_utf8(bytes, offset),
+        Encoding::Utf16le => decode_utf16(bytes, offset, u16::from_le_bytes),
+        Encoding::Utf16be => decode_utf16(bytes, offset, u16::from_be_bytes),
+        Encoding::Windows1252 => decode_windows_1252(bytes)
+    }.map_err(|error_offset| {
+        format!("Invalid {} at byte offset {}: {}", encoding.name(), error_offset, hex_snippet(bytes, error_offset))
+    })?;
+
+    Ok(Decoded { text, encoding, byte_order_mark })
+}
+
+fn has_byte_order_mark(bytes: &[u8], encoding: Encoding) -> bool {
+    match encoding {
+        Encoding::Utf8 => bytes.starts_with(&[0xEF, 0xBB, 0xBF]),
+        Encoding::Utf16le => bytes.starts_with(&[0xFF, 0xFE]),
+        Encoding::Utf16be => bytes.starts_with(&[0xFE, 0xFF]),
+        Encoding::Windows1252 => false
+    }
+}
+
+fn decode_utf8(bytes: &[u8], offset: usize) -> Result<String, usize> {
+    match std::str::from_utf8(&bytes[offset..]) {
+        Ok(text) => Ok(text.to_string()),
+        Err(error) => Err(offset + error.valid_up_to())
+    }
+}
+
+fn decode_utf16(bytes: &[u8], offset: usize, to_unit: fn([u8; 2]) -> u16) -> Result<String, usize> {
+    let body = &bytes[offset..];
+    if !body.len().is_multiple_of(2) {
+        return Err(bytes.len() - 1);
+    }
+
+    let units = body.chunks(2).map(|pair| to_unit([pair[0], pair[1]]));
+    let mut text = String::new();
+    let mut unit_index = 0;
+    for character in char::decode_utf16(units) {
+        match character {
+            Ok(character) => {
+                text.push(character);
+                unit_index += character.len_utf16();
+            },
+            Err(_) => return Err(offset + unit_index * 2)
+        }
+    }
+    Ok(text)
+}
+
+fn decode_windows_1252(bytes: &[u8]) -> Result<String, usize> {
+    let mut text = String::new();
+    for (offset, &byte) in bytes.iter().enumerate() {
+        let character = match byte {
+            0x80..=0x9F => WINDOWS_1252_HIGH[(byte - 0x80) as usize].ok_or(offset)?,
+            _ => byte as char
+        };
+        text.push(character);
+    }
+    Ok(text)
+}
+
+/// The bytes starting at an offset, written out in hex
+fn hex_snippet(bytes: &[u8], offset: usize) -> String {
+    bytes.iter()
+         .skip(offset)
+         .take(8)
+         .map(|byte| format!("{:02x}", byte))
+         .collect::<Vec<String>>()
+         .join(" ")
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const SOURCE: &str = "# Oven at 200°C\nTAG hot = FALSE\n";
+
+    #[test]
+    fn test_decode_utf8() {
+        let decoded = decode(SOURCE.as_bytes(), None).unwrap();
+        assert_eq!(SOURCE, decoded.text);
+        assert_eq!(None, decoded.note());
+
+        let mut bytes = vec![0xEF, 0xBB, 0xBF];
+        bytes.extend(SOURCE.as_bytes());
+        let decoded = decode(&bytes, None).unwrap();
+        assert_eq!(SOURCE, decoded.text);
+        assert_eq!(Some("Source is encoded as UTF-8 with a byte order mark".to_string()), decoded.note());
+    }
+
+    #[test]
+    fn test_decode_utf16() {
+        let mut little_endian = vec![0xFF, 0xFE];
+        let mut big_endian = vec![0xFE, 0xFF];
+        for unit in SOURCE.encode_utf16() {
+            little_endian.extend(unit.to_le_bytes());
+            big_endian.extend(unit.to_be_bytes());
+        }
+
+        let decoded = decode(&little_endian, None).unwrap();
+        assert_eq!(SOURCE, decoded.text);
+        assert_eq!(Encoding::Utf16le, decoded.encoding);
+
+        let decoded = decode(&big_endian, None).unwrap();
+        assert_eq!(SOURCE, decoded.text);
+        assert_eq!(Encoding::Utf16be, decoded.encoding);
+
+        // A lone surrogate can't be decoded
+        let error = decode(&[0xFF, 0xFE, 0x41, 0x00, 0x00, 0xD8], None).unwrap_err();
+        assert_eq!("Invalid UTF-16LE at byte offset 4: 00 d8", error);
+    }
+
+    #[test]
+    fn test_decode_windows_1252() {
+        let decoded = decode(b"# Oven at 200\xB0C\nTAG hot = FALSE\n", None).unwrap();
+        assert_eq!(SOURCE, decoded.text);
+        assert_eq!(Some("Source is encoded as Windows-1252".to_string()), decoded.note());
+
+        let decoded = decode(&[0x80, 0x93, 0x94], Some(Encoding::Windows1252)).unwrap();
+        assert_eq!("€“”", decoded.text);
+    }
+
+    #[test]
+    fn test_decode_invalid() {
+        // 0x81 is unassigned in Windows-1252, so nothing can decode it
+        let error = decode(b"TAG a\x81b", None).unwrap_err();
+        assert_eq!("Invalid Windows-1252 at byte offset 5: 81 62", error);
+
+        // Forcing UTF-8 reports the invalid byte rather than falling back
+        let error = decode(b"# 200\xB0C", Some(Encoding::Utf8)).unwrap_err();
+        assert_eq!("Invalid UTF-8 at byte offset 5: b0 43", error);
+    }
+}
@@ -0,0 +1,172 @@
+use std::fmt;
+
+use crate::lexer::{Token, TokenType};
+
+/// Reason a program failed to compile
+#[derive(Debug, Clone, PartialEq)]
+pub enum ParseError {
+    /// A specific token was required but something else was found
+    UnexpectedToken { expected: TokenType, found: Token },
+    /// The lexer couldn't make a token of some text
+    InvalidToken(Token),
+    /// A line starts with something that can't begin a statement
+    InvalidStatement(Token),
+    /// A statement is complete but more follows it on the same line
+    TrailingTokens { found: Token, message: String },
+    /// A name breaks a naming rule
+    InvalidIdentifier { name: Token, rule: String },
+    /// A tag is referenced before it is declared
+    UndefinedTag(String),
+    /// A TEMP tag is referenced outside of the rung that declares it
+    TempOutOfScope(String),
+    /// A JSR targets a routine that is never defined
+    UndefinedRoutine(String),
+    /// An EMIT names an event that no task is bound to
+    UndefinedEvent(String),
+    /// A namespaced event has the same name as an event outside of any namespace
+    EventCollision { qualified: String, unqualified: String },
+    /// Tasks, routines and rungs are nested or closed in the wrong order
+    StructuralMismatch(String),
+    /// The type and attributes between the angle brackets of a task are malformed
+    InvalidTaskHeader(String),
+    /// A tag declaration is malformed
+    InvalidTag(String),
+    /// An index into a tag array is malformed or out of bounds
+    InvalidIndex(String),
+    /// The PROGRAM statement is missing, repeated, misplaced or malformed
+    InvalidProgram(String),
+    /// A tag is declared again as another kind of tag
+    ConflictingTag { tag: String, first_kind: String, first_line: usize, kind: String, line: usize },
+    /// A label is defined twice in the same routine
+    DuplicateLabel { label: String, routine: String, first_line: usize, line: usize },
+    /// A JMP targets a label that its routine doesn't define
+    UndefinedLabel { label: String, routine: String, line: usize },
+    /// A routine is defined twice in the same task
+    DuplicateRoutine { routine: String, task: String, first_line: usize, line: usize },
+    /// A task doesn't have exactly one routine to start with
+    InvalidEntryRoutine(String),
+    /// An instruction appears somewhere other than inside a rung
+    InstructionOutsideRung { instruction: Token, found_in: String },
+    /// An input instruction follows an output instruction in the same rung
+    InputAfterOutput(TokenType),
+    /// A routine can be run by more than one task
+    SharedRoutine { routine: String, tasks: Vec<String> },
+    /// A JSR calls a routine that is only defined in another task
+    CrossTaskJump { routine: String, task: String, defined_in: String },
+    /// The generated output grew past --max-output-size
+    OutputTooLarge { budget: usize, largest_routines: Vec<(String, usize)> },
+    /// Warnings were reported while they are treated as errors
+    WarningsDenied(usize),
+    /// The program breaks rules of the --policy file
+    PolicyViolations(Vec<String>)
+}
+
+impl ParseError {
+    /// Line and column of the token the error was found at, when it points at one
+    pub fn location(&self) -> Option<(usize, usize)> {
+        match self {
+            ParseError::UnexpectedToken { found: token, .. } |
+            ParseError::InvalidToken(token) |
+            ParseError::InvalidStatement(token) |
+            ParseError::TrailingTokens { found: token, .. } |
+            ParseError::InvalidIdentifier { name: token, .. } |
+            ParseError::InstructionOutsideRung { instruction: token, .. } => Some((token.get_line(), token.get_column())),
+            _ => None
+        }
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ParseError::UnexpectedToken { expected, found } => {
+                // Suggest escaping keywords that were meant to be names
+                if *expected == TokenType::Identifier && Token::is_keyword(found.get_text()).is_some() {
+                    write!(f, "Expected {:?}, but found keyword {}. To use it as a name, escape it as `{}`",
+                           expected, found.get_text(), found.get_text())
+                } else {
+                    match found.get_type() {
+                        TokenType::NewLine => write!(f, "Expected {:?}, but found the end of the line", expected),
+                        TokenType::Eof => write!(f, "Expected {:?}, but found the end of the file", expected),
+                        found_type => write!(f, "Expected {:?}, but found '{}' ({:?})", expected, found.get_text(), found_type)
+                    }
+                }
+            },
+            ParseError::InvalidToken(token) => write!(f, "{}", token.get_error().unwrap_or("Invalid token")),
+            ParseError::InvalidStatement(token) => write!(f, "Invalid statement at {} ({:?})", token.get_text(), token.get_type()),
+            ParseError::TrailingTokens { message, .. } => write!(f, "{}", message),
+            ParseError::InvalidIdentifier { rule, .. } => write!(f, "{}", rule),
+            ParseError::UndefinedTag(tag) => write!(f, "Referencing tag {} before assignment", tag),
+            ParseError::TempOutOfScope(tag) => {
+                write!(f, "Referencing tag {} before assignment. A TEMP {} is declared in another rung, \
+                           but temps are only visible in the rung that declares them", tag, tag)
+            },
+            ParseError::UndefinedRoutine(routine) => write!(f, "Routine {} does not exist", routine),
+            ParseError::UndefinedEvent(event) => write!(f, "Emitted event {} does not correspond to a task", event),
+            ParseError::EventCollision { qualified, unqualified } => {
+                write!(f, "Event {} has the same name as the unqualified event {}. Qualify {} with a namespace or rename one of them",
+                       qualified, unqualified, unqualified)
+            },
+            ParseError::StructuralMismatch(message) |
+            ParseError::InvalidTaskHeader(message) |
+            ParseError::InvalidTag(message) |
+            ParseError::InvalidIndex(message) |
+            ParseError::InvalidProgram(message) |
+            ParseError::InvalidEntryRoutine(message) => write!(f, "{}", message),
+            ParseError::DuplicateRoutine { routine, task, first_line, line } => {
+                write!(f, "Routine {} is defined more than once in task {}, on lines {} and {}", routine, task, first_line, line)
+            },
+            ParseError::ConflictingTag { tag, first_kind, first_line, kind, line } => {
+                write!(f, "Tag {} is declared as {} on line {} and as {} on line {}. A name can only be declared as one kind of tag",
+                       tag, first_kind, first_line, kind, line)
+            },
+            ParseError::DuplicateLabel { label, routine, first_line, line } => {
+                write!(f, "Label {} is defined more than once in routine {}, on lines {} and {}", label, routine, first_line, line)
+            },
+            ParseError::UndefinedLabel { label, routine, line } => {
+                write!(f, "JMP on line {} targets label {}, which routine {} doesn't define. Jumps can only go to labels of their own routine",
+                       line, label, routine)
+            },
+            ParseError::InstructionOutsideRung { instruction, found_in } => {
+                write!(f, "Instruction {} must be inside of a rung, but was found {}", instruction.get_text(), found_in)
+            },
+            ParseError::InputAfterOutput(instruction) => {
+                write!(f, "Input instruction {:?} appears after an output instruction", instruction)
+            },
+            ParseError::SharedRoutine { routine, tasks } => {
+                write!(f, "Routine {} is reachable from more than one task: {}", routine, tasks.join(", "))
+            },
+            ParseError::CrossTaskJump { routine, task, defined_in } => {
+                write!(f, "JSR {} in task {} calls a routine of task {}. Routines can only be called from their own task",
+                       routine, task, defined_in)
+            },
+            ParseError::OutputTooLarge { budget, largest_routines } => {
+                let largest_routines = largest_routines.iter()
+                                                       .map(|(name, size)| format!("{} ({} bytes)", name, size))
+                                                       .collect::<Vec<String>>()
+                                                       .join(", ");
+                write!(f, "Generated output exceeds the size budget of {} bytes. Largest routines: {}", budget, largest_routines)
+            },
+            ParseError::WarningsDenied(count) => write!(f, "{} warning(s) treated as errors", count),
+            ParseError::PolicyViolations(violations) => write!(f, "{}", violations.join(". "))
+        }
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+/// An error along with the line it was found on, so that every error of a compile can be reported in order
+#[derive(Debug, Clone, PartialEq)]
+pub struct Diagnostic {
+    /// Line of the statement the error was found in. Errors of the program as a whole, like a missing PROGRAM, have none
+    pub line: Option<usize>,
+    pub error: ParseError
+}
+
+impl Diagnostic {
+    pub fn new(error: ParseError, line: Option<usize>) -> Diagnostic {
+        // The token an error points at is more precise than the start of its statement
+        let line = error.location().map(|(line, _)| line).or(line);
+        Diagnostic { line, error }
+    }
+}
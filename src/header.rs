@@ -0,0 +1,117 @@
+use crate::clock::{self, Clock};
+
+/// Values that can be substituted into a header template
+pub struct HeaderValues {
+    program: String,
+    program_version: String,
+    version: String,
+    date: String,
+    timestamp: String,
+    source_hash: String,
+    target: String
+}
+
+impl HeaderValues {
+    /// Without a clock, as in reproducible builds, the date and timestamp are left empty so that
+    /// the output only depends on the source
+    pub fn new(program: &str, program_version: &str, source_code: &str, clock: Option<&dyn Clock>) -> HeaderValues {
+        let now = clock.map(|clock| clock.now());
+        HeaderValues {
+            program: program.to_string(),
+            program_version: program_version.to_string(),
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            date: now.map(clock::date).unwrap_or_default(),
+            timestamp: now.map(clock::timestamp).unwrap_or_default(),
+            source_hash: format!("{:016x}", hash(source_code)),
+            target: "python".to_string()
+        }
+    }
+
+    fn get(&self, variable: &str) -> Option<&str> {
+        match variable {
+            "program" => Some(&self.program),
+            "program_version" => Some(&self.program_version),
+            "version" => Some(&self.version),
+            "date" => Some(&self.date),
+            "timestamp" => Some(&self.timestamp),
+            "source_hash" => Some(&self.source_hash),
+            "target" => Some(&self.target),
+            _ => None
+        }
+    }
+}
+
+/// Substitutes the values into the template and turns each line into a comment. Fails on a variable
+/// that doesn't exist
+pub fn render(template: &str, values: &HeaderValues) -> Result<String, String> {
+    let mut header = String::new();
+    for (line_number, line) in template.lines().enumerate() {
+        let mut rendered_line = String::new();
+        let mut rest = line;
+        while let Some(start) = rest.find('{') {
+            let end = match rest[start..].find('}') {
+                Some(length) => start + length,
+                None => break
+            };
+
+            let variable = &rest[start + 1..end];
+            let value = values.get(variable).ok_or_else(|| {
+                format!("Unknown variable {{{}}} on line {} of the header template", variable, line_number + 1)
+            })?;
+
+            rendered_line += &rest[..start];
+            rendered_line += value;
+            rest = &rest[end + 1..];
+        }
+        rendered_line += rest;
+
+        if rendered_line.is_empty() {
+            header += "#\n";
+        } else {
+            header += &format!("# {}\n", rendered_line);
+        }
+    }
+    Ok(header)
+}
+
+/// FNV-1a, which is stable across platforms and compiler versions unlike the std hasher
+pub fn hash(text: &str) -> u64 {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for byte in text.bytes() {
+        hash ^= byte as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    hash
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_render() {
+        let values = HeaderValues::new("conveyor", "2.4", "TAG myTag = TRUE", None);
+        let template = "Copyright ACME\n\n{program} {program_version} by v{version} for {target} ({source_hash}){date}{timestamp}";
+
+        let expected_header = format!("# Copyright ACME\n#\n# conveyor 2.4 by v{} for python ({:016x})\n",
+                                      env!("CARGO_PKG_VERSION"), hash("TAG myTag = TRUE"));
+        assert_eq!(Ok(expected_header), render(template, &values));
+    }
+
+    #[test]
+    fn test_render_date() {
+        let values = HeaderValues::new("conveyor", "", "", Some(&clock::FixedClock(1709647629)));
+        assert_eq!("# 2024-03-05 2024-03-05T14:07:09Z\n", render("{date} {timestamp}", &values).unwrap());
+
+        let values = HeaderValues::new("conveyor", "", "", Some(&clock::SystemClock));
+        let header = render("{date}", &values).unwrap();
+        assert_eq!("# YYYY-MM-DD\n".len(), header.len());
+        assert!(header.starts_with("# 20"));
+    }
+
+    #[test]
+    fn test_render_unknown_variable() {
+        let values = HeaderValues::new("conveyor", "", "", None);
+        assert_eq!(Err("Unknown variable {author} on line 2 of the header template".to_string()), render("{program}\nBy {author}", &values));
+    }
+}
@@ -0,0 +1,123 @@
+use crate::error::{Diagnostic, ParseError};
+use crate::lexer::Token;
+use crate::options::{CompilerOptions, DEFAULT_NAME_LIMIT, DEFAULT_TAG_NAME_LIMIT};
+
+/// The kinds of names that can be declared in a program
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum IdentifierKind {
+    Program,
+    Tag,
+    Routine,
+    Task,
+    Event,
+    EventSpace,
+    Rung,
+    Label
+}
+
+impl IdentifierKind {
+    pub fn length_limit(&self, options: &CompilerOptions) -> usize {
+        match self {
+            IdentifierKind::Tag => options.tag_name_limit.unwrap_or(DEFAULT_TAG_NAME_LIMIT),
+            _ => options.name_limit.unwrap_or(DEFAULT_NAME_LIMIT)
+        }
+    }
+}
+
+/// Names the generated code relies on, which a declaration would shadow
+const RESERVED_NAMES: [&str; 1] = ["EmitEvent"];
+
+/// Checks a declared name against the rules for its kind, returning an error
+/// at the name for every rule that it breaks
+pub fn validate(kind: IdentifierKind, name: &Token, options: &CompilerOptions) -> Vec<Diagnostic> {
+    let text = name.get_text();
+    let mut rules = Vec::new();
+
+    let length_limit = kind.length_limit(options);
+    if text.chars().count() > length_limit {
+        rules.push(format!("{:?} name {} too long. The limit is {} characters", kind, text, length_limit));
+    }
+
+    match text.chars().next() {
+        Some(first) if first.is_alphabetic() => (),
+        _ => rules.push(format!("{:?} name {} must start with a letter", kind, text))
+    }
+
+    if !text.chars().all(|character| character.is_alphabetic() || character.is_ascii_digit()) {
+        rules.push(format!("{:?} name {} may only contain letters and digits", kind, text));
+    }
+
+    if RESERVED_NAMES.contains(&text) {
+        rules.push(format!("{:?} name {} is reserved", kind, text));
+    }
+
+    rules.into_iter()
+         .map(|rule| Diagnostic::new(ParseError::InvalidIdentifier { name: name.clone(), rule }, Some(name.get_line())))
+         .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const KINDS: [IdentifierKind; 8] = [IdentifierKind::Tag, IdentifierKind::Program, IdentifierKind::Routine,
+                                        IdentifierKind::Task, IdentifierKind::Event, IdentifierKind::EventSpace,
+                                        IdentifierKind::Rung, IdentifierKind::Label];
+
+    /// Rules the name breaks, as messages
+    fn broken_rules(kind: IdentifierKind, name: &str) -> Vec<String> {
+        validate(kind, &Token::identifier(name, 1), &CompilerOptions::default()).iter().map(|diagnostic| diagnostic.error.to_string()).collect()
+    }
+
+    #[test]
+    fn test_valid_names() {
+        for kind in KINDS {
+            assert!(broken_rules(kind, "a").is_empty());
+            assert!(broken_rules(kind, "myTag1").is_empty());
+            assert!(broken_rules(kind, "étape1").is_empty());
+        }
+    }
+
+    #[test]
+    fn test_length() {
+        assert!(broken_rules(IdentifierKind::Tag, "sevenCh").is_empty());
+        assert_eq!(broken_rules(IdentifierKind::Tag, "eightChr"), ["Tag name eightChr too long. The limit is 7 characters"]);
+
+        for kind in &KINDS[1..] {
+            assert!(broken_rules(*kind, &"a".repeat(64)).is_empty());
+            assert_eq!(broken_rules(*kind, &"a".repeat(65)),
+                       [format!("{:?} name {} too long. The limit is 64 characters", kind, "a".repeat(65))]);
+        }
+    }
+
+    #[test]
+    fn test_configured_length() {
+        let options = CompilerOptions { tag_name_limit: Some(10), name_limit: Some(3), ..Default::default() };
+        assert!(validate(IdentifierKind::Tag, &Token::identifier("eightChr", 1), &options).is_empty());
+        assert_eq!(1, validate(IdentifierKind::Routine, &Token::identifier("four", 1), &options).len());
+    }
+
+    #[test]
+    fn test_characters() {
+        for kind in KINDS {
+            assert_eq!(broken_rules(kind, "1abc"), [format!("{:?} name 1abc must start with a letter", kind)]);
+            assert_eq!(broken_rules(kind, "a_b"), [format!("{:?} name a_b may only contain letters and digits", kind)]);
+            assert_eq!(broken_rules(kind, ""), [format!("{:?} name  must start with a letter", kind)]);
+        }
+    }
+
+    #[test]
+    fn test_reserved() {
+        for kind in &KINDS[1..] {
+            assert_eq!(broken_rules(*kind, "EmitEvent"), [format!("{:?} name EmitEvent is reserved", kind)]);
+        }
+        assert_eq!(broken_rules(IdentifierKind::Tag, "EmitEvent").len(), 2);
+    }
+
+    #[test]
+    fn test_location() {
+        // Each rule is reported at the name
+        let diagnostics = validate(IdentifierKind::Tag, &Token::identifier("EmitEvent", 4), &CompilerOptions::default());
+        assert_eq!(vec![Some((4, 1)), Some((4, 1))], diagnostics.iter().map(|diagnostic| diagnostic.error.location()).collect::<Vec<_>>());
+    }
+}
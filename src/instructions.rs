@@ -0,0 +1,199 @@
+use crate::lexer::TokenType;
+
+/// Whether an instruction contributes to the rung condition or acts on it
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum InstructionClass {
+    Input,
+    Output
+}
+
+/// What an operand of an instruction refers to, which decides how it is checked
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OperandKind {
+    /// A boolean tag or an element of a tag array, which must be declared before use
+    BoolTag,
+    /// A routine, which may be defined anywhere in the program
+    Routine,
+    /// An event, which must be bound to a task somewhere in the program
+    Event,
+    /// A TIMER tag, which must be declared before use
+    Timer,
+    /// A COUNTER tag, which must be declared before use
+    Counter,
+    /// An INT tag, which must be declared before use
+    IntTag,
+    /// An INT tag or a whole number
+    IntValue,
+    /// A label, which must be defined exactly once in the routine
+    Label
+}
+
+impl OperandKind {
+    pub fn name(&self) -> &'static str {
+        match self {
+            OperandKind::BoolTag => "bool tag",
+            OperandKind::Routine => "routine",
+            OperandKind::Event => "event",
+            OperandKind::Timer => "timer",
+            OperandKind::Counter => "counter",
+            OperandKind::IntTag => "int tag",
+            OperandKind::IntValue => "int value",
+            OperandKind::Label => "label"
+        }
+    }
+}
+
+/// Everything the compiler needs to know about an instruction apart from the code it generates
+#[derive(Debug)]
+pub struct InstructionSpec {
+    pub instruction: TokenType,
+    pub keyword: &'static str,
+    pub class: InstructionClass,
+    pub operands: &'static [OperandKind],
+    /// Contacts that are checked for contradicting each other within a rung
+    pub check_contradiction: bool,
+    /// One sentence summary for the language reference
+    pub description: &'static str,
+    /// Body of a rung that uses the instruction, shown in the language reference
+    pub example: &'static str
+}
+
+pub const INSTRUCTIONS: [InstructionSpec; 23] = [
+    InstructionSpec { instruction: TokenType::Xic, keyword: "XIC", class: InstructionClass::Input,
+                      operands: &[OperandKind::BoolTag], check_contradiction: true,
+                      description: "Examines if closed: the rung stays true only if the tag is on",
+                      example: "XIC start\nOTE motor" },
+    InstructionSpec { instruction: TokenType::Xio, keyword: "XIO", class: InstructionClass::Input,
+                      operands: &[OperandKind::BoolTag], check_contradiction: true,
+                      description: "Examines if open: the rung stays true only if the tag is off",
+                      example: "XIO stop\nOTE motor" },
+    InstructionSpec { instruction: TokenType::Ote, keyword: "OTE", class: InstructionClass::Output,
+                      operands: &[OperandKind::BoolTag], check_contradiction: false,
+                      description: "Output energize: sets the tag to whether the rung is true",
+                      example: "XIC start\nOTE motor" },
+    InstructionSpec { instruction: TokenType::Otl, keyword: "OTL", class: InstructionClass::Output,
+                      operands: &[OperandKind::BoolTag], check_contradiction: false,
+                      description: "Output latch: turns the tag on when the rung is true and leaves it on",
+                      example: "XIC start\nOTL motor" },
+    InstructionSpec { instruction: TokenType::Otu, keyword: "OTU", class: InstructionClass::Output,
+                      operands: &[OperandKind::BoolTag], check_contradiction: false,
+                      description: "Output unlatch: turns the tag off when the rung is true and leaves it off",
+                      example: "XIC stop\nOTU motor" },
+    InstructionSpec { instruction: TokenType::Jsr, keyword: "JSR", class: InstructionClass::Output,
+                      operands: &[OperandKind::Routine], check_contradiction: false,
+                      description: "Jump to subroutine: runs the routine when the rung is true",
+                      example: "XIC start\nJSR Helper" },
+    InstructionSpec { instruction: TokenType::Ret, keyword: "RET", class: InstructionClass::Output,
+                      operands: &[], check_contradiction: false,
+                      description: "Return: leaves the current routine when the rung is true",
+                      example: "XIC stop\nRET" },
+    InstructionSpec { instruction: TokenType::Emit, keyword: "EMIT", class: InstructionClass::Output,
+                      operands: &[OperandKind::Event], check_contradiction: false,
+                      description: "Emits the event when the rung is true, which runs the tasks bound to it",
+                      example: "XIC start\nEMIT done" },
+    InstructionSpec { instruction: TokenType::Ton, keyword: "TON", class: InstructionClass::Output,
+                      operands: &[OperandKind::Timer], check_contradiction: false,
+                      description: "Timer on delay: sets the timer's DN bit once the rung has been true for its preset",
+                      example: "XIC start\nTON delay" },
+    InstructionSpec { instruction: TokenType::Tof, keyword: "TOF", class: InstructionClass::Output,
+                      operands: &[OperandKind::Timer], check_contradiction: false,
+                      description: "Timer off delay: clears the timer's DN bit once the rung has been false for its preset",
+                      example: "XIC start\nTOF delay" },
+    InstructionSpec { instruction: TokenType::Ctu, keyword: "CTU", class: InstructionClass::Output,
+                      operands: &[OperandKind::Counter], check_contradiction: false,
+                      description: "Count up: adds one to the counter each time the rung turns true",
+                      example: "XIC start\nCTU parts" },
+    InstructionSpec { instruction: TokenType::Ctd, keyword: "CTD", class: InstructionClass::Output,
+                      operands: &[OperandKind::Counter], check_contradiction: false,
+                      description: "Count down: takes one from the counter each time the rung turns true",
+                      example: "XIC stop\nCTD parts" },
+    InstructionSpec { instruction: TokenType::Res, keyword: "RES", class: InstructionClass::Output,
+                      operands: &[OperandKind::Counter], check_contradiction: false,
+                      description: "Reset: sets the counter back to zero and clears its DN bit when the rung is true",
+                      example: "XIC stop\nRES parts" },
+    InstructionSpec { instruction: TokenType::Mov, keyword: "MOV", class: InstructionClass::Output,
+                      operands: &[OperandKind::IntValue, OperandKind::IntTag], check_contradiction: false,
+                      description: "Move: copies the first operand into the INT tag when the rung is true",
+                      example: "XIC start\nMOV 5 count" },
+    InstructionSpec { instruction: TokenType::Add, keyword: "ADD", class: InstructionClass::Output,
+                      operands: &[OperandKind::IntValue, OperandKind::IntValue, OperandKind::IntTag], check_contradiction: false,
+                      description: "Add: stores the sum of the first two operands in the INT tag when the rung is true",
+                      example: "XIC start\nADD count 1 count" },
+    InstructionSpec { instruction: TokenType::Sub, keyword: "SUB", class: InstructionClass::Output,
+                      operands: &[OperandKind::IntValue, OperandKind::IntValue, OperandKind::IntTag], check_contradiction: false,
+                      description: "Subtract: stores the first operand minus the second in the INT tag when the rung is true",
+                      example: "XIC stop\nSUB count 1 count" },
+    InstructionSpec { instruction: TokenType::Equ, keyword: "EQU", class: InstructionClass::Input,
+                      operands: &[OperandKind::IntValue, OperandKind::IntValue], check_contradiction: false,
+                      description: "Equal: the rung stays true only if the operands are equal",
+                      example: "EQU count 3\nOTE motor" },
+    InstructionSpec { instruction: TokenType::Neq, keyword: "NEQ", class: InstructionClass::Input,
+                      operands: &[OperandKind::IntValue, OperandKind::IntValue], check_contradiction: false,
+                      description: "Not equal: the rung stays true only if the operands differ",
+                      example: "NEQ count 0\nOTE motor" },
+    InstructionSpec { instruction: TokenType::Grt, keyword: "GRT", class: InstructionClass::Input,
+                      operands: &[OperandKind::IntValue, OperandKind::IntValue], check_contradiction: false,
+                      description: "Greater than: the rung stays true only if the first operand is greater than the second",
+                      example: "GRT count 10\nOTE motor" },
+    InstructionSpec { instruction: TokenType::Les, keyword: "LES", class: InstructionClass::Input,
+                      operands: &[OperandKind::IntValue, OperandKind::IntValue], check_contradiction: false,
+                      description: "Less than: the rung stays true only if the first operand is less than the second",
+                      example: "LES count 10\nOTE motor" },
+    InstructionSpec { instruction: TokenType::Ons, keyword: "ONS", class: InstructionClass::Input,
+                      operands: &[OperandKind::BoolTag], check_contradiction: false,
+                      description: "One shot: the rung stays true only on the scan where the conditions before it turn true. \
+                                    The tag remembers them for the next scan",
+                      example: "XIC start\nONS pulse\nCTU parts" },
+    InstructionSpec { instruction: TokenType::Lbl, keyword: "LBL", class: InstructionClass::Input,
+                      operands: &[OperandKind::Label], check_contradiction: false,
+                      description: "Label: marks its rung as a place JMP can go to. It has to be the first instruction of the rung \
+                                    and doesn't change the rung condition",
+                      example: "LBL retry\nXIC start\nOTE motor" },
+    InstructionSpec { instruction: TokenType::Jmp, keyword: "JMP", class: InstructionClass::Output,
+                      operands: &[OperandKind::Label], check_contradiction: false,
+                      description: "Jump: once the rung is done, carries on from the rung with the label when the rung is true, \
+                                    skipping the rungs in between or running them again",
+                      example: "XIC stop\nJMP skip" }
+];
+
+impl InstructionSpec {
+    /// Whether the instruction assigns to its last operand. ONS is an input, but stores the rung condition in its tag
+    pub fn writes_tag(&self) -> bool {
+        let writable = matches!(self.operands.last(), Some(OperandKind::BoolTag) | Some(OperandKind::IntTag));
+        writable && (self.class == InstructionClass::Output || self.instruction == TokenType::Ons)
+    }
+}
+
+/// Looks up the spec of an instruction, or None if the token isn't an instruction
+pub fn spec(instruction: TokenType) -> Option<&'static InstructionSpec> {
+    INSTRUCTIONS.iter().find(|spec| spec.instruction == instruction)
+}
+
+/// Keywords of every instruction in the given class, in table order
+pub fn keywords(class: InstructionClass) -> Vec<String> {
+    INSTRUCTIONS.iter()
+                .filter(|spec| spec.class == class)
+                .map(|spec| spec.keyword.to_string())
+                .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::lexer::Token;
+
+    #[test]
+    fn test_instruction_table() {
+        for spec in &INSTRUCTIONS {
+            // The keyword must lex to the instruction it describes
+            assert_eq!(Some(spec.instruction), Token::is_keyword(spec.keyword));
+        }
+
+        assert_eq!(vec!["XIC", "XIO", "EQU", "NEQ", "GRT", "LES", "ONS", "LBL"], keywords(InstructionClass::Input));
+        assert!(spec(TokenType::Ons).unwrap().writes_tag());
+        assert!(spec(TokenType::Mov).unwrap().writes_tag());
+        assert!(!spec(TokenType::Xic).unwrap().writes_tag());
+        assert!(!spec(TokenType::Jsr).unwrap().writes_tag());
+        assert!(spec(TokenType::Tag).is_none());
+    }
+}
@@ -1,7 +1,13 @@
+use std::collections::HashSet;
+use std::ops::Range;
+use std::rc::Rc;
 
-#[derive(Debug, PartialEq, Clone, Copy)]
+#[derive(Debug, PartialEq, Clone, Copy, Default)]
 pub enum TokenType {
+    #[default]
     Eof = -1,
+    /// Text the lexer couldn't make a token of
+    Invalid = -2,
     NewLine = 0,
     Number = 1,
     Identifier = 2,
@@ -26,6 +32,33 @@ pub enum TokenType {
     Jsr = 118,
     Ret = 119,
     Emit = 120,
+    Program = 121,
+    Version = 122,
+    Entry = 123,
+    Temp = 124,
+    EventSpace = 125,
+    EndEventSpace = 126,
+    Timer = 127,
+    Ton = 128,
+    Tof = 129,
+    Counter = 130,
+    Ctu = 131,
+    Ctd = 132,
+    Res = 133,
+    Int = 134,
+    Mov = 135,
+    Add = 136,
+    Sub = 137,
+    Equ = 138,
+    Neq = 139,
+    Grt = 140,
+    Les = 141,
+    Ons = 142,
+    Bst = 143,
+    Nxb = 144,
+    Bnd = 145,
+    Lbl = 146,
+    Jmp = 147,
 
     Eq = 201,
     OpenAngle = 202,
@@ -35,16 +68,70 @@ pub enum TokenType {
     Indexer = 206
 }
 
-impl Default for TokenType {
-    fn default() -> Self {
-        TokenType::Eof    
-    }
-}
-
-#[derive(Default, Debug, Clone)]
+/// Every keyword of the language and the token it produces
+pub const KEYWORDS: [(&str, TokenType); 47] = [
+    ("TAG", TokenType::Tag),
+    ("TASK", TokenType::Task),
+    ("ENDTASK", TokenType::EndTask),
+    ("PERIOD", TokenType::Period),
+    ("EVENT", TokenType::Event),
+    ("CONTINUOUS", TokenType::Continuous),
+    ("ROUTINE", TokenType::Routine),
+    ("ENDROUTINE", TokenType::EndRoutine),
+    ("RUNG", TokenType::Rung),
+    ("ENDRUNG", TokenType::EndRung),
+    ("FALSE", TokenType::False),
+    ("TRUE", TokenType::True),
+    ("XIC", TokenType::Xic),
+    ("XIO", TokenType::Xio),
+    ("OTE", TokenType::Ote),
+    ("OTL", TokenType::Otl),
+    ("OTU", TokenType::Otu),
+    ("JSR", TokenType::Jsr),
+    ("RET", TokenType::Ret),
+    ("EMIT", TokenType::Emit),
+    ("PROGRAM", TokenType::Program),
+    ("VERSION", TokenType::Version),
+    ("ENTRY", TokenType::Entry),
+    ("TEMP", TokenType::Temp),
+    ("EVENTSPACE", TokenType::EventSpace),
+    ("ENDEVENTSPACE", TokenType::EndEventSpace),
+    ("TIMER", TokenType::Timer),
+    ("TON", TokenType::Ton),
+    ("TOF", TokenType::Tof),
+    ("COUNTER", TokenType::Counter),
+    ("CTU", TokenType::Ctu),
+    ("CTD", TokenType::Ctd),
+    ("RES", TokenType::Res),
+    ("INT", TokenType::Int),
+    ("MOV", TokenType::Mov),
+    ("ADD", TokenType::Add),
+    ("SUB", TokenType::Sub),
+    ("EQU", TokenType::Equ),
+    ("NEQ", TokenType::Neq),
+    ("GRT", TokenType::Grt),
+    ("LES", TokenType::Les),
+    ("ONS", TokenType::Ons),
+    ("BST", TokenType::Bst),
+    ("NXB", TokenType::Nxb),
+    ("BND", TokenType::Bnd),
+    ("LBL", TokenType::Lbl),
+    ("JMP", TokenType::Jmp)
+];
+
+/// Keywords that can follow the opening angle bracket of a task
+pub const TASK_ATTRIBUTES: [TokenType; 3] = [TokenType::Period, TokenType::Event, TokenType::Continuous];
+
+#[derive(Default, Debug, Clone, PartialEq)]
 pub struct Token {
-    text: String,
-    token_type: TokenType
+    /// Shared with every other token of the same text, so that copying a token doesn't allocate
+    text: Rc<str>,
+    token_type: TokenType,
+    span: Range<usize>,
+    line: usize,
+    column: usize,
+    /// Why an Invalid token couldn't be read
+    error: Option<Rc<str>>
 }
 
 impl Token {
@@ -56,73 +143,110 @@ impl Token {
         &self.text
     }
 
+    /// Byte offsets of the token within the source code
+    pub fn get_span(&self) -> &Range<usize> {
+        &self.span
+    }
+
+    /// Why the lexer couldn't make a token of the text, for Invalid tokens
+    pub fn get_error(&self) -> Option<&str> {
+        self.error.as_deref()
+    }
+
+    /// Line of the token's first character, counting from 1
+    pub fn get_line(&self) -> usize {
+        self.line
+    }
+
+    /// Column of the token's first character in characters, counting from 1
+    pub fn get_column(&self) -> usize {
+        self.column
+    }
+
+    /// Identifier token at the start of the given line, for tests that don't need the lexer
+    #[cfg(test)]
+    pub fn identifier(text: &str, line: usize) -> Token {
+        Token { text: text.into(), token_type: TokenType::Identifier, span: 0..text.len(), line, column: 1, error: None }
+    }
+
     pub fn is_keyword(token_text: &str) -> Option<TokenType> {
-        let mut retval: Option<TokenType> = None;
-        match token_text {
-            "TAG" => retval = Some(TokenType::Tag),
-            "TASK" => retval = Some(TokenType::Task),
-            "ENDTASK" => retval = Some(TokenType::EndTask),
-            "PERIOD" => retval = Some(TokenType::Period),
-            "EVENT" => retval = Some(TokenType::Event),
-            "CONTINUOUS" => retval = Some(TokenType::Continuous),
-            "ROUTINE" => retval = Some(TokenType::Routine),
-            "ENDROUTINE" => retval = Some(TokenType::EndRoutine),
-            "RUNG" => retval = Some(TokenType::Rung),
-            "ENDRUNG" => retval = Some(TokenType::EndRung),
-            "FALSE" => retval = Some(TokenType::False),
-            "TRUE" => retval = Some(TokenType::True),
-            "XIC" => retval = Some(TokenType::Xic),
-            "XIO" => retval = Some(TokenType::Xio),
-            "OTE" => retval = Some(TokenType::Ote),
-            "OTL" => retval = Some(TokenType::Otl),
-            "OTU" => retval = Some(TokenType::Otu),
-            "JSR" => retval = Some(TokenType::Jsr),
-            "RET" => retval = Some(TokenType::Ret),
-            "EMIT" => retval = Some(TokenType::Emit),
-            _ => ()
-        }
-        retval
+        KEYWORDS.iter()
+                .find(|(keyword, _)| *keyword == token_text)
+                .map(|(_, token_type)| *token_type)
     }
 }
 
 pub struct Lexer {
-    source_code: String,
-    line_number: u32,
+    characters: Vec<char>,
+    line_number: usize,
+    line_start: usize,
     current_character: char,
-    current_position: usize
+    current_position: usize,
+    /// Byte offset of the current character in the source code, which is what spans are given in
+    byte_position: usize,
+    /// Text of every distinct token so far. Names repeat far more often than new ones appear
+    interned: HashSet<Rc<str>>,
+    buffer: String
 }
 
 impl Lexer {
     pub fn new(mut source_code: String) -> Lexer {
         source_code.push('\n');
-        let mut lexer = Lexer {
-            source_code,
+        let characters = source_code.chars().collect::<Vec<char>>();
+        Lexer {
+            current_character: characters[0],
+            characters,
             line_number: 1,
-            current_character: '\0',
-            current_position: 0
-        };
-        lexer.current_character = lexer.source_code.chars().collect::<Vec<char>>()[0];
-        lexer
+            line_start: 0,
+            current_position: 0,
+            byte_position: 0,
+            interned: HashSet::new(),
+            buffer: String::new()
+        }
     }
 
     fn next_character(&mut self) {
+        self.current_position += 1;
+        self.byte_position += self.current_character.len_utf8();
         if self.current_character == '\n' {
             self.line_number += 1;
+            self.line_start = self.current_position;
         }
+        self.current_character = *self.characters.get(self.current_position).unwrap_or(&'\0');
+    }
 
-        self.current_position += 1;
-        if self.current_position >= self.source_code.len() {
-            self.current_character = '\0';
-        } else {
-            self.current_character = self.source_code.chars().collect::<Vec<char>>()[self.current_position];
-        }
+    fn peek(&self) -> char {
+        *self.characters.get(self.current_position + 1).unwrap_or(&'\0')
+    }
+
+    /// Source code between two character offsets
+    fn text(&mut self, range: Range<usize>) -> Rc<str> {
+        self.buffer.clear();
+        self.buffer.extend(&self.characters[range]);
+        self.intern()
     }
 
-    fn peek(&self) -> char{
-        if self.current_position + 1 >= self.source_code.len() {
-            return '\0';
+    /// Shared copy of the text in the buffer, which is only allocated the first time the text is seen
+    fn intern(&mut self) -> Rc<str> {
+        if let Some(text) = self.interned.get(self.buffer.as_str()) {
+            return text.clone();
         }
-        self.source_code.chars().collect::<Vec<char>>()[self.current_position + 1]
+        let text: Rc<str> = Rc::from(self.buffer.as_str());
+        self.interned.insert(text.clone());
+        text
+    }
+
+    fn current_text(&mut self) -> Rc<str> {
+        self.buffer.clear();
+        self.buffer.push(self.current_character);
+        self.intern()
+    }
+
+    /// Makes an Invalid token of the text from `start_position` up to the current character
+    fn invalid(&mut self, token: &mut Token, start_position: usize, error: String) {
+        token.text = self.text(start_position..self.current_position + 1);
+        token.token_type = TokenType::Invalid;
+        token.error = Some(Rc::from(error));
     }
 
     fn skip_whitespace(&mut self) {
@@ -144,46 +268,83 @@ impl Lexer {
     pub fn get_token(&mut self) -> Token {
         self.skip_whitespace();
         self.skip_comment();
-        let mut token = Token::default();
+        let mut token = Token {
+            line: self.line_number,
+            column: self.current_position - self.line_start + 1,
+            ..Default::default()
+        };
+        let start_byte = self.byte_position;
 
         match self.current_character {
             '=' => {
-                token.text = self.current_character.to_string();
+                token.text = self.current_text();
                 token.token_type = TokenType::Eq;
             },
             '<' => {
-                token.text = self.current_character.to_string();
+                token.text = self.current_text();
                 token.token_type = TokenType::OpenAngle;
             },
             '>' => {
-                token.text = self.current_character.to_string();
+                token.text = self.current_text();
                 token.token_type = TokenType::CloseAngle;
             },
             '[' => {
-                token.text = self.current_character.to_string();
+                token.text = self.current_text();
                 token.token_type = TokenType::OpenBracket;
             },
             ']' => {
-                token.text = self.current_character.to_string();
+                token.text = self.current_text();
                 token.token_type = TokenType::CloseBracket;
             },
             '\n' => {
-                token.text = self.current_character.to_string();
+                token.text = self.current_text();
                 token.token_type = TokenType::NewLine;
             },
             '\0' => {
-                token.text = self.current_character.to_string();
+                token.text = self.current_text();
                 token.token_type = TokenType::Eof;
             },
             '.' => {
-                token.text = self.current_character.to_string();
+                token.text = self.current_text();
                 token.token_type = TokenType::Indexer;
+            },
+            '`' => {
+                // Escaped identifier, which allows keywords to be used as names
+                let start_position = self.current_position + 1;
+                let mut illegal_character = None;
+                while self.peek() != '`' && self.peek() != '\n' && self.peek() != '\0' {
+                    if illegal_character.is_none() && !self.peek().is_alphabetic() && !self.peek().is_ascii_digit() {
+                        illegal_character = Some(self.peek());
+                    }
+                    self.next_character();
+                }
+                let word = self.text(start_position..self.current_position + 1);
+
+                // Move onto the closing backtick
+                let closed = self.peek() == '`';
+                if closed {
+                    self.next_character();
+                }
+
+                let error = match illegal_character {
+                    _ if !closed => Some("Escaped identifier is missing a closing `".to_string()),
+                    Some(character) => Some(format!("Illegal character in escaped identifier: {}", character)),
+                    None if word.is_empty() => Some("Escaped identifier can't be empty".to_string()),
+                    None => None
+                };
+                match error {
+                    Some(error) => self.invalid(&mut token, start_position - 1, error),
+                    None => {
+                        token.text = word;
+                        token.token_type = TokenType::Identifier;
+                    }
+                }
             }
             _ => {
-                if self.current_character.is_digit(10) {
+                if self.current_character.is_ascii_digit() {
                     // Token is a number, so get all the next digits
                     let start_position = self.current_position;
-                    while self.peek().is_digit(10) {
+                    while self.peek().is_ascii_digit() {
                         self.next_character();
                     }
 
@@ -192,39 +353,48 @@ impl Lexer {
                         self.next_character();
 
                         // We need to have at least one digit after the decimal
-                        if !self.peek().is_digit(10) {
-                            panic!("Illegal character in number");
+                        if !self.peek().is_ascii_digit() {
+                            self.invalid(&mut token, start_position, "Illegal character in number".to_string());
                         }
 
                         // Get all the digits after the decimal point
-                        while self.peek().is_digit(10) {
+                        while self.peek().is_ascii_digit() {
                             self.next_character();
                         }
                     }
 
                     // Construct the substring and token
-                    let number = &self.source_code[start_position..self.current_position + 1];
-                    token.text = number.to_string();
-                    token.token_type = TokenType::Number;
+                    if token.token_type != TokenType::Invalid {
+                        token.text = self.text(start_position..self.current_position + 1);
+                        token.token_type = TokenType::Number;
+                    }
                 } else if self.current_character.is_alphabetic() {
                     // Token is either a keyword or identifier
                     let start_position = self.current_position;
-                    while self.peek().is_alphabetic() || self.peek().is_digit(10) {
+                    while self.peek().is_alphabetic() || self.peek().is_ascii_digit() {
                         self.next_character();
                     }
 
                     // Construct the substring and check if it's a keyword
-                    let word = &self.source_code[start_position..self.current_position + 1];
-                    token.text = word.to_string();
+                    let word = self.text(start_position..self.current_position + 1);
+                    token.text = word;
 
-                    let keyword = Token::is_keyword(word);
+                    let keyword = Token::is_keyword(&token.text);
                     token.token_type = keyword.unwrap_or(TokenType::Identifier);
                 } else {
-                    panic!("Unknown token: {}", self.current_character);
+                    let error = format!("Unknown token: {}", self.current_character);
+                    self.invalid(&mut token, self.current_position, error);
                 }
             }
         }
 
+        // The end of the file doesn't occupy any characters
+        if token.token_type == TokenType::Eof {
+            token.span = start_byte..start_byte;
+        } else {
+            token.span = start_byte..self.byte_position + self.current_character.len_utf8();
+        }
+
         self.next_character();
         token
     }
@@ -239,7 +409,7 @@ mod tests {
     fn test_next_character() {
         let test_input = "test input".to_string();
         let mut lexer = Lexer::new(test_input.clone());
-        assert_eq!(test_input.clone() + "\n", lexer.source_code);
+        assert_eq!((test_input.clone() + "\n").chars().collect::<Vec<char>>(), lexer.characters);
 
         for (i, character) in test_input.chars().enumerate() {
             assert_eq!(character, lexer.current_character);
@@ -271,39 +441,39 @@ mod tests {
 
         let mut token = lexer.get_token();
         assert_eq!(TokenType::Task, token.token_type);
-        assert_eq!("TASK", token.text);
+        assert_eq!("TASK", token.get_text());
 
         token = lexer.get_token();
         assert_eq!(TokenType::OpenAngle, token.token_type);
-        assert_eq!("<", token.text);
+        assert_eq!("<", token.get_text());
 
         token = lexer.get_token();
         assert_eq!(TokenType::Period, token.token_type);
-        assert_eq!("PERIOD", token.text);
+        assert_eq!("PERIOD", token.get_text());
 
         token = lexer.get_token();
         assert_eq!(TokenType::Eq, token.token_type);
-        assert_eq!("=", token.text);
+        assert_eq!("=", token.get_text());
 
         token = lexer.get_token();
         assert_eq!(TokenType::Number, token.token_type);
-        assert_eq!("10.50", token.text);
+        assert_eq!("10.50", token.get_text());
 
         token = lexer.get_token();
         assert_eq!(TokenType::CloseAngle, token.token_type);
-        assert_eq!(">", token.text);
+        assert_eq!(">", token.get_text());
 
         token = lexer.get_token();
         assert_eq!(TokenType::Identifier, token.token_type);
-        assert_eq!("myTask", token.text);
+        assert_eq!("myTask", token.get_text());
 
         token = lexer.get_token();
         assert_eq!(TokenType::NewLine, token.token_type);
-        assert_eq!("\n", token.text);
+        assert_eq!("\n", token.get_text());
 
         token = lexer.get_token();
         assert_eq!(TokenType::Eof, token.token_type);
-        assert_eq!("\0", token.text);
+        assert_eq!("\0", token.get_text());
     }
 
     #[test]
@@ -313,31 +483,31 @@ mod tests {
 
         let mut token = lexer.get_token();
         assert_eq!(TokenType::Tag, token.token_type);
-        assert_eq!("TAG", token.text);
+        assert_eq!("TAG", token.get_text());
 
         token = lexer.get_token();
         assert_eq!(TokenType::OpenBracket, token.token_type);
-        assert_eq!("[", token.text);
+        assert_eq!("[", token.get_text());
 
         token = lexer.get_token();
         assert_eq!(TokenType::Number, token.token_type);
-        assert_eq!("10", token.text);
+        assert_eq!("10", token.get_text());
 
         token = lexer.get_token();
         assert_eq!(TokenType::CloseBracket, token.token_type);
-        assert_eq!("]", token.text);
+        assert_eq!("]", token.get_text());
 
         token = lexer.get_token();
         assert_eq!(TokenType::Identifier, token.token_type);
-        assert_eq!("myTagArray", token.text);
+        assert_eq!("myTagArray", token.get_text());
 
         token = lexer.get_token();
         assert_eq!(TokenType::Eq, token.token_type);
-        assert_eq!("=", token.text);
+        assert_eq!("=", token.get_text());
 
         token = lexer.get_token();
         assert_eq!(TokenType::False, token.token_type);
-        assert_eq!("FALSE", token.text);
+        assert_eq!("FALSE", token.get_text());
     }
 
     #[test]
@@ -347,47 +517,186 @@ mod tests {
 
         let mut token = lexer.get_token();
         assert_eq!(TokenType::Ote, token.token_type);
-        assert_eq!("OTE", token.text);
+        assert_eq!("OTE", token.get_text());
 
         token = lexer.get_token();
         assert_eq!(TokenType::Identifier, token.token_type);
-        assert_eq!("myTagArray", token.text);
+        assert_eq!("myTagArray", token.get_text());
 
         token = lexer.get_token();
         assert_eq!(TokenType::Indexer, token.token_type);
-        assert_eq!(".", token.text);
+        assert_eq!(".", token.get_text());
 
         token = lexer.get_token();
         assert_eq!(TokenType::Number, token.token_type);
-        assert_eq!("0", token.text);
+        assert_eq!("0", token.get_text());
+    }
+
+    #[test]
+    fn test_get_token_escaped_identifier() {
+        let test_input = "TAG `EVENT` = FALSE\nXIC `TRUE`".to_string();
+        let mut lexer = Lexer::new(test_input.clone());
+
+        let mut token = lexer.get_token();
+        assert_eq!(TokenType::Tag, token.token_type);
+
+        token = lexer.get_token();
+        assert_eq!(TokenType::Identifier, token.token_type);
+        assert_eq!("EVENT", token.get_text());
+
+        token = lexer.get_token();
+        assert_eq!(TokenType::Eq, token.token_type);
+
+        token = lexer.get_token();
+        assert_eq!(TokenType::False, token.token_type);
+
+        token = lexer.get_token();
+        assert_eq!(TokenType::NewLine, token.token_type);
+
+        token = lexer.get_token();
+        assert_eq!(TokenType::Xic, token.token_type);
+
+        token = lexer.get_token();
+        assert_eq!(TokenType::Identifier, token.token_type);
+        assert_eq!("TRUE", token.get_text());
+
+        token = lexer.get_token();
+        assert_eq!(TokenType::NewLine, token.token_type);
+    }
+
+    #[test]
+    fn test_get_token_escaped_identifier_invalid() {
+        let cases = [("TAG `EVENT", "`EVENT", "Escaped identifier is missing a closing `"),
+                     ("TAG `my_Tag` = FALSE", "`my_Tag`", "Illegal character in escaped identifier: _"),
+                     ("TAG `` = FALSE", "``", "Escaped identifier can't be empty")];
+        for (test_input, text, error) in cases {
+            let mut lexer = Lexer::new(test_input.to_string());
+            lexer.get_token();
+
+            let token = lexer.get_token();
+            assert_eq!((TokenType::Invalid, text, Some(error)), (token.token_type, token.get_text(), token.get_error()));
+            assert_eq!(5, token.column);
+
+            // Lexing carries on after the invalid text
+            assert_ne!(TokenType::Invalid, lexer.get_token().token_type);
+        }
+    }
+
+    #[test]
+    fn test_get_token_spans() {
+        let inputs = ["TAG[10] array = FALSE # comment\nTASK<PERIOD=10.50> myTask\n\tRUNG\nXIC `EVENT`\nOTE array.3",
+                      "# température élevée\nTAG chaleur = FALSE # °C\nXIC `façade`\nOTE chaleur"];
+        for test_input in inputs {
+            let source_code = format!("{}\n", test_input);
+            let mut lexer = Lexer::new(test_input.to_string());
+
+            loop {
+                let token = lexer.get_token();
+                let span = token.get_span().clone();
+                if token.token_type == TokenType::Eof {
+                    assert_eq!(source_code.len()..source_code.len(), span);
+                    break;
+                }
+
+                // Escaped identifiers include the backticks in their span
+                let slice = &source_code[span];
+                if slice.starts_with('`') {
+                    assert_eq!(format!("`{}`", token.get_text()), slice);
+                } else {
+                    assert_eq!(token.get_text(), slice);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_get_token_interned() {
+        let mut lexer = Lexer::new("XIC motor\nOTE motor".to_string());
+        let tokens = (0..5).map(|_| lexer.get_token()).collect::<Vec<Token>>();
+
+        // Both uses of the name share the text of the first
+        assert_eq!("motor", tokens[1].get_text());
+        assert!(Rc::ptr_eq(&tokens[1].text, &tokens[4].text));
+        assert!(!Rc::ptr_eq(&tokens[0].text, &tokens[3].text));
+        assert_eq!(4, lexer.interned.len());
+    }
+
+    #[test]
+    fn test_get_token_positions() {
+        let test_input = "TAG a = FALSE # comment\n# whole line comment\n\tRUNG  name\n\t\tXIC a".to_string();
+        let mut lexer = Lexer::new(test_input.clone());
+
+        let mut positions = Vec::new();
+        loop {
+            let token = lexer.get_token();
+            if token.token_type == TokenType::Eof {
+                break;
+            }
+            positions.push((token.get_text().to_string(), token.line, token.column));
+        }
+
+        let expected = [("TAG", 1, 1), ("a", 1, 5), ("=", 1, 7), ("FALSE", 1, 9), ("\n", 1, 24),
+                        ("\n", 2, 21),
+                        ("RUNG", 3, 2), ("name", 3, 8), ("\n", 3, 12),
+                        ("XIC", 4, 3), ("a", 4, 7), ("\n", 4, 8)];
+        assert_eq!(expected.map(|(text, line, column)| (text.to_string(), line, column)).to_vec(), positions);
+    }
+
+    #[test]
+    fn test_get_token_non_ascii() {
+        let test_input = "# température trop élevée\nTAG[2] chaleur = FALSE # °C\nOTE chaleur.1\nXIC `façade`".to_string();
+        let mut lexer = Lexer::new(test_input.clone());
+
+        let mut tokens = Vec::new();
+        loop {
+            let token = lexer.get_token();
+            if token.token_type == TokenType::Eof {
+                break;
+            }
+            tokens.push((token.token_type, token.get_text().to_string(), token.column));
+        }
+
+        let expected = [(TokenType::NewLine, "\n", 26),
+                        (TokenType::Tag, "TAG", 1), (TokenType::OpenBracket, "[", 4), (TokenType::Number, "2", 5),
+                        (TokenType::CloseBracket, "]", 6), (TokenType::Identifier, "chaleur", 8), (TokenType::Eq, "=", 16),
+                        (TokenType::False, "FALSE", 18), (TokenType::NewLine, "\n", 28),
+                        (TokenType::Ote, "OTE", 1), (TokenType::Identifier, "chaleur", 5), (TokenType::Indexer, ".", 12),
+                        (TokenType::Number, "1", 13), (TokenType::NewLine, "\n", 14),
+                        (TokenType::Xic, "XIC", 1), (TokenType::Identifier, "façade", 5), (TokenType::NewLine, "\n", 13)];
+        assert_eq!(expected.map(|(token_type, text, column)| (token_type, text.to_string(), column)).to_vec(), tokens);
+    }
+
+    #[test]
+    fn test_get_token_large_source() {
+        // Lexing used to rebuild the characters on every step, which took minutes for sources like this
+        let test_input = "RUNG\nXIC start\nOTE motor\nENDRUNG\n".repeat(100_000);
+        let mut lexer = Lexer::new(test_input);
+
+        let mut token_count = 0;
+        while lexer.get_token().token_type != TokenType::Eof {
+            token_count += 1;
+        }
+        assert_eq!(100_000 * 10 + 1, token_count);
     }
 
     #[test]
-    #[should_panic(expected="Illegal character in number")]
     fn test_get_token_failure_1() {
         let test_input = "TASK<PERIOD=10.> myTask # This is my task".to_string();
         let mut lexer = Lexer::new(test_input.clone());
 
-        lexer.get_token();
-        lexer.get_token();
-        lexer.get_token();
-        lexer.get_token();
-        lexer.get_token();
+        let token = (0..5).map(|_| lexer.get_token()).last().unwrap();
+        assert_eq!((TokenType::Invalid, "10.", Some("Illegal character in number")), (token.token_type, token.get_text(), token.get_error()));
+        assert_eq!(TokenType::CloseAngle, lexer.get_token().token_type);
     }
 
     #[test]
-    #[should_panic(expected="Unknown token: _")]
     fn test_get_token_failure_2() {
         let test_input = "TASK<PERIOD=10.50> my_Task # This is my task".to_string();
         let mut lexer = Lexer::new(test_input.clone());
 
-        lexer.get_token();
-        lexer.get_token();
-        lexer.get_token();
-        lexer.get_token();
-        lexer.get_token();
-        lexer.get_token();
-        lexer.get_token();
-        lexer.get_token();
+        let token = (0..8).map(|_| lexer.get_token()).last().unwrap();
+        assert_eq!((TokenType::Invalid, "_", Some("Unknown token: _")), (token.token_type, token.get_text(), token.get_error()));
+        assert_eq!((22, 21..22), (token.column, token.get_span().clone()));
+        assert_eq!("Task", lexer.get_token().get_text());
     }
-}
\ No newline at end of file
+}
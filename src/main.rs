@@ -1,32 +1,597 @@
-
+use std::env;
 use std::fs;
-use clap::Parser;
+use std::io;
+use std::path::Path;
+use std::process;
+use clap::{ArgMatches, Args, CommandFactory, FromArgMatches, Subcommand};
+use clap::{ErrorKind, ValueSource};
+use clap_complete::Shell;
 
+mod about;
+mod cache;
+mod call_graph;
+mod clock;
+mod docs;
 mod emitter;
+mod encoding;
+mod error;
+mod header;
 mod lexer;
 mod parse;
+mod plan;
 mod code_generation;
-#[derive(Parser)]
-#[clap(about, version, author)]
-struct Args {
+mod identifiers;
+mod instructions;
+mod options;
+mod policy;
+mod schema;
+mod validation;
+mod stats;
+mod xref;
+
+const LONG_VERSION: &str = concat!(env!("CARGO_PKG_VERSION"), "
+
+Upgrade note: the output file now defaults to the source file name with a .out
+extension instead of Program.out. Pass --legacy-out-name for the old behavior.");
+
+#[derive(clap::Parser)]
+#[clap(about, version, author, long_version = LONG_VERSION)]
+#[clap(args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
+struct Cli {
+    #[clap(subcommand)]
+    command: Option<Command>,
+
+    /// Print a JSON description of the compiler's capabilities and limits
+    #[clap(long, exclusive = true)]
+    about_json: bool,
+
+    // Compiling without a subcommand behaves like `build`
+    #[clap(flatten)]
+    build: BuildArgs
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Compile a source file
+    Build(BuildArgs),
+
+    /// Check that a source file compiles without writing any output, the same as build --check
+    Check(BuildArgs),
+
+    /// Report how tags are used across the program
+    Xref(XrefArgs),
+
+    /// Print a completion script for the given shell to stdout
+    Completions {
+        #[clap(value_enum)]
+        shell: Shell
+    },
+
+    /// Generate a reference of the language from the compiler's own tables
+    Docs {
+        /// Format of the reference
+        #[clap(long, value_enum, default_value = "markdown")]
+        format: DocsFormat,
+
+        /// File to write the reference to. Printed to stdout if left out
+        #[clap(short, long)]
+        out: Option<String>
+    },
+
+    /// Print the JSON Schema of one of the JSON files the compiler writes
+    Schema {
+        #[clap(value_enum)]
+        artifact: schema::Artifact
+    }
+}
+
+#[derive(Args)]
+struct SourceArgs {
     /// File containing source code to compile
-    #[clap(short, long)]
-    source_file: String,
+    // Optional only so that it can be left out when a subcommand is given
+    #[clap(short, long, required = true, help_heading = "INPUT")]
+    source_file: Option<String>,
+
+    /// Encoding of the source file. Detected from the byte order mark or the contents by default
+    #[clap(long, value_enum, help_heading = "INPUT")]
+    input_encoding: Option<encoding::Encoding>
+}
+
+#[derive(Args)]
+struct CompileArgs {
+    /// Base tick of the runtime scheduler in milliseconds. Task periods
+    /// that aren't a multiple of it produce a warning
+    #[clap(long, value_parser = clap::value_parser!(u32).range(1..), help_heading = "CHECKS")]
+    scheduler_tick: Option<u32>,
+
+    /// Treat warnings as errors
+    #[clap(long, help_heading = "CHECKS")]
+    deny_warnings: bool,
+
+    /// Require the program to start with a PROGRAM statement
+    #[clap(long, help_heading = "CHECKS")]
+    strict: bool,
+
+    /// Largest allowed size of the generated output in bytes
+    #[clap(long, default_value_t = options::DEFAULT_MAX_OUTPUT_SIZE, help_heading = "CHECKS")]
+    max_output_size: usize,
+
+    /// Number of errors after which the compile stops reporting them
+    #[clap(long, default_value_t = options::DEFAULT_MAX_ERRORS as u64, value_parser = clap::value_parser!(u64).range(1..),
+           help_heading = "CHECKS")]
+    max_errors: u64,
+
+    /// TOML file restricting the instructions, task types and JSR depth the program may use
+    #[clap(long, value_name = "FILE", help_heading = "CHECKS")]
+    policy: Option<String>
+}
+
+#[derive(Args)]
+struct BuildArgs {
+    #[clap(flatten)]
+    source: SourceArgs,
+
+    /// Name of the output file, or - for stdout. Defaults to the source file with a .out extension
+    #[clap(short, long, help_heading = "OUTPUT")]
+    out: Option<String>,
+
+    /// Default the output file to Program.out like earlier releases did
+    #[clap(long, help_heading = "OUTPUT")]
+    legacy_out_name: bool,
+
+    /// Print statistics about the generated code
+    #[clap(long, help_heading = "OUTPUT")]
+    stats: bool,
+
+    /// File whose contents are placed at the top of the output as a comment. It may use the variables
+    /// {program}, {program_version}, {version}, {date}, {timestamp}, {source_hash} and {target}
+    #[clap(long, help_heading = "OUTPUT")]
+    header_template: Option<String>,
+
+    /// Leave anything that changes between builds, like the date, out of the output.
+    /// Times are still written when SOURCE_DATE_EPOCH pins them
+    #[clap(long, help_heading = "OUTPUT")]
+    reproducible: bool,
+
+    /// JSON lines file that a line of metrics about the program is appended to on every build
+    #[clap(long, value_name = "FILE", help_heading = "OUTPUT")]
+    stats_history: Option<String>,
+
+    /// TOML file with [warning] and [error] tables giving the largest each metric may be,
+    /// like rungs = 500 or JSR = 40. Compared against the previous release in --stats-history
+    #[clap(long, value_name = "FILE", help_heading = "CHECKS")]
+    stats_budget: Option<String>,
+
+    /// Order of the declarations in the TAG section of the output. Names are compared
+    /// ignoring case, with uppercase first when two only differ in case
+    #[clap(long, value_enum, default_value = "source", value_name = "ORDER", help_heading = "OUTPUT")]
+    sort_tags: options::TagOrder,
+
+    /// Declare tags with TAG and TAG_ARRAY lines like earlier releases did, instead of Python assignments
+    #[clap(long, help_heading = "OUTPUT")]
+    legacy_tag_declarations: bool,
+
+    /// What the generated code does when an array indexed by an INT tag, like lights.cursor,
+    /// is indexed out of its bounds
+    #[clap(long, value_enum, default_value = "raise", value_name = "MODE", help_heading = "OUTPUT")]
+    index_bounds: options::IndexBounds,
+
+    /// Directory of the outputs of earlier builds. A build with the same source, options and compiler
+    /// version as an earlier one copies its output and warnings from there instead of compiling
+    #[clap(long, value_name = "DIR", help_heading = "OUTPUT")]
+    cache_dir: Option<String>,
+
+    /// Whether the build may use outputs in --cache-dir and store its own output there [default: readwrite]
+    #[clap(long, value_enum, value_name = "MODE", requires = "cache-dir", help_heading = "OUTPUT")]
+    cache_mode: Option<cache::CacheMode>,
+
+    /// Only check that the program compiles, without writing any output
+    #[clap(long, help_heading = "OUTPUT")]
+    check: bool,
+
+    /// Print a JSON plan of the files the build would write, without compiling or writing anything
+    #[clap(long, help_heading = "OUTPUT")]
+    plan: bool,
+
+    #[clap(flatten)]
+    compile: CompileArgs
+}
+
+#[derive(clap::ValueEnum, Clone)]
+enum DocsFormat {
+    Markdown
+}
+
+#[derive(clap::ValueEnum, Clone)]
+enum ImpactFormat {
+    Text,
+    Dot
+}
+
+#[derive(clap::ValueEnum, Clone)]
+enum LastWriterFormat {
+    Text,
+    Json
+}
+
+#[derive(Args)]
+#[clap(group(clap::ArgGroup::new("report").required(true).args(&["impact", "last-writer"])))]
+struct XrefArgs {
+    #[clap(flatten)]
+    source: SourceArgs,
+
+    #[clap(flatten)]
+    compile: CompileArgs,
+
+    /// Print every tag and task downstream of the given tag
+    #[clap(long, value_name = "TAG", help_heading = "REPORT")]
+    impact: Option<String>,
+
+    /// How many tags away from the --impact tag to follow
+    #[clap(long, default_value_t = 10, help_heading = "REPORT")]
+    impact_depth: usize,
+
+    /// Output format of the --impact report
+    #[clap(long, value_enum, default_value = "text", help_heading = "REPORT")]
+    impact_format: ImpactFormat,
+
+    /// Print every write to the given tag, or element of a tag array, in the order a scan runs them,
+    /// and which of them writes last
+    #[clap(long, value_name = "TAG", help_heading = "REPORT")]
+    last_writer: Option<String>,
+
+    /// Output format of the --last-writer report
+    #[clap(long, value_enum, default_value = "text", help_heading = "REPORT")]
+    last_writer_format: LastWriterFormat
+}
+
+impl CompileArgs {
+    fn options(&self) -> options::CompilerOptions {
+        options::CompilerOptions {
+            scheduler_tick: self.scheduler_tick,
+            deny_warnings: self.deny_warnings,
+            strict: self.strict,
+            max_output_size: Some(self.max_output_size),
+            max_errors: Some(self.max_errors as usize),
+            policy: self.policy.as_deref().map(|path| policy::Policy::load(path).unwrap_or_else(|error| {
+                eprintln!("Error: {}: {}", path, error);
+                process::exit(1);
+            })),
+            ..Default::default()
+        }
+    }
+}
+
+fn read_source(source: &SourceArgs) -> String {
+    let source_file = source.source_file.as_ref().expect("Source file is required");
+    let decoded = match fs::read(source_file).map_err(|error| error.to_string())
+                                             .and_then(|bytes| encoding::decode(&bytes, source.input_encoding)) {
+        Ok(decoded) => decoded,
+        Err(error) => {
+            eprintln!("Error: {}: {}", source_file, error);
+            process::exit(1);
+        }
+    };
+    if let Some(note) = decoded.note() {
+        eprintln!("Note: {}: {}", source_file, note);
+    }
+    let source_code = decoded.text;
+
+    // Catch compiled output being passed back in before it produces a wall of errors
+    if emitter::is_compiled_output(&source_code) {
+        eprintln!("{} looks like compiled output, not LogText source", source_file);
+        process::exit(2);
+    }
+    source_code
+}
+
+impl BuildArgs {
+    fn output_file(&self) -> String {
+        if let Some(out) = &self.out {
+            return out.clone();
+        }
+        if self.legacy_out_name {
+            return "Program.out".to_string();
+        }
+
+        // Name the output after the source so compiling several files doesn't overwrite one output
+        let source_file = self.source.source_file.as_ref().unwrap();
+        let output_file = Path::new(source_file).with_extension("out");
+        if output_file == Path::new(source_file) {
+            // Never overwrite a source file that already has the .out extension
+            return format!("{}.out", source_file);
+        }
+        output_file.to_string_lossy().to_string()
+    }
+}
+
+/// Parses the whole program, exiting with the error if it doesn't compile
+fn compile(parser: &mut parse::Parser, source: &SourceArgs) {
+    let result = parser.program();
+    report_warnings(parser.warnings());
+    if let Err(diagnostics) = result {
+        let source_file = source.source_file.as_ref().unwrap();
+        for diagnostic in &diagnostics {
+            let error = &diagnostic.error;
+            match (error.location(), diagnostic.line) {
+                (Some((line, column)), _) => eprintln!("Error: {}:{}:{}: {}", source_file, line, column, error),
+                (None, Some(line)) => eprintln!("Error: {}:{}: {}", source_file, line, error),
+                (None, None) => eprintln!("Error: {}: {}", source_file, error)
+            }
+        }
+        if parser.error_limit_reached() {
+            eprintln!("Note: Stopped after {} error(s). Raise --max-errors to see the rest", diagnostics.len());
+        }
+        process::exit(1);
+    }
+}
+
+fn report_warnings(warnings: &[String]) {
+    for warning in warnings {
+        eprintln!("Warning: {}", warning);
+    }
+}
+
+/// Rejects combinations of flags that don't make sense together, all at once, and notes the redundant ones
+fn validate_flags(matches: &ArgMatches, implied: &[&'static str]) {
+    let command = BuildArgs::augment_args(clap::Command::new("build"));
+    let given = command.get_arguments()
+                       .filter(|arg| !["help", "version"].contains(&arg.get_id()))
+                       .filter(|arg| matches.value_source(arg.get_id()) == Some(ValueSource::CommandLine))
+                       .filter_map(|arg| arg.get_long())
+                       .chain(implied.iter().copied())
+                       .collect::<Vec<&str>>();
+
+    let findings = validation::validate(&given);
+    for note in &findings.notes {
+        eprintln!("Note: {}", note);
+    }
+    if !findings.conflicts.is_empty() {
+        let message = format!("Conflicting options:\n    {}", findings.conflicts.join("\n    "));
+        Cli::command().error(ErrorKind::ArgumentConflict, message).exit();
+    }
+}
+
+fn build(args: &BuildArgs) {
+    let output_file = args.output_file();
+    let source_code = read_source(&args.source);
+
+    let options = options::CompilerOptions {
+        tag_order: args.sort_tags,
+        legacy_tag_declarations: args.legacy_tag_declarations,
+        index_bounds: args.index_bounds,
+        ..args.compile.options()
+    };
+
+    // Prefer the name the program declares for itself over the file name
+    let source_file = args.source.source_file.as_ref().unwrap();
+    let file_stem = Path::new(source_file).file_stem().unwrap_or_default().to_string_lossy().to_string();
+    let name_program = |declaration: Option<&(String, String)>| match declaration {
+        Some((name, version)) => (name.clone(), version.clone()),
+        None => (file_stem.clone(), String::new())
+    };
+
+    let clock = clock::build_clock(args.reproducible);
+    let template = args.header_template.as_ref().map(|header_template| {
+        fs::read_to_string(header_template).unwrap_or_else(|error| {
+            eprintln!("Error: {}: {}", header_template, error);
+            process::exit(1);
+        })
+    });
+
+    // The header is part of the output, so whatever it's rendered from is part of the key
+    let cache = match &args.cache_dir {
+        Some(_) if args.check => None,
+        Some(_) if template.is_some() && !args.reproducible && env::var("SOURCE_DATE_EPOCH").is_err() => {
+            eprintln!("Note: the header template may write the time of the build, so the output isn't cached. \
+                       Pass --reproducible to cache it");
+            None
+        },
+        Some(cache_dir) => {
+            let header = template.as_ref().map(|template| {
+                format!("{}\0{}\0{:?}", file_stem, template, clock.as_ref().map(|clock| clock.now()))
+            }).unwrap_or_default();
+            Some((cache::Cache::new(cache_dir, args.cache_mode.unwrap_or_default()), cache::key(&source_code, &options, &header)))
+        },
+        None => None
+    };
+
+    if !args.check {
+        let entry_directory = cache.as_ref().and_then(|(cache, key)| cache.entry_to_write(key));
+        let mut targets = vec![plan::Target::Output(&output_file)];
+        targets.extend(args.stats_history.as_deref().map(plan::Target::StatsHistory));
+        targets.extend(entry_directory.as_deref().map(plan::Target::CacheEntry));
+
+        let plan = plan::Plan::new(&targets);
+        if args.plan {
+            println!("{}", plan.to_json());
+        }
+        for error in plan.errors() {
+            eprintln!("Error: {}", error);
+        }
+        if !plan.errors().is_empty() {
+            process::exit(1);
+        }
+        if args.plan {
+            return;
+        }
+    }
+
+    let cached = cache.as_ref().and_then(|(cache, key)| match cache.read(key) {
+        cache::Lookup::Hit(entry) => Some(*entry),
+        cache::Lookup::Miss => None,
+        cache::Lookup::Damaged(reason) => {
+            eprintln!("Note: cache entry {} is ignored because {}", key, reason);
+            None
+        }
+    });
+
+    let mut parser = None;
+    let entry = match cached {
+        Some(entry) => {
+            eprintln!("Note: {} is taken from cache entry {}", output_file, entry.diagnostics.key);
+
+            // Warnings are reported again, as if the program had been compiled
+            report_warnings(&entry.diagnostics.warnings);
+            entry
+        },
+        None => {
+            let lexer = lexer::Lexer::new(source_code.clone());
+            let compiled = parser.insert(parse::Parser::new(lexer, emitter::Emitter::new(&output_file), options.clone()));
+
+            compile(compiled, &args.source);
+            if args.check {
+                return;
+            }
+
+            // Record which policy was enforced so auditors can verify it
+            if let Some(policy) = &options.policy {
+                compiled.add_header(&format!("# Enforced policy {:016x}\n", policy.hash()));
+            }
+
+            if let (Some(template), Some(header_template)) = (&template, &args.header_template) {
+                let (program, program_version) = name_program(compiled.program_declaration());
+                let values = header::HeaderValues::new(&program, &program_version, &source_code, clock.as_deref());
+                match header::render(template, &values) {
+                    Ok(header) => compiled.add_header(&header),
+                    Err(error) => {
+                        eprintln!("Error: {}: {}", header_template, error);
+                        process::exit(1);
+                    }
+                }
+            }
+
+            let key = cache.as_ref().map(|(_, key)| key.as_str()).unwrap_or_default();
+            cache::Entry::new(key, compiled.output(), compiled.warnings().to_vec(), compiled.program_declaration().cloned(),
+                              compiled.metrics(), compiled.routine_sizes().to_vec())
+        }
+    };
+
+    let (program, program_version) = name_program(entry.diagnostics.program.as_ref());
+    let metrics = entry.diagnostics.metrics.clone();
+    if let Some(stats_budget) = &args.stats_budget {
+        let history = args.stats_history.as_deref().map(|path| stats::read_history(path).unwrap_or_else(|error| {
+            eprintln!("Error: {}: {}", path, error);
+            process::exit(1);
+        })).unwrap_or_default();
+        let budget = stats::Budget::load(stats_budget).unwrap_or_else(|error| {
+            eprintln!("Error: {}: {}", stats_budget, error);
+            process::exit(1);
+        });
+        let previous = stats::previous_release(&history, &program, &program_version);
+        let exceedances = budget.check(&metrics, previous);
+        report_warnings(&exceedances.warnings);
+        for error in &exceedances.errors {
+            eprintln!("Error: {}: {}", source_file, error);
+        }
+        if !exceedances.errors.is_empty() {
+            process::exit(1);
+        }
+        if options.deny_warnings && !exceedances.warnings.is_empty() {
+            eprintln!("Error: {}: {}", source_file, error::ParseError::WarningsDenied(exceedances.warnings.len()));
+            process::exit(1);
+        }
+    }
+
+    match &parser {
+        Some(parser) => parser.write_output(),
+        None => emitter::write_output(Path::new(&output_file), &entry.output)
+    }
+
+    if let (Some((cache, _)), Some(_)) = (&cache, &parser) {
+        if let Err(error) = cache.write(&entry) {
+            eprintln!("Note: the output couldn't be stored in the cache: {}", error);
+        }
+    }
+
+    if let Some(stats_history) = &args.stats_history {
+        // Reproducible builds are told apart by their source rather than by when they ran
+        let timestamp = clock.as_ref().map(|clock| clock::timestamp(clock.now()));
+        let source_hash = args.reproducible.then(|| format!("{:016x}", header::hash(&source_code)));
+        let schema = schema::SchemaVersion::of(schema::Artifact::StatsHistory);
+        stats::append_history(stats_history, &stats::HistoryEntry { schema, timestamp, source_hash, program, program_version, metrics });
+    }
+
+    if args.stats {
+        let mut stats = String::from("Generated size per routine:\n");
+        for (routine, size) in &entry.diagnostics.routine_sizes {
+            stats += &format!("    {}: {} bytes\n", routine, size);
+        }
+
+        // Keep the compiled code on stdout free of anything else
+        if output_file == emitter::STDOUT_PATH {
+            eprint!("{}", stats);
+        } else {
+            print!("{}", stats);
+        }
+    }
+}
+
+fn xref(args: &XrefArgs) {
+    let lexer = lexer::Lexer::new(read_source(&args.source));
+    let emitter = emitter::Emitter::new("");
+    let mut parser = parse::Parser::new(lexer, emitter, args.compile.options());
+
+    compile(&mut parser, &args.source);
+
+    if let Some(tag) = &args.impact {
+        let impact = parser.cross_reference().impact(tag, args.impact_depth);
+        match args.impact_format {
+            ImpactFormat::Text => print!("{}", impact.to_text()),
+            ImpactFormat::Dot => println!("{}", impact.to_dot())
+        }
+    }
 
-    /// Name of the output file
-    #[clap(short, long, default_value="Program.out")]
-    out: String
+    if let Some(tag) = &args.last_writer {
+        match parser.cross_reference().last_writer(parser.call_graph(), tag) {
+            Ok(last_writer) => match args.last_writer_format {
+                LastWriterFormat::Text => print!("{}", last_writer.to_text()),
+                LastWriterFormat::Json => println!("{}", last_writer.to_json())
+            },
+            Err(message) => {
+                eprintln!("Error: {}", message);
+                process::exit(1);
+            }
+        }
+    }
 }
 
 fn main() {
-    let args = Args::parse();
+    let matches = Cli::command().get_matches();
+    let cli = Cli::from_arg_matches(&matches).unwrap_or_else(|error| error.exit());
 
-    let source_code = fs::read_to_string(args.source_file)
-                                .expect("File doesn't exist");
-    
-    let lexer = lexer::Lexer::new(source_code);
-    let emitter = emitter::Emitter::new(&args.out);
-    let mut parser = parse::Parser::new(lexer, emitter);
+    if cli.about_json {
+        println!("{}", about::About::new().to_json());
+        return;
+    }
 
-    parser.program();
+    match cli.command {
+        Some(Command::Build(args)) => {
+            validate_flags(matches.subcommand_matches("build").unwrap(), &[]);
+            build(&args)
+        },
+        Some(Command::Check(mut args)) => {
+            validate_flags(matches.subcommand_matches("check").unwrap(), &["check"]);
+            args.check = true;
+            build(&args)
+        },
+        Some(Command::Xref(args)) => xref(&args),
+        Some(Command::Completions { shell }) => {
+            let mut command = Cli::command();
+            let name = command.get_name().to_string();
+            clap_complete::generate(shell, &mut command, name, &mut io::stdout());
+        },
+        Some(Command::Docs { format: DocsFormat::Markdown, out }) => {
+            let reference = docs::markdown();
+            match out {
+                Some(out) => fs::write(out, reference).expect("Unable to write file"),
+                None => print!("{}", reference)
+            }
+        },
+        Some(Command::Schema { artifact }) => print!("{}", artifact.schema()),
+        None => {
+            validate_flags(&matches, &[]);
+            build(&cli.build)
+        }
+    }
 }
@@ -0,0 +1,73 @@
+use crate::policy::Policy;
+
+/// Largest size of the generated output in bytes unless configured otherwise
+pub const DEFAULT_MAX_OUTPUT_SIZE: usize = 64 * 1024 * 1024;
+
+/// Number of errors after which a compile stops unless configured otherwise
+pub const DEFAULT_MAX_ERRORS: usize = 20;
+
+/// Longest tag name in characters unless configured otherwise
+pub const DEFAULT_TAG_NAME_LIMIT: usize = 7;
+
+/// Longest name of anything other than a tag in characters unless configured otherwise
+pub const DEFAULT_NAME_LIMIT: usize = 64;
+
+/// Order of the declarations in the TAG section of the output
+#[derive(Debug, Clone, Copy, Default, PartialEq, clap::ValueEnum)]
+pub enum TagOrder {
+    /// The order they're declared in
+    #[default]
+    Source,
+    /// By name
+    Name,
+    /// Single tags before tag arrays, each by name
+    TypeThenName
+}
+
+/// What the generated code does with an index held in an INT tag that is out of the bounds of its array
+#[derive(Debug, Clone, Copy, Default, PartialEq, clap::ValueEnum)]
+pub enum IndexBounds {
+    /// Raise an IndexError
+    #[default]
+    Raise,
+    /// Use the nearest element
+    Clamp
+}
+
+/// Settings that influence how a program is validated and compiled
+#[derive(Default, Clone)]
+pub struct CompilerOptions {
+    /// Base tick of the runtime scheduler in milliseconds. When set, task
+    /// periods are checked against it
+    pub scheduler_tick: Option<u32>,
+
+    /// Fail the compilation if any warnings were reported
+    pub deny_warnings: bool,
+
+    /// Require the program to start with a PROGRAM statement
+    pub strict: bool,
+
+    /// Largest allowed size of the generated output in bytes
+    pub max_output_size: Option<usize>,
+
+    /// Number of errors after which parsing stops. Every error is reported when unset
+    pub max_errors: Option<usize>,
+
+    /// Longest allowed tag name in characters
+    pub tag_name_limit: Option<usize>,
+
+    /// Longest allowed name of a program, task, routine, event, rung or label in characters
+    pub name_limit: Option<usize>,
+
+    /// Restrictions checked once the program has otherwise been validated
+    pub policy: Option<Policy>,
+
+    /// Order of the declarations in the TAG section of the output
+    pub tag_order: TagOrder,
+
+    /// Declare tags with TAG lines instead of Python assignments
+    pub legacy_tag_declarations: bool,
+
+    /// How arrays indexed by a tag are kept within their bounds
+    pub index_bounds: IndexBounds
+}
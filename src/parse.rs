@@ -1,23 +1,122 @@
-use crate::{lexer::{Lexer, Token, TokenType}, emitter::Emitter, code_generation::CodeGenerator};
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+
+use crate::{lexer::{Lexer, Token, TokenType}, emitter::Emitter, code_generation::{self, CodeGenerator}, options::CompilerOptions,
+            xref::CrossReference, call_graph::CallGraph, identifiers::{self, IdentifierKind},
+            instructions::{self, InstructionClass, OperandKind}, policy::Policy, error::{Diagnostic, ParseError}, stats::Metrics};
+
+/// Shortest period a task may have in milliseconds
+pub const PERIOD_LOWER_BOUND: u32 = 20;
+
+/// An XIC or XIO of a tag
+type Contact = (TokenType, String);
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum TagKind {
+    Bool,
+    /// Array of bools of the given length
+    BoolArray(usize),
+    Timer,
+    Counter,
+    Int
+}
+
+impl TagKind {
+    /// How a tag of the kind is declared
+    fn keyword(&self) -> String {
+        match self {
+            TagKind::Bool => "TAG".to_string(),
+            TagKind::BoolArray(length) => format!("TAG[{}]", length),
+            TagKind::Timer => "TIMER".to_string(),
+            TagKind::Counter => "COUNTER".to_string(),
+            TagKind::Int => "INT".to_string()
+        }
+    }
+}
 
-#[derive(Clone)]
 struct TagDescriptor {
+    kind: TagKind,
+    /// Line of the declaration
+    line: usize
+}
+
+/// A TASK, ROUTINE or RUNG that has been opened but not closed yet
+struct Structure {
+    kind: TokenType,
     name: String,
-    length: usize
+    line: usize
+}
+
+impl Structure {
+    fn closing_keyword(&self) -> &'static str {
+        match self.kind {
+            TokenType::Task => "ENDTASK",
+            TokenType::Routine => "ENDROUTINE",
+            TokenType::EventSpace => "ENDEVENTSPACE",
+            _ => "ENDRUNG"
+        }
+    }
+}
+
+impl fmt::Display for Structure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // Rungs don't have to be named
+        if self.name.is_empty() {
+            write!(f, "{:?} on line {}", self.kind, self.line)
+        } else {
+            write!(f, "{:?} {} on line {}", self.kind, self.name, self.line)
+        }
+    }
 }
 
 pub struct Parser<'a> {
     lexer: Lexer,
     emitter: Emitter<'a>,
     code_generator: CodeGenerator,
-
-    tags: Vec<TagDescriptor>,
-    routines: Vec<String>,
-    jumps: Vec<String>,
-    events: Vec<String>,
-    emitted_events: Vec<String>,
-    stack: Vec<TokenType>,
+    cross_reference: CrossReference,
+    call_graph: CallGraph,
+    options: CompilerOptions,
+
+    // Symbols are looked up by name. References are kept in order so errors are reported in source order
+    tags: HashMap<String, TagDescriptor>,
+    /// Tasks defining each routine, with the line of the definition
+    routines: HashMap<String, Vec<(String, usize)>>,
+    /// Task, target and line of each JSR
+    jumps: Vec<(String, String, usize)>,
+    events: HashMap<String, usize>,
+    emitted_events: Vec<(String, usize)>,
+    stack: Vec<Structure>,
+    /// Contacts on the series path being parsed, which are all true together whenever the path is
+    rung_contacts: Vec<Contact>,
+    rung_temps: HashSet<String>,
+    temps: HashSet<String>,
+    ons_storage: HashMap<String, usize>,
+    rung_has_output: bool,
+    /// Lines of the BSTs of the current rung that haven't been closed yet
+    rung_branches: Vec<usize>,
+    /// For each open BST, the contacts of the path before it and those common to each of its finished legs
+    branch_contacts: Vec<(Vec<Contact>, Option<Vec<Contact>>)>,
+    rung_empty: bool,
+    /// Line of each label of the current routine
+    labels: HashMap<String, usize>,
+    /// Labels the JMPs of the current routine target, with the line of the JMP
+    label_jumps: Vec<(String, usize)>,
     main_flag: bool,
+    entry_routines: Vec<String>,
+    task_routines: HashMap<String, usize>,
+    task_name: String,
+    warnings: Vec<String>,
+    /// Errors of the statement being parsed besides the one it stopped at
+    statement_errors: Vec<Diagnostic>,
+    policy_violations: Vec<String>,
+    complete: bool,
+    /// Cleared once an error is found. The output of a program with errors is never written, and the statement
+    /// that failed may have left a structure half open, so no more code is generated for it
+    generating: bool,
+    error_limit_reached: bool,
+    statement_count: usize,
+    program_declaration: Option<(String, String)>,
+    metrics: Metrics,
 
     previous_token: Token,
     current_token: Token,
@@ -25,23 +124,50 @@ pub struct Parser<'a> {
 }
 
 impl<'a> Parser<'a> {
-    pub fn new(lexer: Lexer, emitter: Emitter<'a>) -> Parser<'a> {
+    pub fn new(lexer: Lexer, emitter: Emitter<'a>, options: CompilerOptions) -> Parser<'a> {
         let mut parser = Parser {
             lexer,
             emitter,
             code_generator: CodeGenerator::new(),
-            tags: Vec::new(),
-            routines: Vec::new(),
+            cross_reference: CrossReference::new(),
+            call_graph: CallGraph::new(),
+            options,
+            tags: HashMap::new(),
+            routines: HashMap::new(),
             jumps: Vec::new(),
-            events: Vec::new(),
+            events: HashMap::new(),
             emitted_events: Vec::new(),
             stack: Vec::new(),
+            rung_contacts: Vec::new(),
+            rung_temps: HashSet::new(),
+            temps: HashSet::new(),
+            ons_storage: HashMap::new(),
+            rung_has_output: false,
+            rung_branches: Vec::new(),
+            branch_contacts: Vec::new(),
+            rung_empty: false,
+            labels: HashMap::new(),
+            label_jumps: Vec::new(),
             main_flag: false,
+            entry_routines: Vec::new(),
+            task_routines: HashMap::new(),
+            task_name: String::new(),
+            warnings: Vec::new(),
+            statement_errors: Vec::new(),
+            policy_violations: Vec::new(),
+            complete: false,
+            generating: true,
+            error_limit_reached: false,
+            statement_count: 0,
+            program_declaration: None,
+            metrics: Metrics::default(),
             previous_token: Token::default(),
             current_token: Token::default(),
             peek_token: Token::default()
         };
 
+        parser.code_generator.set_index_bounds(parser.options.index_bounds);
+
         // Call next token twice to initialize current and peek
         parser.next_token();
         parser.next_token();
@@ -52,477 +178,2574 @@ impl<'a> Parser<'a> {
         token_type == *(self.current_token.get_type())
     }
 
-    fn match_token(&mut self, token_type: TokenType) {
+    fn match_token(&mut self, token_type: TokenType) -> Result<(), ParseError> {
         if !self.check_token(token_type) {
-            panic!("Expected {:?}, but found {:?}", token_type, self.current_token);
+            return Err(ParseError::UnexpectedToken { expected: token_type, found: self.current_token.clone() });
         }
         self.next_token();
+        Ok(())
+    }
+
+    /// Checks the name that was just matched, stopping at the first rule it breaks and keeping the rest to report along with it
+    fn validate_identifier(&mut self, kind: IdentifierKind) -> Result<(), ParseError> {
+        let mut diagnostics = identifiers::validate(kind, &self.previous_token, &self.options).into_iter();
+        match diagnostics.next() {
+            Some(first) => {
+                self.statement_errors.extend(diagnostics);
+                Err(first.error)
+            },
+            None => Ok(())
+        }
     }
 
     fn next_token(&mut self) {
-        self.previous_token = self.current_token.clone();
-        self.current_token = self.peek_token.clone();
-        self.peek_token = self.lexer.get_token();
+        self.previous_token = std::mem::replace(&mut self.current_token, std::mem::replace(&mut self.peek_token, self.lexer.get_token()));
     }
 
-    pub fn program(&mut self) {
+    /// Parses the whole program, returning every error found by line. After an error in a statement,
+    /// parsing carries on with the next line so that one compile reports as many errors as it can
+    pub fn program(&mut self) -> Result<(), Vec<Diagnostic>> {
+        let max_errors = self.options.max_errors.unwrap_or(usize::MAX);
+        let mut errors = Vec::new();
+
         // Parse all of the statements
         while !self.check_token(TokenType::Eof) {
-            self.statement();
+            if errors.len() >= max_errors {
+                errors.truncate(max_errors);
+                self.error_limit_reached = true;
+                return Err(errors);
+            }
+
+            let line = self.current_token.get_line();
+            match self.statement() {
+                Ok(()) => (),
+                // Every statement after this one would only grow the output further
+                Err(error @ ParseError::OutputTooLarge { .. }) => {
+                    errors.push(Diagnostic::new(error, Some(line)));
+                    return Err(errors);
+                },
+                Err(error) => {
+                    // Text the lexer couldn't read explains the error better than what was expected in its place
+                    let error = match self.current_token.get_type() {
+                        TokenType::Invalid => ParseError::InvalidToken(self.current_token.clone()),
+                        _ => error
+                    };
+                    errors.push(Diagnostic::new(error, Some(line)));
+                    errors.append(&mut self.statement_errors);
+                    self.generating = false;
+                    self.skip_line();
+                }
+            }
+        }
+
+        if self.options.strict && self.program_declaration.is_none() {
+            errors.push(Diagnostic::new(ParseError::InvalidProgram("A PROGRAM statement is required in strict mode".to_string()), None));
+        }
+
+        // Every structure must be closed before the end of the file
+        for structure in &self.stack {
+            let error = ParseError::StructuralMismatch(format!("Unexpected end of file. {} is not closed", structure));
+            errors.push(Diagnostic::new(error, Some(structure.line)));
         }
 
         // Check that all emitted events correspond to actual events
-        for event in &self.emitted_events {
-            if !self.events.contains(event) {
-                panic!("Emitted event {} does not correspond to a task", event);
+        let mut undefined_events = Vec::new();
+        for (event, line) in self.emitted_events.iter().filter(|(event, _)| !self.events.contains_key(event)) {
+            if !undefined_events.contains(event) {
+                undefined_events.push(event.clone());
+                errors.push(Diagnostic::new(ParseError::UndefinedEvent(event.clone()), Some(*line)));
             }
         }
 
+        // A namespaced event reads the same as an unqualified one once the namespace is left off
+        let mut collisions = self.events.iter().filter_map(|(event, &line)| {
+            let (_, name) = event.split_once('.')?;
+            self.events.contains_key(name).then(|| (line, event.clone(), name.to_string()))
+        }).collect::<Vec<(usize, String, String)>>();
+        collisions.sort();
+        for (line, qualified, unqualified) in collisions {
+            errors.push(Diagnostic::new(ParseError::EventCollision { qualified, unqualified }, Some(line)));
+        }
+
         // Check that all JSR instructions jump to valid routines
-        for jump in &self.jumps {
-            if !self.routines.contains(jump) {
-                panic!("Routine {} does not exist", jump);
+        let mut undefined_routines = Vec::new();
+        for (_, routine, line) in self.jumps.iter().filter(|(_, routine, _)| !self.routines.contains_key(routine)) {
+            if !undefined_routines.contains(routine) {
+                undefined_routines.push(routine.clone());
+                errors.push(Diagnostic::new(ParseError::UndefinedRoutine(routine.clone()), Some(*line)));
+            }
+        }
+
+        // A routine run by several tasks could be interleaved with itself by the scheduler
+        for (routine, tasks) in self.call_graph.shared_routines() {
+            // Point at its definition in the first task that runs it
+            let line = self.routines.get(&routine).and_then(|definitions| {
+                definitions.iter().find(|(task, _)| *task == tasks[0]).or(definitions.first()).map(|&(_, line)| line)
+            });
+            errors.push(Diagnostic::new(ParseError::SharedRoutine { routine, tasks }, line));
+        }
+
+        // Routines are generated as functions of their own task, so a JSR can't reach into another task
+        let mut cross_task_jumps = Vec::new();
+        for (task, routine, line) in &self.jumps {
+            let definitions = match self.routines.get(routine) {
+                Some(definitions) => definitions,
+                None => continue
+            };
+            if !definitions.iter().any(|(defined_in, _)| defined_in == task) && !cross_task_jumps.contains(&(task, routine)) {
+                cross_task_jumps.push((task, routine));
+                let error = ParseError::CrossTaskJump { routine: routine.clone(), task: task.clone(), defined_in: definitions[0].0.clone() };
+                errors.push(Diagnostic::new(error, Some(*line)));
+            }
+        }
+
+        if !errors.is_empty() {
+            // Errors of the program as a whole have no line, so they go last
+            errors.sort_by_key(|diagnostic| (diagnostic.line.is_none(), diagnostic.line));
+            if errors.len() > max_errors {
+                errors.truncate(max_errors);
+                self.error_limit_reached = true;
+            }
+            // Warnings of a program that doesn't compile would only bury its errors
+            self.warnings.clear();
+            return Err(errors);
+        }
+
+        // Fail if the policy is broken or warnings have been promoted to errors, reporting both
+        if let Err(error) = self.check_policy() {
+            errors.push(Diagnostic::new(error, None));
+        }
+        if self.options.deny_warnings && !self.warnings.is_empty() {
+            errors.push(Diagnostic::new(ParseError::WarningsDenied(self.warnings.len()), None));
+        }
+        if !errors.is_empty() {
+            return Err(errors);
+        }
+
+        self.emitter.sort_tags(self.options.tag_order);
+        self.complete = true;
+        Ok(())
+    }
+
+    /// Whether parsing stopped at --max-errors before the end of the program
+    pub fn error_limit_reached(&self) -> bool {
+        self.error_limit_reached
+    }
+
+    fn check_policy(&mut self) -> Result<(), ParseError> {
+        let policy = match &self.options.policy {
+            Some(policy) => policy,
+            None => return Ok(())
+        };
+
+        for (task, depth) in self.call_graph.jsr_depths() {
+            if let Some(violation) = policy.check_jsr_depth(&task, depth) {
+                self.policy_violations.push(violation);
             }
         }
 
+        if !self.policy_violations.is_empty() {
+            return Err(ParseError::PolicyViolations(self.policy_violations.clone()));
+        }
+        Ok(())
+    }
+
+    /// Records a policy violation found while parsing, to be reported once the program is otherwise valid
+    fn check_policy_rule(&mut self, check: impl Fn(&Policy, usize) -> Option<String>) {
+        let line = self.previous_token.get_line();
+        if let Some(violation) = self.options.policy.as_ref().and_then(|policy| check(policy, line)) {
+            self.policy_violations.push(violation);
+        }
+    }
+
+    pub fn write_output(&self) {
+        // The emitted code is only staged until the whole program has been validated
+        if !self.complete {
+            panic!("Output can't be written before the program compiles successfully");
+        }
         self.emitter.write_file();
     }
 
-    fn statement(&mut self) {
+    /// The compiled code as it will be written
+    pub fn output(&self) -> String {
+        self.emitter.contents()
+    }
+
+    /// Warnings to report along with the result of `program()`
+    pub fn warnings(&self) -> &[String] {
+        &self.warnings
+    }
+
+    pub fn add_header(&mut self, header: &str) {
+        self.emitter.add_header(header);
+    }
+
+    /// Name and version of the program, if it declared them
+    pub fn program_declaration(&self) -> Option<&(String, String)> {
+        self.program_declaration.as_ref()
+    }
+
+    pub fn cross_reference(&self) -> &CrossReference {
+        &self.cross_reference
+    }
+
+    pub fn call_graph(&self) -> &CallGraph {
+        &self.call_graph
+    }
+
+    fn statement(&mut self) -> Result<(), ParseError> {
+        self.statement_count += 1;
+        let statement = self.current_token.clone();
         match self.current_token.get_type() {
+            &TokenType::Program => {
+                self.next_token();
+                self.program_statement()?;
+            },
             &TokenType::Task => {
                 self.next_token();
-                self.task();
+                self.task()?;
             },
             &TokenType::Routine => {
                 self.next_token();
-                self.routine();
+                self.routine()?;
             },
             &TokenType::Rung => {
                 self.next_token();
-                self.rung();
+                self.rung()?;
+            },
+            token_type if instructions::spec(*token_type).is_some() => {
+                self.next_token();
+                self.instruction()?;
+            },
+            &TokenType::Bst | &TokenType::Nxb | &TokenType::Bnd => {
+                self.next_token();
+                self.branch()?;
             },
-            &TokenType::Xic | &TokenType::Xio | &TokenType::Ote |
-            &TokenType::Otl | &TokenType::Otu | &TokenType::Jsr |
-            &TokenType::Ret | &TokenType::Emit => {
+            &TokenType::Temp => {
                 self.next_token();
-                self.instruction();
+                self.temp()?;
             },
             &TokenType::EndRung => {
                 self.next_token();
-                self.end_rung();
+                self.end_rung()?;
             },
             &TokenType::EndRoutine => {
                 self.next_token();
-                self.end_routine();
+                self.end_routine()?;
             },
             &TokenType::EndTask => {
                 self.next_token();
-                self.end_task();
+                self.end_task()?;
             },
             &TokenType::Tag => {
                 self.next_token();
-                self.tag();
+                self.tag()?;
+            },
+            &TokenType::Timer => {
+                self.next_token();
+                self.timer()?;
+            },
+            &TokenType::Counter => {
+                self.next_token();
+                self.counter()?;
+            },
+            &TokenType::Int => {
+                self.next_token();
+                self.int_tag()?;
+            },
+            &TokenType::EventSpace => {
+                self.next_token();
+                self.eventspace()?;
+            },
+            &TokenType::EndEventSpace => {
+                self.next_token();
+                self.end_eventspace()?;
             },
             _ => {
-                panic!("Invalid statement at {} ({:?})", self.current_token.get_text(), self.current_token.get_type());
+                return Err(ParseError::InvalidStatement(self.current_token.clone()));
             }
         }
 
         // All statements end in nl
-        self.new_line()
+        if !self.check_token(TokenType::NewLine) {
+            return Err(self.trailing_tokens(&statement));
+        }
+        self.new_line()?;
+
+        self.check_output_size()
     }
 
-    fn task(&mut self) {
-        // Verify we are at the outter most level
-        if !self.stack.is_empty() {
-            panic!("Tasks may not be inside of other structures");
+    /// Describes what a statement allows when something follows it on its line
+    fn trailing_tokens(&self, statement: &Token) -> ParseError {
+        let found = self.current_token.clone();
+        let keyword = statement.get_text();
+        let message = match statement.get_type() {
+            token_type if instructions::spec(*token_type).is_some() => {
+                let operands = instructions::spec(*token_type).unwrap().operands;
+                let kinds = operands.iter().map(|operand| operand.name()).collect::<Vec<&str>>().join(", ");
+                let arity = match operands.len() {
+                    0 => "no operands".to_string(),
+                    1 => format!("exactly one operand ({})", kinds),
+                    count => format!("exactly {} operands ({})", count, kinds)
+                };
+                format!("Unexpected extra operand '{}'. {} takes {}", found.get_text(), keyword, arity)
+            },
+            TokenType::Tag => format!("Unexpected '{}' after the initial value. TAG takes a single initial value, e.g. TAG motor = FALSE",
+                                      found.get_text()),
+            TokenType::Rung => format!("Unexpected '{}' after the rung name. A rung takes at most one name", found.get_text()),
+            _ => format!("Unexpected '{}' at the end of the {} statement", found.get_text(), keyword)
+        };
+        ParseError::TrailingTokens { found, message }
+    }
+
+    fn check_output_size(&self) -> Result<(), ParseError> {
+        let max_output_size = match self.options.max_output_size {
+            Some(max_output_size) => max_output_size,
+            None => return Ok(())
+        };
+
+        // Fail as soon as the budget is exceeded rather than after generating everything
+        let output_size = self.emitter.size() + self.code_generator.code_block_size();
+        if output_size > max_output_size {
+            let mut routine_sizes = self.code_generator.routine_sizes().to_vec();
+            routine_sizes.sort_by_key(|(_, size)| std::cmp::Reverse(*size));
+            routine_sizes.truncate(5);
+            return Err(ParseError::OutputTooLarge { budget: max_output_size, largest_routines: routine_sizes });
+        }
+        Ok(())
+    }
+
+    pub fn routine_sizes(&self) -> &[(String, usize)] {
+        self.code_generator.routine_sizes()
+    }
+
+    /// Counts of what the program contains, along with the size of the output including any headers
+    pub fn metrics(&self) -> Metrics {
+        Metrics { generated_bytes: self.emitter.size(), ..self.metrics.clone() }
+    }
+
+    fn program_statement(&mut self) -> Result<(), ParseError> {
+        if self.program_declaration.is_some() {
+            return Err(ParseError::InvalidProgram("There can only be one PROGRAM statement".to_string()));
+        }
+        if self.statement_count != 1 {
+            return Err(ParseError::InvalidProgram("PROGRAM must be the first statement".to_string()));
+        }
+
+        self.match_token(TokenType::Identifier)?;
+        self.validate_identifier(IdentifierKind::Program)?;
+        let name = self.previous_token.get_text().to_string();
+
+        self.match_token(TokenType::Version)?;
+        let version = self.version_number()?;
+
+        self.emitter.emit_line(&format!("PROGRAM {} VERSION {}", name, version));
+        self.program_declaration = Some((name, version));
+        Ok(())
+    }
+
+    fn version_number(&mut self) -> Result<String, ParseError> {
+        // The lexer reads 2.4 as one number, but 2.4.1 arrives as a number, an indexer and
+        // another number, so put the pieces back together
+        self.match_token(TokenType::Number)?;
+        let mut version = self.previous_token.get_text().to_string();
+        while self.check_token(TokenType::Indexer) && self.current_token.get_span().start == self.previous_token.get_span().end {
+            self.next_token();
+            if self.current_token.get_span().start != self.previous_token.get_span().end {
+                return Err(ParseError::InvalidProgram("Version number can't contain spaces".to_string()));
+            }
+            self.match_token(TokenType::Number)?;
+            version += ".";
+            version += self.previous_token.get_text();
+        }
+        Ok(version)
+    }
+
+    fn task(&mut self) -> Result<(), ParseError> {
+        // Verify we are at the outter most level, where an EVENTSPACE block may be open
+        if self.stack.iter().any(|structure| structure.kind != TokenType::EventSpace) {
+            return Err(ParseError::StructuralMismatch("Tasks may not be inside of other structures".to_string()));
         } else {
-            self.stack.push(*self.previous_token.get_type());
+            self.open_structure();
         }
         self.emitter.emit("TASK ");
 
-        self.task_type();
-        self.match_token(TokenType::Identifier);
+        // Catch the name being written before the task type
+        if self.check_token(TokenType::Identifier) && self.peek_token.get_type() == &TokenType::OpenAngle {
+            return Err(ParseError::InvalidTaskHeader(format!("Task name {} must come after the task type, e.g. TASK<PERIOD=100> {}",
+                                                             self.current_token.get_text(), self.current_token.get_text())));
+        }
+
+        let event = self.task_type()?;
+        self.match_token(TokenType::Identifier)?;
+        self.validate_identifier(IdentifierKind::Task)?;
         self.emitter.emit(" ");
         self.emitter.emit_line(self.previous_token.get_text());
+        self.task_name = self.previous_token.get_text().to_string();
+        self.name_structure();
+        self.cross_reference.start_task(self.previous_token.get_text(), event.as_deref());
+        self.call_graph.start_task(self.previous_token.get_text());
+        if self.generating {
+            self.code_generator.start_task(self.previous_token.get_text());
+        }
         self.emitter.emit_line("{");
+        self.metrics.tasks += 1;
+        Ok(())
     }
 
-    fn task_type(&mut self) {
+    fn task_type(&mut self) -> Result<Option<String>, ParseError> {
         // Require an open bracket
-        self.match_token(TokenType::OpenAngle);
+        self.match_token(TokenType::OpenAngle)?;
 
         // Determine whether it's periodic or event driven
+        let kind = self.current_token.get_text().to_string();
+        self.check_policy_rule(|policy, line| policy.check_task_kind(&kind, line));
+        let mut event = None;
         if self.check_token(TokenType::Period) {
-            self.period_type();
+            self.period_type()?;
         } else if self.check_token(TokenType::Event) {
-            self.event_type();
+            event = Some(self.event_type()?);
         } else if self.check_token(TokenType::Continuous) {
-            self.match_token(TokenType::Continuous);
+            self.match_token(TokenType::Continuous)?;
         } else {
-            panic!("Invalid task type {}", self.current_token.get_text());
+            self.check_header_closed()?;
+            return Err(ParseError::InvalidTaskHeader(format!("Invalid task type {}", self.current_token.get_text())));
         }
 
         // Require a closing bracket
-        self.match_token(TokenType::CloseAngle);
+        self.match_header_token(TokenType::CloseAngle)?;
+        Ok(event)
     }
 
-    fn period_type(&mut self) {
+    fn match_header_token(&mut self, token_type: TokenType) -> Result<(), ParseError> {
+        self.check_header_closed()?;
+        self.match_token(token_type)
+    }
+
+    fn check_header_closed(&self) -> Result<(), ParseError> {
+        // A wrapped line is the usual cause, so say so rather than reporting the NewLine
+        if self.check_token(TokenType::NewLine) || self.check_token(TokenType::Eof) {
+            return Err(ParseError::InvalidTaskHeader("Task header is not closed. Expected '>' before end of line".to_string()));
+        }
+        Ok(())
+    }
+
+    fn period_type(&mut self) -> Result<(), ParseError> {
         // Require the following tokens
-        self.match_token(TokenType::Period);
+        self.match_token(TokenType::Period)?;
         self.emitter.emit("PERIOD ");
-        self.match_token(TokenType::Eq);
-        self.match_token(TokenType::Number);
-        self.emitter.emit(self.previous_token.get_text());
+        self.match_header_token(TokenType::Eq)?;
+        self.match_header_token(TokenType::Number)?;
 
         // Enforce a lower bound on the period
-        const PERIOD_LOWER_BOUND: u32 = 20;
-        if self.previous_token.get_text().parse::<u32>().unwrap() < PERIOD_LOWER_BOUND {
-            panic!("Period below allowable limit {}", PERIOD_LOWER_BOUND);
+        let period = self.previous_token.get_text().parse::<u32>().map_err(|_| {
+            ParseError::InvalidTaskHeader(format!("Period {} must be a whole number of milliseconds", self.previous_token.get_text()))
+        })?;
+        if period < PERIOD_LOWER_BOUND {
+            return Err(ParseError::InvalidTaskHeader(format!("Period below allowable limit {}", PERIOD_LOWER_BOUND)));
+        }
+
+        // The runtime only schedules on multiples of its base tick, so emit
+        // the period it will actually use
+        let mut effective_period = period;
+        if let Some(tick) = self.options.scheduler_tick {
+            effective_period = period.div_ceil(tick).checked_mul(tick).ok_or_else(|| {
+                ParseError::InvalidTaskHeader(format!("Period {} rounded up to a multiple of the scheduler tick {} is too long", period, tick))
+            })?;
+            if effective_period != period {
+                self.warnings.push(format!("Period {} is not a multiple of the scheduler tick {}. The effective period will be {}",
+                                           period, tick, effective_period));
+            }
         }
+        self.emitter.emit(&effective_period.to_string());
+        Ok(())
     }
 
-    fn event_type(&mut self) {
+    fn event_type(&mut self) -> Result<String, ParseError> {
         // Require the following tokens
-        self.match_token(TokenType::Event);
+        self.match_token(TokenType::Event)?;
         self.emitter.emit("EVENT ");
-        self.match_token(TokenType::Eq);
-        self.match_token(TokenType::Identifier);
-        self.emitter.emit(self.previous_token.get_text());
+        self.match_header_token(TokenType::Eq)?;
+        self.match_header_token(TokenType::Identifier)?;
+        let event = self.event_name()?;
+        self.emitter.emit(&event);
 
         // Add the event to the list
-        self.events.push(self.previous_token.get_text().to_string());
+        self.events.insert(event.clone(), self.previous_token.get_line());
+        Ok(event)
     }
 
-    fn routine(&mut self) {
+    /// Reads the rest of an event name whose first word was just matched. A dot directly after
+    /// the word qualifies the name, unlike a tag where it would index an array. Unqualified
+    /// names belong to the enclosing EVENTSPACE block, if there is one
+    fn event_name(&mut self) -> Result<String, ParseError> {
+        let first_end = self.previous_token.get_span().end;
+        let qualified = self.check_token(TokenType::Indexer) && self.current_token.get_span().start == first_end;
+        if !qualified {
+            self.validate_identifier(IdentifierKind::Event)?;
+            let name = self.previous_token.get_text().to_string();
+            return Ok(match self.default_namespace() {
+                Some(namespace) => format!("{}.{}", namespace, name),
+                None => name
+            });
+        }
+
+        self.validate_identifier(IdentifierKind::EventSpace)?;
+        let namespace = self.previous_token.get_text().to_string();
+        self.match_token(TokenType::Indexer)?;
+        let indexer_end = self.previous_token.get_span().end;
+        self.match_token(TokenType::Identifier)?;
+        let event = format!("{}.{}", namespace, self.previous_token.get_text());
+        if self.previous_token.get_span().start != indexer_end {
+            let rule = format!("Qualified event name must be written as a single word, e.g. {}", event);
+            return Err(ParseError::InvalidIdentifier { name: self.previous_token.clone(), rule });
+        }
+        self.validate_identifier(IdentifierKind::Event)?;
+        Ok(event)
+    }
+
+    /// Namespace of the EVENTSPACE block being parsed
+    fn default_namespace(&self) -> Option<&str> {
+        self.stack.iter()
+                  .find(|structure| structure.kind == TokenType::EventSpace)
+                  .map(|structure| structure.name.as_str())
+    }
+
+    fn eventspace(&mut self) -> Result<(), ParseError> {
+        if !self.stack.is_empty() {
+            return Err(ParseError::StructuralMismatch("EVENTSPACE blocks may only be opened outside of other structures".to_string()));
+        }
+        self.open_structure();
+        self.match_token(TokenType::Identifier)?;
+        self.validate_identifier(IdentifierKind::EventSpace)?;
+        self.name_structure();
+        Ok(())
+    }
+
+    fn end_eventspace(&mut self) -> Result<(), ParseError> {
+        match self.stack.pop() {
+            Some(structure) if structure.kind == TokenType::EventSpace => Ok(()),
+            Some(structure) => {
+                Err(ParseError::StructuralMismatch(format!("Missing matching {} for {}", structure.closing_keyword(), structure)))
+            },
+            None => Err(ParseError::StructuralMismatch("Missing matching EVENTSPACE".to_string()))
+        }
+    }
+
+    fn routine(&mut self) -> Result<(), ParseError> {
         // Ensure we are inside of a task
-        if self.stack.last().unwrap_or(&TokenType::Eof) != &TokenType::Task {
-            panic!("Routines must be defined inside of a task");
+        if self.innermost_structure() != Some(TokenType::Task) {
+            return Err(ParseError::StructuralMismatch("Routines must be defined inside of a task".to_string()));
         } else {
-            self.stack.push(*self.previous_token.get_type());
+            self.open_structure();
+        }
+
+        // The ENTRY attribute picks the routine the task starts with in place of Main
+        let entry = self.check_token(TokenType::OpenAngle);
+        if entry {
+            self.match_token(TokenType::OpenAngle)?;
+            self.match_token(TokenType::Entry)?;
+            self.match_token(TokenType::CloseAngle)?;
+        }
+
+        self.match_token(TokenType::Identifier)?;
+        self.validate_identifier(IdentifierKind::Routine)?;
+        self.name_structure();
+
+        // A second definition would silently replace the first in the generated code
+        let line = self.previous_token.get_line();
+        if let Some(&first_line) = self.task_routines.get(self.previous_token.get_text()) {
+            return Err(ParseError::DuplicateRoutine {
+                routine: self.previous_token.get_text().to_string(),
+                task: self.task_name.clone(),
+                first_line,
+                line
+            });
+        }
+        self.task_routines.insert(self.previous_token.get_text().to_string(), line);
+
+        if entry {
+            self.entry_routines.push(self.previous_token.get_text().to_string());
+        }
+        if self.generating {
+            self.code_generator.start_routine(self.previous_token.get_text());
         }
-        self.match_token(TokenType::Identifier);
-        self.code_generator.start_routine(self.previous_token.get_text());
+        self.cross_reference.start_routine(self.previous_token.get_text());
+        self.call_graph.start_routine(self.previous_token.get_text());
 
         // Determine if this is a Main routine or not
         if self.previous_token.get_text() == "Main" {
-            if self.main_flag {
-                panic!("There can only be one Main routine");
-            } else {
-                self.main_flag = true;
-            }
+            self.main_flag = true;
         }
 
         // Add routine to the list
-        self.routines.push(self.previous_token.get_text().to_string());
+        self.routines.entry(self.previous_token.get_text().to_string()).or_default().push((self.task_name.clone(), line));
+        self.metrics.routines += 1;
+        Ok(())
     }
 
-    fn rung(&mut self) {
+    fn rung(&mut self) -> Result<(), ParseError> {
         // Ensure we are inside of a routine
-        if self.stack.last().unwrap_or(&TokenType::Eof) != &TokenType::Routine {
-            panic!("Rungs must be defined inside of a routine");
+        if self.innermost_structure() != Some(TokenType::Routine) {
+            return Err(ParseError::StructuralMismatch("Rungs must be defined inside of a routine".to_string()));
         } else {
-            self.stack.push(*self.previous_token.get_type());
+            self.open_structure();
         }
 
+        let mut rung_name = String::new();
         if self.check_token(TokenType::Identifier) {
             self.next_token();
-            self.code_generator.start_rung(self.previous_token.get_text());
-        } else {
-            self.code_generator.start_rung("");
+            self.validate_identifier(IdentifierKind::Rung)?;
+            self.name_structure();
+            rung_name = self.previous_token.get_text().to_string();
+        }
+        self.rung_contacts.clear();
+        self.rung_temps.clear();
+        self.rung_has_output = false;
+        self.rung_branches.clear();
+        self.branch_contacts.clear();
+        self.rung_empty = true;
+        if self.generating {
+            self.code_generator.start_rung(&rung_name);
         }
+        self.cross_reference.start_rung(&rung_name);
+        self.metrics.rungs += 1;
+        Ok(())
     }
 
-    fn instruction(&mut self) {
-        let instruction_type = self.previous_token.get_type().to_owned();
+    fn instruction(&mut self) -> Result<(), ParseError> {
+        let spec = instructions::spec(*self.previous_token.get_type()).unwrap();
 
-        if instruction_type == TokenType::Ret {
-            self.code_generator.add_instruction(instruction_type, "");
-            return;
+        // Instructions act on the rung condition, so there has to be a rung to act on
+        if self.innermost_structure() != Some(TokenType::Rung) {
+            let found_in = match self.stack.last() {
+                Some(structure) => format!("directly inside {}", structure),
+                None => "at file scope".to_string()
+            };
+            return Err(ParseError::InstructionOutsideRung { instruction: self.previous_token.clone(), found_in });
+        }
+        self.check_policy_rule(|policy, line| policy.check_instruction(spec.keyword, line));
+        *self.metrics.instructions.entry(spec.keyword.to_string()).or_insert(0) += 1;
+
+        // A label marks where its rung starts, so nothing may come before it
+        if spec.instruction == TokenType::Lbl && !self.rung_empty {
+            return Err(ParseError::StructuralMismatch("LBL must be the first instruction of its rung".to_string()));
+        }
+        self.rung_empty = false;
+
+        // The rung condition has to be complete before anything acts on it
+        match spec.class {
+            InstructionClass::Input if self.rung_has_output => return Err(ParseError::InputAfterOutput(spec.instruction)),
+            InstructionClass::Input => (),
+            InstructionClass::Output if !self.rung_branches.is_empty() => {
+                return Err(ParseError::StructuralMismatch(format!("Output instruction {} can't be inside of a branch. Close the branch with BND first",
+                                                                  spec.keyword)));
+            },
+            InstructionClass::Output => self.rung_has_output = true
+        }
+
+        let mut targets = Vec::new();
+        for operand in spec.operands {
+            if *operand == OperandKind::IntValue && self.check_token(TokenType::Number) {
+                self.match_token(TokenType::Number)?;
+                targets.push(self.int_literal()?);
+                continue;
+            }
+            self.match_token(TokenType::Identifier)?;
+            targets.push(self.operand(*operand)?);
+        }
+        // Instructions with more than one operand write to the last
+        let target = targets.last().cloned().unwrap_or_default();
+
+        // The done bit belongs to the timer or counter, which is the only thing that may change it
+        if spec.writes_tag() && target.ends_with(".DN") {
+            return Err(ParseError::InvalidTag(format!("{} is set by its timer or counter and can't be written by {}", target, spec.keyword)));
+        }
+
+        // Labels are checked once the whole routine is known, since a JMP may go forward
+        let line = self.previous_token.get_line();
+        match spec.instruction {
+            TokenType::Lbl => {
+                if let Some(&first_line) = self.labels.get(&target) {
+                    let routine = self.stack.iter().rev().find(|structure| structure.kind == TokenType::Routine).unwrap();
+                    return Err(ParseError::DuplicateLabel { label: target, routine: routine.name.clone(), first_line, line });
+                }
+                self.labels.insert(target.clone(), line);
+            },
+            TokenType::Jmp => self.label_jumps.push((target.clone(), line)),
+            _ => ()
+        }
+
+        // A one shot remembers the previous scan in its tag, so every ONS needs one of its own
+        if spec.instruction == TokenType::Ons {
+            if self.rung_temps.contains(&target) {
+                return Err(ParseError::InvalidTag(format!("ONS can't use TEMP {}, which doesn't keep its value between scans", target)));
+            }
+            let line = self.previous_token.get_line();
+            if let Some(first_line) = self.ons_storage.insert(target.clone(), line) {
+                self.warnings.push(format!("ONS on line {} uses {}, which the ONS on line {} also uses. They will interfere with each other",
+                                           line, target, first_line));
+            }
+        }
+
+        if spec.check_contradiction {
+            self.check_contradiction(spec.instruction, &target);
+        }
+        let operands = targets.iter().map(String::as_str).collect::<Vec<&str>>();
+        if self.generating {
+            self.code_generator.add_instruction(spec.instruction, &operands);
+        }
+
+        // Temps don't carry anything between rungs, so they're left out of the cross reference
+        if !self.rung_temps.contains(&target) {
+            self.cross_reference.add_instruction(spec.instruction, &operands);
+        } else if spec.class == InstructionClass::Input {
+            // ...but they still decide whether the rest of the rung runs
+            self.cross_reference.add_condition();
+        }
+        Ok(())
+    }
+
+    /// BST opens a group of legs that are ORed together, NXB starts the next leg and BND closes the group
+    fn branch(&mut self) -> Result<(), ParseError> {
+        let keyword = self.previous_token.get_text().to_string();
+        if self.innermost_structure() != Some(TokenType::Rung) {
+            return Err(ParseError::StructuralMismatch(format!("{} must be inside of a rung", keyword)));
+        }
+        if self.rung_has_output {
+            return Err(ParseError::StructuralMismatch(format!("{} must come before the output instructions of its rung", keyword)));
+        }
+
+        self.rung_empty = false;
+        match self.previous_token.get_type() {
+            TokenType::Bst => self.rung_branches.push(self.previous_token.get_line()),
+            TokenType::Nxb if !self.rung_branches.is_empty() => (),
+            TokenType::Bnd if self.rung_branches.pop().is_some() => (),
+            _ => return Err(ParseError::StructuralMismatch(format!("{} must be inside of a branch opened by BST", keyword)))
+        }
+        self.branch_path();
+        if self.generating {
+            match self.previous_token.get_type() {
+                TokenType::Bst => self.code_generator.start_branch(),
+                TokenType::Nxb => self.code_generator.next_branch_leg(),
+                _ => self.code_generator.end_branch()
+            }
+        }
+        Ok(())
+    }
+
+    fn temp(&mut self) -> Result<(), ParseError> {
+        if self.innermost_structure() != Some(TokenType::Rung) {
+            return Err(ParseError::StructuralMismatch("TEMP tags must be declared inside of a rung".to_string()));
+        }
+
+        self.match_token(TokenType::Identifier)?;
+        self.validate_identifier(IdentifierKind::Tag)?;
+        let name = self.previous_token.get_text().to_string();
+
+        if self.rung_temps.contains(&name) {
+            return Err(ParseError::InvalidTag(format!("TEMP {} is already declared in this rung", name)));
+        }
+        self.rung_empty = false;
+        if self.tags.contains_key(&name) {
+            self.warnings.push(format!("TEMP {} hides the tag {} within its rung", name, name));
         }
 
-        self.match_token(TokenType::Identifier);
+        if self.generating {
+            self.code_generator.declare_temp(&name);
+        }
+        self.rung_temps.insert(name.clone());
+        self.temps.insert(name);
+        Ok(())
+    }
+
+    /// Checks the operand that was just matched and returns its full text
+    fn operand(&mut self, kind: OperandKind) -> Result<String, ParseError> {
         let mut target = self.previous_token.get_text().to_string();
 
-        match instruction_type {
-            TokenType::Jsr => {
+        match kind {
+            OperandKind::Routine => {
                 // Add the routine name to a list to be verified later
                 // during compilation
-                self.jumps.push(target.clone());
+                self.jumps.push((self.task_name.clone(), target.clone(), self.previous_token.get_line()));
+                self.call_graph.add_call(&target);
             },
-            TokenType::Emit => {
+            OperandKind::Label => self.validate_identifier(IdentifierKind::Label)?,
+            OperandKind::Event => {
                 // Add the event name to a list to be verified later
                 // during compilation
-                self.emitted_events.push(target.clone());
+                target = self.event_name()?;
+                self.emitted_events.push((target.clone(), self.previous_token.get_line()));
             },
-            _ => {
+            OperandKind::Timer | OperandKind::Counter => {
+                let (expected, keyword) = match kind {
+                    OperandKind::Timer => (TagKind::Timer, "TIMER"),
+                    _ => (TagKind::Counter, "COUNTER")
+                };
+                match self.tags.get(&target) {
+                    Some(TagDescriptor { kind, .. }) if *kind == expected => (),
+                    Some(_) => return Err(ParseError::InvalidTag(format!("{} is not a {} tag", target, keyword))),
+                    None => return Err(ParseError::UndefinedTag(target))
+                }
+            },
+            OperandKind::IntTag | OperandKind::IntValue => {
+                if self.rung_temps.contains(&target) {
+                    return Err(ParseError::InvalidTag(format!("{} is not an INT tag", target)));
+                }
+                match self.tags.get(&target) {
+                    Some(TagDescriptor { kind: TagKind::Int, .. }) => (),
+                    Some(TagDescriptor { kind: TagKind::BoolArray(_), .. }) => {
+                        return Err(ParseError::InvalidTag(format!("{} is not an INT tag. Tag arrays can't be compared, moved or used \
+                                                                   in arithmetic as a whole", target)));
+                    },
+                    Some(_) => return Err(ParseError::InvalidTag(format!("{} is not an INT tag", target))),
+                    None if self.temps.contains(&target) => return Err(ParseError::TempOutOfScope(target)),
+                    None => return Err(ParseError::UndefinedTag(target))
+                }
+            },
+            OperandKind::BoolTag => {
+                // Temps of the current rung hide tags of the same name
+                if self.rung_temps.contains(&target) {
+                    return Ok(target);
+                }
+
                 // Verify the tag exists
-                let tag_descriptor = self.tags.iter()
-                                                           .find(|&item| item.name == target)
-                                                           .or_else(|| {
-                                                                panic!("Referencing tag {} before assignment", target);
-                                                           }).unwrap().clone();
+                let length = match self.tags.get(&target) {
+                    Some(TagDescriptor { kind: TagKind::Bool, .. }) => return Ok(target),
+                    Some(TagDescriptor { kind: TagKind::BoolArray(length), .. }) => *length,
+                    Some(TagDescriptor { kind: TagKind::Timer, .. }) => return self.done_bit("Timer", target),
+                    Some(TagDescriptor { kind: TagKind::Counter, .. }) => return self.done_bit("Counter", target),
+                    Some(TagDescriptor { kind: TagKind::Int, .. }) => return Err(ParseError::InvalidTag(format!("{} is not a BOOL tag", target))),
+                    None if self.temps.contains(&target) => return Err(ParseError::TempOutOfScope(target)),
+                    None => return Err(ParseError::UndefinedTag(target))
+                };
 
                 // We are referencing a tag array, so require an index
-                if tag_descriptor.length != 0 {
-                    self.match_token(TokenType::Indexer);
-                    target += self.previous_token.get_text();
+                let tag_end = self.previous_token.get_span().end;
+                self.match_token(TokenType::Indexer)?;
+                let indexer_span = self.previous_token.get_span().clone();
+                target += self.previous_token.get_text();
+
+                // The index is either a number or an INT tag, which is only known as the program runs
+                let index_by_tag = self.check_token(TokenType::Identifier);
+                self.match_token(if index_by_tag { TokenType::Identifier } else { TokenType::Number })?;
+                target += self.previous_token.get_text();
+
+                // Indexing is written as a single word
+                if indexer_span.start != tag_end || self.previous_token.get_span().start != indexer_span.end {
+                    return Err(ParseError::InvalidIndex(format!("Array index must directly follow the tag name, e.g. {}", target)));
+                }
 
-                    self.match_token(TokenType::Number);
-                    target += self.previous_token.get_text();
+                if index_by_tag {
+                    let index = self.previous_token.get_text().to_string();
+                    return match self.tags.get(&index) {
+                        Some(TagDescriptor { kind: TagKind::Int, .. }) if !self.rung_temps.contains(&index) => Ok(target),
+                        Some(_) => Err(ParseError::InvalidIndex(format!("Index {} must be an INT tag or a whole number", index))),
+                        None if self.rung_temps.contains(&index) => {
+                            Err(ParseError::InvalidIndex(format!("Index {} must be an INT tag or a whole number", index)))
+                        },
+                        None => Err(ParseError::UndefinedTag(index))
+                    };
+                }
 
-                    if self.previous_token.get_text().parse::<usize>().unwrap() >= tag_descriptor.length {
-                        panic!("Index {} is out of bounds for tag array of length {}", self.previous_token.get_text(),
-                                                                                       tag_descriptor.length);
-                    }
+                let index = self.previous_token.get_text().parse::<usize>().map_err(|_| {
+                    ParseError::InvalidIndex(format!("Index {} must be a whole number", self.previous_token.get_text()))
+                })?;
+                if index >= length {
+                    return Err(ParseError::InvalidIndex(format!("Index {} is out of bounds for tag array of length {}",
+                                                                index, length)));
                 }
             }
         }
-
-        self.code_generator.add_instruction(instruction_type, &target);
+        Ok(target)
     }
 
-    fn end_rung(&mut self) {
-        if self.stack.pop().unwrap_or(TokenType::Eof) != TokenType::Rung {
-            panic!("Missing matching RUNG");
+    /// Checks the number that was just matched as an operand is a whole number
+    fn int_literal(&mut self) -> Result<String, ParseError> {
+        let text = self.previous_token.get_text();
+        match text.parse::<i64>() {
+            Ok(value) => Ok(value.to_string()),
+            Err(_) => Err(ParseError::InvalidTag(format!("Operand {} must be a whole number", text)))
         }
-        self.code_generator.end_rung();
     }
 
-    fn end_routine(&mut self) {
-        if self.stack.pop().unwrap_or(TokenType::Eof) != TokenType::Routine {
-            panic!("Missing matching ENDRUNG");
+    /// Reads the bit of a timer or counter whose name was just matched. Only the done bit can be examined
+    fn done_bit(&mut self, kind: &str, tag: String) -> Result<String, ParseError> {
+        let example = format!("{} {} is examined through its done bit, e.g. {}.DN", kind, tag, tag);
+        let tag_end = self.previous_token.get_span().end;
+        if !self.check_token(TokenType::Indexer) || self.current_token.get_span().start != tag_end {
+            return Err(ParseError::InvalidTag(example));
         }
-        self.code_generator.end_routine();
+        self.match_token(TokenType::Indexer)?;
+        let indexer_end = self.previous_token.get_span().end;
+        self.match_token(TokenType::Identifier)?;
+        if self.previous_token.get_text() != "DN" || self.previous_token.get_span().start != indexer_end {
+            return Err(ParseError::InvalidTag(example));
+        }
+        Ok(format!("{}.DN", tag))
     }
 
-    fn end_task(&mut self) {
-        if self.stack.is_empty() {
-            panic!("Too many end statements");
+    fn check_contradiction(&mut self, instruction_type: TokenType, target: &str) {
+        let opposite = match instruction_type {
+            TokenType::Xic => TokenType::Xio,
+            TokenType::Xio => TokenType::Xic,
+            _ => return
+        };
+
+        // A tag can't be both on and off, so the path could never be true
+        let contact = (instruction_type, target.to_string());
+        if self.rung_contacts.contains(&(opposite, target.to_string())) && !self.rung_contacts.contains(&contact) {
+            let path = if self.branch_contacts.is_empty() { "Rung condition" } else { "Branch leg" };
+            self.warnings.push(format!("{} is always false because {} is examined by both XIC and XIO", path, target));
         }
+        self.rung_contacts.push(contact);
+    }
 
-        if self.stack.pop().unwrap() != TokenType::Task {
-            panic!("Missing matching ENDROUTINE");
+    /// Moves the series path along with the branch keyword just parsed. Each leg starts from the contacts
+    /// before its BST, and after the BND only the contacts every leg examined are known to hold
+    fn branch_path(&mut self) {
+        if self.previous_token.get_type() == &TokenType::Bst {
+            self.branch_contacts.push((self.rung_contacts.clone(), None));
+            return;
         }
 
-        if !self.main_flag {
-            panic!("There must be a single Main routine");
+        let (before, common) = match self.branch_contacts.pop() {
+            Some(branch) => branch,
+            None => return
+        };
+        let leg = std::mem::take(&mut self.rung_contacts);
+        let common = match common {
+            Some(common) => common.into_iter().filter(|contact| leg.contains(contact)).collect(),
+            None => leg
+        };
+        if self.previous_token.get_type() == &TokenType::Nxb {
+            self.rung_contacts = before.clone();
+            self.branch_contacts.push((before, Some(common)));
         } else {
-            self.main_flag = false;
+            self.rung_contacts = common;
         }
+    }
 
-        self.emitter.emit_line(&self.code_generator.finish_code_block());
-        self.emitter.emit_line("}");
+    /// Pushes the structure whose keyword was just matched. It's named once its name is parsed
+    fn open_structure(&mut self) {
+        self.stack.push(Structure { kind: *self.previous_token.get_type(), name: String::new(), line: self.previous_token.get_line() });
     }
 
-    fn tag(&mut self) {
-        // Determine if this is a tag array or a single tag
-        let mut length: usize = 0;
-        if self.check_token(TokenType::OpenBracket) {
-            length = self.tag_array();
-        } else {
-            self.emitter.emit("TAG ");
+    fn name_structure(&mut self) {
+        if let Some(structure) = self.stack.last_mut() {
+            structure.name = self.previous_token.get_text().to_string();
         }
+    }
 
-        self.match_token(TokenType::Identifier);
-        self.emitter.emit(self.previous_token.get_text());
+    fn innermost_structure(&self) -> Option<TokenType> {
+        self.stack.last().map(|structure| structure.kind)
+    }
 
-        // Enforce a charater limit on tag names
-        const TAG_CHARACTER_LIMIT: usize = 7;
-        if self.previous_token.get_text().len() > TAG_CHARACTER_LIMIT {
-            panic!("Tag name {} too long. The limit is {} characters",
-                   self.previous_token.get_text(), TAG_CHARACTER_LIMIT);
+    fn end_rung(&mut self) -> Result<(), ParseError> {
+        if self.stack.pop().map(|structure| structure.kind) != Some(TokenType::Rung) {
+            return Err(ParseError::StructuralMismatch("Missing matching RUNG".to_string()));
         }
-
-        self.tags.push(TagDescriptor {
-            name: self.previous_token.get_text().to_string(),
-            length
-        });
-        self.match_token(TokenType::Eq);
-
-        // Either true or false are acceptable
-        if self.check_token(TokenType::True) {
-            self.match_token(TokenType::True);
-            self.emitter.emit_line(" TRUE");
-        } else {
-            self.match_token(TokenType::False);
-            self.emitter.emit_line(" FALSE");
+        if let Some(line) = self.rung_branches.last() {
+            return Err(ParseError::StructuralMismatch(format!("Missing matching BND for BST on line {}", line)));
+        }
+        self.rung_temps.clear();
+        if self.generating {
+            self.code_generator.end_rung();
         }
+        Ok(())
     }
 
-    fn tag_array(&mut self) -> usize{
-        self.match_token(TokenType::OpenBracket);
-        self.match_token(TokenType::Number);
+    fn end_routine(&mut self) -> Result<(), ParseError> {
+        match self.stack.pop() {
+            Some(structure) if structure.kind == TokenType::Routine => {
+                let labels = std::mem::take(&mut self.labels);
+                if let Some((label, line)) = std::mem::take(&mut self.label_jumps).into_iter().find(|(label, _)| !labels.contains_key(label)) {
+                    return Err(ParseError::UndefinedLabel { label, routine: structure.name, line });
+                }
+            },
+            Some(structure) if structure.kind == TokenType::Rung => {
+                return Err(ParseError::StructuralMismatch(format!("Missing matching ENDRUNG for {}", structure)));
+            },
+            _ => return Err(ParseError::StructuralMismatch("Missing matching ENDRUNG".to_string()))
+        }
+        if self.generating {
+            self.code_generator.end_routine();
+        }
+        Ok(())
+    }
 
-        let length: usize = self.previous_token.get_text().parse().unwrap();
-        if length == 0 {
-            panic!("Length of tag array must be greater than zero");
+    fn end_task(&mut self) -> Result<(), ParseError> {
+        match self.stack.pop() {
+            None => return Err(ParseError::StructuralMismatch("Too many end statements".to_string())),
+            Some(structure) if structure.kind == TokenType::EventSpace => {
+                return Err(ParseError::StructuralMismatch("Too many end statements".to_string()));
+            },
+            Some(structure) if structure.kind == TokenType::Task => (),
+            Some(structure) => {
+                return Err(ParseError::StructuralMismatch(format!("Missing matching {} for {}", structure.closing_keyword(), structure)));
+            }
         }
 
-        self.emitter.emit("TAG_ARRAY ");
-        self.emitter.emit(self.previous_token.get_text());
-        self.emitter.emit(" ");
+        // The routines of this task are done with, even if it turns out to have no single entry routine
+        let main_flag = std::mem::take(&mut self.main_flag);
+        let entry_routines = std::mem::take(&mut self.entry_routines);
+        self.task_routines.clear();
 
-        self.match_token(TokenType::CloseBracket);
-        length
-    }
+        let entry_routine = match entry_routines.as_slice() {
+            [] if main_flag => "Main".to_string(),
+            [] => {
+                return Err(ParseError::InvalidEntryRoutine(format!("Task {} has no entry routine. Name one Main or mark it ROUTINE<ENTRY>",
+                                                                   self.task_name)));
+            },
+            [entry_routine] => {
+                if main_flag && entry_routine != "Main" {
+                    self.warnings.push(format!("Task {} starts with {} because it is marked ENTRY, so Main only runs if it is called",
+                                               self.task_name, entry_routine));
+                }
+                entry_routine.clone()
+            },
+            entry_routines => {
+                return Err(ParseError::InvalidEntryRoutine(format!("Task {} has more than one entry routine: {}",
+                                                                   self.task_name, entry_routines.join(", "))));
+            }
+        };
+        self.call_graph.set_entry(&entry_routine);
 
-    fn new_line(&mut self) {
-        self.match_token(TokenType::NewLine);
-        while self.check_token(TokenType::NewLine) {
-            self.next_token();
+        if self.generating {
+            self.code_generator.set_entry_point(&entry_routine);
+            self.emitter.emit_line(&self.code_generator.finish_code_block());
         }
+        self.emitter.emit_line("}");
+        Ok(())
     }
-}
 
+    fn tag(&mut self) -> Result<(), ParseError> {
+        // Determine if this is a tag array or a single tag
+        let mut length: usize = 0;
+        if self.check_token(TokenType::OpenBracket) {
+            length = self.tag_array()?;
+        }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+        self.match_token(TokenType::Identifier)?;
+        self.validate_identifier(IdentifierKind::Tag)?;
+        let name = self.previous_token.get_text().to_string();
 
-    #[test]
-    fn test_statement_tag_1() {
-        let source_code = "TAG myTag = TRUE\nTAG myTag = FALSE".to_string();
-        let mut par = Parser::new(Lexer::new(source_code.clone()), Emitter::new("test.out"));
-        par.program();
+        let kind = if length == 0 { TagKind::Bool } else { TagKind::BoolArray(length) };
+        self.declare_tag(&name, kind)?;
+
+        // Catch the common ways of getting the initialization wrong
+        if self.check_token(TokenType::True) || self.check_token(TokenType::False) {
+            return Err(ParseError::InvalidTag(format!("Missing '=' before the initial value. Tag initialization is written TAG {} = {}",
+                                                      name, self.current_token.get_text())));
+        }
+        self.match_token(TokenType::Eq)?;
+        if self.check_token(TokenType::Eq) {
+            return Err(ParseError::InvalidTag("Found '==', tag initialization uses a single '='".to_string()));
+        }
+
+        // Either true or false are acceptable
+        let value = self.check_token(TokenType::True);
+        if value {
+            self.match_token(TokenType::True)?;
+        } else {
+            self.match_token(TokenType::False)?;
+        }
+
+        let line = if self.options.legacy_tag_declarations {
+            code_generation::legacy_tag_declaration(&name, length, value)
+        } else {
+            code_generation::tag_declaration(&name, length, value)
+        };
+        self.emitter.emit_tag(&name, length != 0, &line);
+        if length != 0 && self.generating {
+            self.code_generator.declare_array(&name, length);
+        }
+        Ok(())
+    }
+
+    fn int_tag(&mut self) -> Result<(), ParseError> {
+        self.match_token(TokenType::Identifier)?;
+        self.validate_identifier(IdentifierKind::Tag)?;
+        let name = self.previous_token.get_text().to_string();
+        self.declare_tag(&name, TagKind::Int)?;
+
+        self.match_token(TokenType::Eq)?;
+        self.match_token(TokenType::Number)?;
+        let value = self.previous_token.get_text().parse::<i64>().map_err(|_| {
+            ParseError::InvalidTag(format!("Initial value {} of INT {} must be a whole number", self.previous_token.get_text(), name))
+        })?;
+
+        let line = if self.options.legacy_tag_declarations {
+            code_generation::legacy_int_declaration(&name, value)
+        } else {
+            code_generation::int_declaration(&name, value)
+        };
+        self.emitter.emit_tag(&name, false, &line);
+        Ok(())
+    }
+
+    fn timer(&mut self) -> Result<(), ParseError> {
+        // The preset is a whole number of milliseconds
+        let (name, preset) = self.preset_tag(TagKind::Timer, "timer", " of milliseconds")?;
+
+        let line = if self.options.legacy_tag_declarations {
+            code_generation::legacy_timer_declaration(&name, preset)
+        } else {
+            code_generation::timer_declaration(&name, preset)
+        };
+        self.emitter.emit_tag(&name, false, &line);
+        Ok(())
+    }
+
+    fn counter(&mut self) -> Result<(), ParseError> {
+        let (name, preset) = self.preset_tag(TagKind::Counter, "counter", "")?;
+
+        let line = if self.options.legacy_tag_declarations {
+            code_generation::legacy_counter_declaration(&name, preset)
+        } else {
+            code_generation::counter_declaration(&name, preset)
+        };
+        self.emitter.emit_tag(&name, false, &line);
+        Ok(())
+    }
+
+    /// Declares a tag written `name = preset`, returning its name and preset
+    fn preset_tag(&mut self, kind: TagKind, kind_name: &str, unit: &str) -> Result<(String, u32), ParseError> {
+        self.match_token(TokenType::Identifier)?;
+        self.validate_identifier(IdentifierKind::Tag)?;
+        let name = self.previous_token.get_text().to_string();
+        self.declare_tag(&name, kind)?;
+
+        self.match_token(TokenType::Eq)?;
+        self.match_token(TokenType::Number)?;
+        match self.previous_token.get_text().parse::<u32>() {
+            Ok(preset) if preset > 0 => Ok((name, preset)),
+            _ => Err(ParseError::InvalidTag(format!("Preset {} of {} {} must be a whole number{} greater than zero",
+                                                    self.previous_token.get_text(), kind_name, name, unit)))
+        }
+    }
+
+    /// Records the declaration of a tag, whose name was just matched. A tag may be declared again as the same
+    /// kind, in which case the first declaration is the one references are checked against
+    fn declare_tag(&mut self, name: &str, kind: TagKind) -> Result<(), ParseError> {
+        let line = self.previous_token.get_line();
+        match self.tags.get(name) {
+            Some(first) if first.kind != kind => Err(ParseError::ConflictingTag {
+                tag: name.to_string(),
+                first_kind: first.kind.keyword(),
+                first_line: first.line,
+                kind: kind.keyword(),
+                line
+            }),
+            Some(_) => Ok(()),
+            None => {
+                self.tags.insert(name.to_string(), TagDescriptor { kind, line });
+                Ok(())
+            }
+        }
+    }
+
+    fn tag_array(&mut self) -> Result<usize, ParseError> {
+        self.match_token(TokenType::OpenBracket)?;
+        self.match_token(TokenType::Number)?;
+
+        let length: usize = self.previous_token.get_text().parse().map_err(|_| {
+            ParseError::InvalidTag(format!("Length of tag array {} must be a whole number", self.previous_token.get_text()))
+        })?;
+        if length == 0 {
+            return Err(ParseError::InvalidTag("Length of tag array must be greater than zero".to_string()));
+        }
+
+        self.match_token(TokenType::CloseBracket)?;
+        Ok(length)
+    }
+
+    /// Skips the rest of the line an error was found on, so that parsing can carry on with the next statement
+    fn skip_line(&mut self) {
+        while !self.check_token(TokenType::NewLine) && !self.check_token(TokenType::Eof) {
+            self.next_token();
+        }
+        while self.check_token(TokenType::NewLine) {
+            self.next_token();
+        }
+    }
+
+    fn new_line(&mut self) -> Result<(), ParseError> {
+        self.match_token(TokenType::NewLine)?;
+        while self.check_token(TokenType::NewLine) {
+            self.next_token();
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Structure a snippet is parsed inside of, as if the source opened it
+    fn enclosing(kind: TokenType) -> Structure {
+        Structure { kind, name: String::new(), line: 0 }
+    }
+
+    /// Compiles the program, returning the error found on its earliest line. Most snippets stop partway through
+    /// a structure, so a structure left open at the end of the file only counts when nothing else is wrong
+    fn first_error(par: &mut Parser) -> ParseError {
+        let errors = par.program().unwrap_err();
+        let unclosed = |diagnostic: &&Diagnostic| {
+            matches!(&diagnostic.error, ParseError::StructuralMismatch(message) if message.starts_with("Unexpected end of file"))
+        };
+        errors.iter().find(|diagnostic| !unclosed(diagnostic)).unwrap_or(&errors[0]).error.clone()
+    }
+
+    fn parse_statements(par: &mut Parser) {
+        while !par.check_token(TokenType::Eof) {
+            par.statement().unwrap();
+        }
+    }
+
+    #[test]
+    fn test_statement_tag_1() {
+        let source_code = "TAG myTag = TRUE\nTAG myTag = FALSE".to_string();
+        let mut par = Parser::new(Lexer::new(source_code.clone()), Emitter::new("test.out"), CompilerOptions::default());
+        par.program().unwrap();
+    }
+
+    #[test]
+    fn test_statement_tag_conflicting() {
+        let source_code = "TAG myTag = FALSE\nTIMER myTag = 5".to_string();
+        let mut par = Parser::new(Lexer::new(source_code), Emitter::new("test.out"), CompilerOptions::default());
+        assert_eq!("Tag myTag is declared as TAG on line 1 and as TIMER on line 2. A name can only be declared as one kind of tag",
+                   first_error(&mut par).to_string());
+
+        let source_code = "TAG[4] myTag = FALSE\nTAG[8] myTag = FALSE".to_string();
+        let mut par = Parser::new(Lexer::new(source_code), Emitter::new("test.out"), CompilerOptions::default());
+        assert!(matches!(first_error(&mut par), ParseError::ConflictingTag { first_line: 1, line: 2, .. }));
     }
 
     #[test]
-    #[should_panic]
     fn test_statement_tag_2() {
         let source_code = "TAG myTag = notAKeyword".to_string();
-        let mut par = Parser::new(Lexer::new(source_code.clone()), Emitter::new("test.out"));
-        par.program();
+        let mut par = Parser::new(Lexer::new(source_code.clone()), Emitter::new("test.out"), CompilerOptions::default());
+        assert!(matches!(first_error(&mut par), ParseError::UnexpectedToken { expected: TokenType::False, .. }));
+    }
+
+    #[test]
+    fn test_statement_rung_reserved() {
+        let source_code = "RUNG EmitEvent".to_string();
+        let mut par = Parser::new(Lexer::new(source_code.clone()), Emitter::new("test.out"), CompilerOptions::default());
+        par.stack.push(enclosing(TokenType::Routine));
+        assert_eq!("Rung name EmitEvent is reserved", first_error(&mut par).to_string());
     }
 
     #[test]
-    #[should_panic]
     fn test_statement_tag_3() {
         let source_code = "TAG myLongTagName = FALSE".to_string();
-        let mut par = Parser::new(Lexer::new(source_code.clone()), Emitter::new("test.out"));
-        par.program();
+        let mut par = Parser::new(Lexer::new(source_code.clone()), Emitter::new("test.out"), CompilerOptions::default());
+        let error = first_error(&mut par);
+        assert_eq!("Tag name myLongTagName too long. The limit is 7 characters", error.to_string());
+        assert_eq!(Some((1, 5)), error.location());
+
+        // Every rule the name breaks is reported, each at the name
+        let source_code = "TAG EmitEvent = FALSE".to_string();
+        let mut par = Parser::new(Lexer::new(source_code.clone()), Emitter::new("test.out"), CompilerOptions::default());
+        let errors = par.program().unwrap_err();
+        assert_eq!(vec![("Tag name EmitEvent too long. The limit is 7 characters".to_string(), Some((1, 5))),
+                        ("Tag name EmitEvent is reserved".to_string(), Some((1, 5)))],
+                   errors.iter().map(|diagnostic| (diagnostic.error.to_string(), diagnostic.error.location())).collect::<Vec<_>>());
+
+        // The limits come from the options
+        let options = CompilerOptions { tag_name_limit: Some(16), ..Default::default() };
+        let mut par = Parser::new(Lexer::new("TAG myLongTagName = FALSE".to_string()), Emitter::new("test.out"), options);
+        par.program().unwrap();
+    }
+
+    #[test]
+    fn test_error_location() {
+        let source_code = "TAG myTag = TRUE # comment\nTASK<PERIOD=100>\tEVENT".to_string();
+        let mut par = Parser::new(Lexer::new(source_code.clone()), Emitter::new("test.out"), CompilerOptions::default());
+        assert_eq!(Some((2, 18)), first_error(&mut par).location());
+    }
+
+    #[test]
+    fn test_multiple_errors() {
+        let source_code = "TAG start = FALSE\nTAG motor = TRUE junk\nTASK<PERIOD=100> MainTask\nROUTINE Main\nRUNG\nXIC strt\nOTE motor
+ENDRUNG\nRUNG\nXIC start\nJSR Helper\nJSR Other\nJSR Helper\nENDRUNG\nENDROUTINE\nENDTASK\nENDTASK".to_string();
+        let mut par = Parser::new(Lexer::new(source_code), Emitter::new("test.out"), CompilerOptions::default());
+
+        // Each statement with an error is skipped, and the checks made once the program is parsed report all they find
+        let errors = par.program().unwrap_err();
+        assert_eq!(vec![Some(2), Some(6), Some(11), Some(12), Some(17)], errors.iter().map(|diagnostic| diagnostic.line).collect::<Vec<_>>());
+        assert!(matches!(errors[0].error, ParseError::TrailingTokens { .. }));
+        assert_eq!(ParseError::UndefinedTag("strt".to_string()), errors[1].error);
+        assert_eq!(ParseError::UndefinedRoutine("Helper".to_string()), errors[2].error);
+        assert_eq!(ParseError::UndefinedRoutine("Other".to_string()), errors[3].error);
+        assert_eq!(ParseError::StructuralMismatch("Too many end statements".to_string()), errors[4].error);
+    }
+
+    #[test]
+    fn test_recovery_after_task() {
+        let task = |name: &str, routines: &str| {
+            format!("TASK<PERIOD=100> {}\nROUTINE<ENTRY> A\nENDROUTINE\n{}ENDTASK\n", name, routines)
+        };
+        let source_code = format!("{}{}", task("one", "ROUTINE<ENTRY> B\nENDROUTINE\n"), task("two", ""));
+        let mut par = Parser::new(Lexer::new(source_code), Emitter::new("test.out"), CompilerOptions::default());
+
+        // The routines of the task with an error don't carry over into the next one
+        let errors = par.program().unwrap_err();
+        assert_eq!(vec![Diagnostic::new(ParseError::InvalidEntryRoutine("Task one has more than one entry routine: A, B".to_string()), Some(6))],
+                   errors);
+    }
+
+    #[test]
+    fn test_max_errors() {
+        let source_code = "XIC a\nXIC b\nXIC c\nTASK<PERIOD=100> MainTask".to_string();
+        let options = CompilerOptions { max_errors: Some(2), ..Default::default() };
+        let mut par = Parser::new(Lexer::new(source_code.clone()), Emitter::new("test.out"), options);
+        assert_eq!(vec![Some(1), Some(2)], par.program().unwrap_err().iter().map(|diagnostic| diagnostic.line).collect::<Vec<_>>());
+        assert!(par.error_limit_reached());
+
+        // Unclosed structures are only reported once the whole program has been parsed
+        let mut par = Parser::new(Lexer::new(source_code), Emitter::new("test.out"), CompilerOptions::default());
+        let errors = par.program().unwrap_err();
+        assert_eq!(4, errors.len());
+        assert!(matches!(&errors[3].error, ParseError::StructuralMismatch(message) if message.starts_with("Unexpected end of file")));
+        assert!(!par.error_limit_reached());
+    }
+
+    #[test]
+    fn test_statement_tag_escaped() {
+        let source_code = "TAG `EVENT` = TRUE\nXIC `EVENT`".to_string();
+        let mut par = Parser::new(Lexer::new(source_code.clone()), Emitter::new("test.out"), CompilerOptions::default());
+        par.stack.push(enclosing(TokenType::Rung));
+        parse_statements(&mut par);
+    }
+
+    #[test]
+    fn test_statement_tag_escaped_invalid() {
+        let source_code = "TAG start = FALSE\nTAG `EVENT = TRUE\nTAG `` = TRUE".to_string();
+        let mut par = Parser::new(Lexer::new(source_code), Emitter::new("test.out"), CompilerOptions::default());
+        let errors = par.program().unwrap_err();
+        assert_eq!(vec![("Escaped identifier is missing a closing `".to_string(), Some((2, 5))),
+                        ("Escaped identifier can't be empty".to_string(), Some((3, 5)))],
+                   errors.iter().map(|diagnostic| (diagnostic.error.to_string(), diagnostic.error.location())).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_statement_invalid_token() {
+        let source_code = "TAG[2.] lights = FALSE\nTAG my_Tag = FALSE".to_string();
+        let mut par = Parser::new(Lexer::new(source_code), Emitter::new("test.out"), CompilerOptions::default());
+        let errors = par.program().unwrap_err();
+        assert_eq!(vec![("Illegal character in number".to_string(), Some((1, 5))), ("Unknown token: _".to_string(), Some((2, 7)))],
+                   errors.iter().map(|diagnostic| (diagnostic.error.to_string(), diagnostic.error.location())).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_statement_tag_keyword() {
+        let source_code = "TAG EVENT = TRUE".to_string();
+        let mut par = Parser::new(Lexer::new(source_code.clone()), Emitter::new("test.out"), CompilerOptions::default());
+        assert_eq!("Expected Identifier, but found keyword EVENT. To use it as a name, escape it as `EVENT`",
+                   first_error(&mut par).to_string());
+    }
+
+    #[test]
+    fn test_unexpected_token_message() {
+        let messages = [("TAG = TRUE", "Expected Identifier, but found '=' (Eq)"), ("TAG", "Expected Identifier, but found the end of the line")];
+        for (source_code, message) in messages {
+            let mut par = Parser::new(Lexer::new(source_code.to_string()), Emitter::new("test.out"), CompilerOptions::default());
+            assert_eq!(message, first_error(&mut par).to_string());
+        }
+    }
+
+    #[test]
+    fn test_statement_tag_double_equals() {
+        let source_code = "TAG myTag == TRUE".to_string();
+        let mut par = Parser::new(Lexer::new(source_code.clone()), Emitter::new("test.out"), CompilerOptions::default());
+        assert_eq!(ParseError::InvalidTag("Found '==', tag initialization uses a single '='".to_string()), first_error(&mut par));
+    }
+
+    #[test]
+    fn test_statement_tag_missing_equals() {
+        let source_code = "TAG myTag TRUE".to_string();
+        let mut par = Parser::new(Lexer::new(source_code.clone()), Emitter::new("test.out"), CompilerOptions::default());
+        assert_eq!(ParseError::InvalidTag("Missing '=' before the initial value. Tag initialization is written TAG myTag = TRUE".to_string()),
+                   first_error(&mut par));
     }
 
     #[test]
     fn test_statement_task_1() {
-        let source_code = "TASK<PERIOD=1000> myTask".to_string();
-        let mut par = Parser::new(Lexer::new(source_code.clone()), Emitter::new("test.out"));
-        par.program();
+        let source_code = "TASK<PERIOD=1000> myTask\nROUTINE Main\nENDROUTINE\nENDTASK".to_string();
+        let mut par = Parser::new(Lexer::new(source_code.clone()), Emitter::new("test.out"), CompilerOptions::default());
+        par.program().unwrap();
     }
 
     #[test]
     fn test_statement_task_2() {
-        let source_code = "TASK<EVENT=myEvent> myTask".to_string();
-        let mut par = Parser::new(Lexer::new(source_code.clone()), Emitter::new("test.out"));
-        par.program();
+        let source_code = "TASK<EVENT=myEvent> myTask\nROUTINE Main\nENDROUTINE\nENDTASK".to_string();
+        let mut par = Parser::new(Lexer::new(source_code.clone()), Emitter::new("test.out"), CompilerOptions::default());
+        par.program().unwrap();
+    }
+
+    #[test]
+    fn test_statement_task_scheduler_tick_1() {
+        let source_code = "TASK<PERIOD=30> myTask\nROUTINE Main\nENDROUTINE\nENDTASK".to_string();
+        let options = CompilerOptions { scheduler_tick: Some(10), ..Default::default() };
+        let mut par = Parser::new(Lexer::new(source_code.clone()), Emitter::new("test.out"), options);
+        par.program().unwrap();
+        assert!(par.warnings.is_empty());
+    }
+
+    #[test]
+    fn test_statement_task_scheduler_tick_2() {
+        let source_code = "TASK<PERIOD=25> myTask\nROUTINE Main\nENDROUTINE\nENDTASK".to_string();
+        let options = CompilerOptions { scheduler_tick: Some(10), ..Default::default() };
+        let mut par = Parser::new(Lexer::new(source_code.clone()), Emitter::new("test.out"), options);
+        par.program().unwrap();
+        assert_eq!(par.warnings, ["Period 25 is not a multiple of the scheduler tick 10. The effective period will be 30"]);
+    }
+
+    #[test]
+    fn test_statement_task_scheduler_tick_3() {
+        let source_code = "TASK<PERIOD=25> myTask\nROUTINE Main\nENDROUTINE\nENDTASK".to_string();
+        let mut par = Parser::new(Lexer::new(source_code.clone()), Emitter::new("test.out"), CompilerOptions::default());
+        par.program().unwrap();
+        assert!(par.warnings.is_empty());
+    }
+
+    #[test]
+    fn test_statement_task_scheduler_tick_4() {
+        let source_code = "TASK<PERIOD=25> myTask\nROUTINE Main\nENDROUTINE\nENDTASK".to_string();
+        let options = CompilerOptions { scheduler_tick: Some(10), deny_warnings: true, ..Default::default() };
+        let mut par = Parser::new(Lexer::new(source_code.clone()), Emitter::new("test.out"), options);
+        assert_eq!(ParseError::WarningsDenied(1), first_error(&mut par));
+    }
+
+    #[test]
+    fn test_statement_task_scheduler_tick_5() {
+        // Warnings are left for the caller to report, except alongside errors
+        let source_code = "TASK<PERIOD=25> myTask\nROUTINE Main\nENDROUTINE\nENDTASK".to_string();
+        let options = CompilerOptions { scheduler_tick: Some(10), deny_warnings: true, ..Default::default() };
+        let mut par = Parser::new(Lexer::new(source_code.clone()), Emitter::new("test.out"), options);
+        assert!(par.program().is_err());
+        assert_eq!(1, par.warnings().len());
+
+        let source_code = format!("{}\nJSR missing", source_code);
+        let options = CompilerOptions { scheduler_tick: Some(10), ..Default::default() };
+        let mut par = Parser::new(Lexer::new(source_code), Emitter::new("test.out"), options);
+        assert!(par.program().is_err());
+        assert!(par.warnings().is_empty());
+    }
+
+    #[test]
+    fn test_statement_task_scheduler_tick_overflow() {
+        let source_code = "TASK<PERIOD=4294967295> myTask\nROUTINE Main\nENDROUTINE\nENDTASK".to_string();
+        let options = CompilerOptions { scheduler_tick: Some(10), ..Default::default() };
+        let mut par = Parser::new(Lexer::new(source_code), Emitter::new("test.out"), options);
+        assert_eq!(ParseError::InvalidTaskHeader("Period 4294967295 rounded up to a multiple of the scheduler tick 10 is too long".to_string()),
+                   first_error(&mut par));
+    }
+
+    #[test]
+    fn test_statement_task_spacing() {
+        let source_code = "TASK <PERIOD = 100> myTask\nROUTINE Main\nENDROUTINE\nENDTASK".to_string();
+        let mut par = Parser::new(Lexer::new(source_code.clone()), Emitter::new("test.out"), CompilerOptions::default());
+        par.program().unwrap();
+
+        let source_code = "TASK\t< EVENT=myEvent >myTask\nROUTINE Main\nENDROUTINE\nENDTASK".to_string();
+        let mut par = Parser::new(Lexer::new(source_code.clone()), Emitter::new("test.out"), CompilerOptions::default());
+        par.program().unwrap();
+    }
+
+    #[test]
+    fn test_statement_task_unclosed_1() {
+        let source_code = "TASK<PERIOD=100\nROUTINE Main".to_string();
+        let mut par = Parser::new(Lexer::new(source_code.clone()), Emitter::new("test.out"), CompilerOptions::default());
+        assert_eq!(ParseError::InvalidTaskHeader("Task header is not closed. Expected '>' before end of line".to_string()),
+                   first_error(&mut par));
+    }
+
+    #[test]
+    fn test_statement_task_unclosed_2() {
+        let source_code = "TASK<EVENT=\nmyEvent> myTask".to_string();
+        let mut par = Parser::new(Lexer::new(source_code.clone()), Emitter::new("test.out"), CompilerOptions::default());
+        assert_eq!(ParseError::InvalidTaskHeader("Task header is not closed. Expected '>' before end of line".to_string()),
+                   first_error(&mut par));
+    }
+
+    #[test]
+    fn test_statement_task_unclosed_3() {
+        let source_code = "TASK<".to_string();
+        let mut par = Parser::new(Lexer::new(source_code.clone()), Emitter::new("test.out"), CompilerOptions::default());
+        assert_eq!(ParseError::InvalidTaskHeader("Task header is not closed. Expected '>' before end of line".to_string()),
+                   first_error(&mut par));
+    }
+
+    #[test]
+    fn test_statement_task_name_first() {
+        let source_code = "TASK myTask<PERIOD=100>".to_string();
+        let mut par = Parser::new(Lexer::new(source_code.clone()), Emitter::new("test.out"), CompilerOptions::default());
+        assert_eq!(ParseError::InvalidTaskHeader("Task name myTask must come after the task type, e.g. TASK<PERIOD=100> myTask".to_string()),
+                   first_error(&mut par));
     }
 
     #[test]
-    #[should_panic]
     fn test_statement_task_3() {
         let source_code = "TASK<INVALID> myTask".to_string();
-        let mut par = Parser::new(Lexer::new(source_code.clone()), Emitter::new("test.out"));
-        par.program();
+        let mut par = Parser::new(Lexer::new(source_code.clone()), Emitter::new("test.out"), CompilerOptions::default());
+        assert_eq!(ParseError::InvalidTaskHeader("Invalid task type INVALID".to_string()), first_error(&mut par));
     }
 
     #[test]
     fn test_statement_routine_success() {
         let source_code = "ROUTINE Main".to_string();
-        let mut par = Parser::new(Lexer::new(source_code.clone()), Emitter::new("test.out"));
-        par.stack.push(TokenType::Task);
+        let mut par = Parser::new(Lexer::new(source_code.clone()), Emitter::new("test.out"), CompilerOptions::default());
+        par.stack.push(enclosing(TokenType::Task));
 
-        par.program();
+        parse_statements(&mut par);
     }
 
     #[test]
-    #[should_panic]
     fn test_statement_routine_failure() {
         let source_code = "ROUTINE ".to_string();
-        let mut par = Parser::new(Lexer::new(source_code.clone()), Emitter::new("test.out"));
-        par.program();
+        let mut par = Parser::new(Lexer::new(source_code.clone()), Emitter::new("test.out"), CompilerOptions::default());
+        assert_eq!(ParseError::StructuralMismatch("Routines must be defined inside of a task".to_string()), first_error(&mut par));
+    }
+
+    #[test]
+    fn test_statement_routine_shared() {
+        let source_code = "TASK<PERIOD=100> Periodic
+ROUTINE Main
+RUNG
+JSR Helper
+ENDRUNG
+ENDROUTINE
+ROUTINE Helper
+ENDROUTINE
+ENDTASK
+TASK<EVENT=alarm> OnAlarm
+ROUTINE Main
+RUNG
+JSR Helper
+ENDRUNG
+ENDROUTINE
+ENDTASK".to_string();
+        let mut par = Parser::new(Lexer::new(source_code.clone()), Emitter::new("test.out"), CompilerOptions::default());
+        assert_eq!(ParseError::SharedRoutine { routine: "Helper".to_string(), tasks: vec!["Periodic".to_string(), "OnAlarm".to_string()] },
+                   first_error(&mut par));
+    }
+
+    #[test]
+    fn test_statement_routine_cross_task() {
+        let source_code = "TASK<PERIOD=100> Periodic
+ROUTINE Main
+ENDROUTINE
+ROUTINE Helper
+ENDROUTINE
+ENDTASK
+TASK<CONTINUOUS> Background
+ROUTINE Main
+RUNG
+JSR Helper
+ENDRUNG
+ENDROUTINE
+ENDTASK".to_string();
+        let mut par = Parser::new(Lexer::new(source_code.clone()), Emitter::new("test.out"), CompilerOptions::default());
+        assert_eq!(ParseError::CrossTaskJump { routine: "Helper".to_string(), task: "Background".to_string(), defined_in: "Periodic".to_string() },
+                   first_error(&mut par));
+    }
+
+    #[test]
+    fn test_statement_routine_duplicate() {
+        let source_code = "TASK<PERIOD=100> cell2
+ROUTINE Main
+ENDROUTINE
+ROUTINE Init
+ENDROUTINE
+ROUTINE Init
+ENDROUTINE
+ENDTASK".to_string();
+        let mut par = Parser::new(Lexer::new(source_code.clone()), Emitter::new("test.out"), CompilerOptions::default());
+        let error = first_error(&mut par);
+        assert_eq!(ParseError::DuplicateRoutine { routine: "Init".to_string(), task: "cell2".to_string(), first_line: 4, line: 6 }, error);
+        assert_eq!("Routine Init is defined more than once in task cell2, on lines 4 and 6", error.to_string());
+
+        // Each task has its own routines
+        let source_code = "TASK<PERIOD=100> cell1
+ROUTINE Main
+ENDROUTINE
+ROUTINE Init
+ENDROUTINE
+ENDTASK
+TASK<PERIOD=100> cell2
+ROUTINE Main
+ENDROUTINE
+ROUTINE Init
+ENDROUTINE
+ENDTASK".to_string();
+        let mut par = Parser::new(Lexer::new(source_code.clone()), Emitter::new("test.out"), CompilerOptions::default());
+        par.program().unwrap();
     }
 
     #[test]
     fn test_statement_rung_1() {
         let source_code = "RUNG".to_string();
-        let mut par = Parser::new(Lexer::new(source_code.clone()), Emitter::new("test.out"));
-        par.stack.push(TokenType::Routine);
+        let mut par = Parser::new(Lexer::new(source_code.clone()), Emitter::new("test.out"), CompilerOptions::default());
+        par.stack.push(enclosing(TokenType::Routine));
         
-        par.program();
+        parse_statements(&mut par);
     }
 
     #[test]
     fn test_statement_rung_2() {
         let source_code = "RUNG myRung".to_string();
-        let mut par = Parser::new(Lexer::new(source_code.clone()), Emitter::new("test.out"));
-        par.stack.push(TokenType::Routine);
+        let mut par = Parser::new(Lexer::new(source_code.clone()), Emitter::new("test.out"), CompilerOptions::default());
+        par.stack.push(enclosing(TokenType::Routine));
         
-        par.program();
+        parse_statements(&mut par);
+    }
+
+    #[test]
+    fn test_statement_unclosed() {
+        let source_code = "TASK<CONTINUOUS> task\nROUTINE Main\nRUNG\nXIC tag".to_string();
+        let mut par = Parser::new(Lexer::new(source_code.clone()), Emitter::new("test.out"), CompilerOptions::default());
+        par.tags.insert("tag".to_string(), TagDescriptor { kind: TagKind::Bool, line: 0 });
+
+        // Each structure left open is reported where it was opened
+        let unclosed = |structure: &str, line| {
+            Diagnostic::new(ParseError::StructuralMismatch(format!("Unexpected end of file. {} on line {} is not closed", structure, line)), Some(line))
+        };
+        assert_eq!(vec![unclosed("Task task", 1), unclosed("Routine Main", 2), unclosed("Rung", 3)], par.program().unwrap_err());
+    }
+
+    #[test]
+    fn test_statement_unclosed_task() {
+        let source_code = "TASK<CONTINUOUS> first\nROUTINE Main\nENDROUTINE\nENDTASK\n\nTASK<PERIOD=100> second\nROUTINE Main\nENDROUTINE\n".to_string();
+        let mut par = Parser::new(Lexer::new(source_code.clone()), Emitter::new("test.out"), CompilerOptions::default());
+        assert_eq!(ParseError::StructuralMismatch("Unexpected end of file. Task second on line 6 is not closed".to_string()),
+                   first_error(&mut par));
+    }
+
+    #[test]
+    fn test_statement_unclosed_routine() {
+        let source_code = "TASK<CONTINUOUS> task\nROUTINE Main\nENDTASK".to_string();
+        let mut par = Parser::new(Lexer::new(source_code.clone()), Emitter::new("test.out"), CompilerOptions::default());
+        assert_eq!(ParseError::StructuralMismatch("Missing matching ENDROUTINE for Routine Main on line 2".to_string()),
+                   first_error(&mut par));
+    }
+
+    #[test]
+    fn test_statement_unclosed_rung() {
+        let source_code = "TASK<CONTINUOUS> task\nROUTINE Main\nRUNG first\nENDRUNG\nRUNG second\nENDROUTINE\nENDTASK".to_string();
+        let mut par = Parser::new(Lexer::new(source_code.clone()), Emitter::new("test.out"), CompilerOptions::default());
+        assert_eq!(ParseError::StructuralMismatch("Missing matching ENDRUNG for Rung second on line 5".to_string()),
+                   first_error(&mut par));
+
+        let source_code = "TASK<CONTINUOUS> task\nROUTINE Main\nRUNG\n".to_string();
+        let mut par = Parser::new(Lexer::new(source_code.clone()), Emitter::new("test.out"), CompilerOptions::default());
+        assert_eq!(3, par.program().unwrap_err().len());
+    }
+
+    #[test]
+    #[should_panic(expected="Output can't be written before the program compiles successfully")]
+    fn test_write_output_incomplete() {
+        let source_code = "TAG myTag = TRUE".to_string();
+        let par = Parser::new(Lexer::new(source_code.clone()), Emitter::new("test.out"), CompilerOptions::default());
+        par.write_output();
     }
 
     #[test]
     fn test_statement_instructions() {
         let source_code = "XIC tag\nXIO tag\nOTE tag\nOTL tag\nOTU tag\nJSR routine\nEMIT event\nRET".to_string();
-        let mut par = Parser::new(Lexer::new(source_code.clone()), Emitter::new("test.out"));
+        let mut par = Parser::new(Lexer::new(source_code.clone()), Emitter::new("test.out"), CompilerOptions::default());
+        par.stack.push(enclosing(TokenType::Rung));
 
         // Add tag to the symbols to avoid errors
-        par.tags.push(TagDescriptor {
-            name: "tag".to_string(),
-            length: 0
-        });
+        par.tags.insert("tag".to_string(), TagDescriptor { kind: TagKind::Bool, line: 0 });
 
         // Event  and routine must exist
-        par.routines.push("routine".to_string());
-        par.events.push("event".to_string());
+        par.routines.insert("routine".to_string(), vec![(String::new(), 0)]);
+        par.events.insert("event".to_string(), 0);
+
+        parse_statements(&mut par);
+    }
+
+    #[test]
+    fn test_statement_instruction_outside_rung() {
+        let source_code = "TAG myTag = FALSE\nXIC myTag".to_string();
+        let mut par = Parser::new(Lexer::new(source_code.clone()), Emitter::new("test.out"), CompilerOptions::default());
+        let error = first_error(&mut par);
+        assert_eq!("Instruction XIC must be inside of a rung, but was found at file scope", error.to_string());
+        assert_eq!(Some((2, 1)), error.location());
+
+        let source_code = "TAG myTag = FALSE\nTASK<CONTINUOUS> task\nROUTINE Main\n  OTE myTag".to_string();
+        let mut par = Parser::new(Lexer::new(source_code.clone()), Emitter::new("test.out"), CompilerOptions::default());
+        let error = first_error(&mut par);
+        assert_eq!("Instruction OTE must be inside of a rung, but was found directly inside Routine Main on line 3", error.to_string());
+        assert_eq!(Some((4, 3)), error.location());
+    }
+
+    #[test]
+    fn test_statement_contradiction() {
+        let source_code = "TAG door = FALSE\nTAG motor = FALSE\nTAG[4] array = FALSE
+TASK<CONTINUOUS> task
+ROUTINE Main
+RUNG
+XIC door
+XIO door
+XIO door
+OTE motor
+ENDRUNG
+RUNG
+XIC array.1
+XIO array.2
+ENDRUNG
+RUNG
+XIO array.3
+XIC array.3
+ENDRUNG
+RUNG
+XIC door
+ENDRUNG
+ENDROUTINE
+ENDTASK".to_string();
+        let mut par = Parser::new(Lexer::new(source_code.clone()), Emitter::new("test.out"), CompilerOptions::default());
+        par.program().unwrap();
+
+        assert_eq!(par.warnings, ["Rung condition is always false because door is examined by both XIC and XIO",
+                                  "Rung condition is always false because array.3 is examined by both XIC and XIO"]);
+    }
+
+    #[test]
+    fn test_statement_contradiction_branch() {
+        let source_code = "TAG a = FALSE\nTAG b = FALSE\nTAG motor = FALSE\nTASK<CONTINUOUS> task\nROUTINE Main
+RUNG\nBST\nXIC a\nNXB\nXIO a\nBND\nOTE motor\nENDRUNG
+RUNG\nBST\nXIC a\nXIC b\nNXB\nXIC a\nBND\nXIO b\nOTE motor\nENDRUNG
+RUNG\nBST\nXIC a\nNXB\nXIC a\nXIC b\nBND\nXIO a\nOTE motor\nENDRUNG
+RUNG\nXIC a\nBST\nXIO a\nNXB\nXIC b\nBND\nOTE motor\nENDRUNG
+ENDROUTINE\nENDTASK".to_string();
+        let mut par = Parser::new(Lexer::new(source_code), Emitter::new("test.out"), CompilerOptions::default());
+        par.program().unwrap();
+
+        // Legs of a branch are ORed, so only what every leg examines holds after the BND
+        assert_eq!(par.warnings, ["Rung condition is always false because a is examined by both XIC and XIO",
+                                  "Branch leg is always false because a is examined by both XIC and XIO"]);
+    }
+
+    #[test]
+    fn test_statement_input_after_output() {
+        let source_code = "TAG motor = FALSE\nTASK<CONTINUOUS> task\nROUTINE Main\nRUNG\nOTE motor\nXIC motor".to_string();
+        let mut par = Parser::new(Lexer::new(source_code.clone()), Emitter::new("test.out"), CompilerOptions::default());
+        assert_eq!(ParseError::InputAfterOutput(TokenType::Xic), first_error(&mut par));
+        assert_eq!("Input instruction Xic appears after an output instruction", ParseError::InputAfterOutput(TokenType::Xic).to_string());
+    }
+
+    #[test]
+    fn test_undefined_reference_order() {
+        // Undefined references are reported in the order they appear, whatever order the symbols are stored in
+        let source_code = "TAG run = FALSE
+TASK<CONTINUOUS> task
+ROUTINE Main
+RUNG
+XIC run
+JSR zeta
+EMIT omega
+JSR alpha
+EMIT beta
+ENDRUNG
+ENDROUTINE
+ENDTASK".to_string();
+        let mut par = Parser::new(Lexer::new(source_code.clone()), Emitter::new("test.out"), CompilerOptions::default());
+        let expected = vec![
+            Diagnostic::new(ParseError::UndefinedRoutine("zeta".to_string()), Some(6)),
+            Diagnostic::new(ParseError::UndefinedEvent("omega".to_string()), Some(7)),
+            Diagnostic::new(ParseError::UndefinedRoutine("alpha".to_string()), Some(8)),
+            Diagnostic::new(ParseError::UndefinedEvent("beta".to_string()), Some(9))
+        ];
+        assert_eq!(expected, par.program().unwrap_err());
+
+        // Errors found once the program is parsed count towards the limit like any other
+        let options = CompilerOptions { max_errors: Some(2), ..Default::default() };
+        let mut par = Parser::new(Lexer::new(source_code.replace("XIC run", "XIC missing")), Emitter::new("test.out"), options);
+        assert_eq!(vec![Some(5), Some(6)], par.program().unwrap_err().iter().map(|diagnostic| diagnostic.line).collect::<Vec<_>>());
+        assert!(par.error_limit_reached());
+    }
+
+    #[test]
+    fn test_large_symbol_tables() {
+        // Diagnostics come out in source order however the symbols are stored. The lines are
+        // worked out from the layout of the program: one line per tag, then four per rung
+        let tag_count = 2_000;
+        let routine_line = tag_count + 2;
+        let mut source_code = String::new();
+        let mut expected = Vec::new();
+        for tag in 0..tag_count {
+            source_code += &format!("TAG t{} = FALSE\n", tag);
+        }
+        source_code += "TASK<CONTINUOUS> task\nROUTINE Main\n";
+        for rung in 0..tag_count {
+            let line = routine_line + rung * 4 + 1;
+            let input = if rung % 250 == 0 {
+                expected.push(Diagnostic::new(ParseError::UndefinedTag(format!("m{}", rung)), Some(line + 1)));
+                format!("m{}", rung)
+            } else {
+                format!("t{}", rung)
+            };
+            let output = if rung % 400 == 0 {
+                expected.push(Diagnostic::new(ParseError::UndefinedRoutine(format!("r{}", rung)), Some(line + 2)));
+                format!("JSR r{}", rung)
+            } else if rung % 300 == 0 {
+                expected.push(Diagnostic::new(ParseError::UndefinedEvent(format!("e{}", rung)), Some(line + 2)));
+                format!("EMIT e{}", rung)
+            } else {
+                format!("OTE t{}", (rung * 7) % tag_count)
+            };
+            source_code += &format!("RUNG\nXIC {}\n{}\nENDRUNG\n", input, output);
+        }
+        source_code += "ENDROUTINE\nENDTASK";
+
+        let mut par = Parser::new(Lexer::new(source_code), Emitter::new("test.out"), CompilerOptions::default());
+        assert_eq!(expected, par.program().unwrap_err());
+    }
+
+    #[test]
+    #[ignore = "benchmark, run with cargo test --release -- --ignored"]
+    fn bench_large_symbol_tables() {
+        // 50k tags and 100k references to them. Looking tags up by scanning every declaration
+        // took about 30 seconds for this program
+        let tag_count = 50_000;
+        let mut source_code = String::new();
+        for tag in 0..tag_count {
+            source_code += &format!("TAG t{} = FALSE\n", tag);
+        }
+        source_code += "TASK<CONTINUOUS> task\nROUTINE Main\n";
+        for rung in 0..tag_count {
+            source_code += &format!("RUNG\nXIC t{}\nOTE t{}\nENDRUNG\n", rung, (rung * 7) % tag_count);
+        }
+        source_code += "ENDROUTINE\nENDTASK";
+
+        let options = CompilerOptions { max_output_size: None, ..Default::default() };
+        let mut par = Parser::new(Lexer::new(source_code), Emitter::new("test.out"), options);
+        let start = std::time::Instant::now();
+        par.program().unwrap();
+        let elapsed = start.elapsed();
+        println!("Compiled {} tags and {} references in {:?}", tag_count, tag_count * 2, elapsed);
+        assert!(elapsed < std::time::Duration::from_secs(5), "{:?}", elapsed);
+    }
+
+    #[test]
+    fn test_statement_temp() {
+        let source_code = "TAG motor = FALSE
+TASK<CONTINUOUS> task
+ROUTINE Main
+RUNG
+TEMP scratch
+XIC scratch
+OTE motor
+OTL scratch
+ENDRUNG
+RUNG
+TEMP scratch
+TEMP motor
+XIC motor
+OTE scratch
+ENDRUNG
+ENDROUTINE
+ENDTASK".to_string();
+        let mut par = Parser::new(Lexer::new(source_code.clone()), Emitter::new("test.out"), CompilerOptions::default());
+        par.program().unwrap();
+        assert_eq!(vec!["TEMP motor hides the tag motor within its rung"], par.warnings);
+
+        // Temps are neither tags nor part of the cross reference
+        assert!(!par.tags.contains_key("scratch"));
+        assert_eq!("motor\n", par.cross_reference().impact("motor", 10).to_text());
+    }
+
+    #[test]
+    fn test_statement_temp_out_of_scope() {
+        let source_code = "TAG motor = FALSE
+TASK<CONTINUOUS> task
+ROUTINE Main
+RUNG
+TEMP scratch
+ENDRUNG
+RUNG
+XIC scratch
+OTE motor
+ENDRUNG
+ENDROUTINE
+ENDTASK".to_string();
+        let mut par = Parser::new(Lexer::new(source_code.clone()), Emitter::new("test.out"), CompilerOptions::default());
+        assert_eq!(ParseError::TempOutOfScope("scratch".to_string()), first_error(&mut par));
+
+        let source_code = "TEMP scratch".to_string();
+        let mut par = Parser::new(Lexer::new(source_code.clone()), Emitter::new("test.out"), CompilerOptions::default());
+        assert_eq!(ParseError::StructuralMismatch("TEMP tags must be declared inside of a rung".to_string()), first_error(&mut par));
+    }
+
+    #[test]
+    fn test_event_namespace_qualified() {
+        let source_code = "TAG jam = FALSE
+TASK<CONTINUOUS> Cell
+ROUTINE Main
+RUNG
+XIC jam
+EMIT cell3.jamEvt
+ENDRUNG
+ENDROUTINE
+ENDTASK
+TASK<EVENT=cell3.jamEvt> Jam
+ROUTINE Main
+ENDROUTINE
+ENDTASK".to_string();
+        let mut par = Parser::new(Lexer::new(source_code.clone()), Emitter::new("test.out"), CompilerOptions::default());
+        par.program().unwrap();
+
+        assert_eq!(HashMap::from([("cell3.jamEvt".to_string(), 10)]), par.events);
+        assert_eq!([("cell3.jamEvt".to_string(), 6)], par.emitted_events.as_slice());
+        assert!(par.cross_reference().impact("jam", 10).to_text().contains("task Jam"));
+    }
+
+    #[test]
+    fn test_event_namespace_block() {
+        let source_code = "EVENTSPACE cell3
+TASK<EVENT=jamEvt> Jam
+ROUTINE Main
+ENDROUTINE
+ENDTASK
+ENDEVENTSPACE
+
+EVENTSPACE cell4
+TASK<EVENT=jamEvt> Jam4
+ROUTINE Main
+RUNG
+EMIT jamEvt
+EMIT cell3.jamEvt
+ENDRUNG
+ENDROUTINE
+ENDTASK
+ENDEVENTSPACE".to_string();
+        let mut par = Parser::new(Lexer::new(source_code.clone()), Emitter::new("test.out"), CompilerOptions::default());
+        par.program().unwrap();
+
+        // Qualified names reach across namespaces
+        assert_eq!(HashMap::from([("cell3.jamEvt".to_string(), 2), ("cell4.jamEvt".to_string(), 9)]), par.events);
+        assert_eq!([("cell4.jamEvt".to_string(), 12), ("cell3.jamEvt".to_string(), 13)], par.emitted_events.as_slice());
+
+        let source_code = "EVENTSPACE cell3\nTASK<EVENT=jamEvt> Jam\nROUTINE Main\nENDROUTINE\nENDTASK\n".to_string();
+        let mut par = Parser::new(Lexer::new(source_code.clone()), Emitter::new("test.out"), CompilerOptions::default());
+        assert_eq!(ParseError::StructuralMismatch("Unexpected end of file. EventSpace cell3 on line 1 is not closed".to_string()),
+                   first_error(&mut par));
+
+        let source_code = "EVENTSPACE cell3\nTASK<CONTINUOUS> task\nEVENTSPACE cell4".to_string();
+        let mut par = Parser::new(Lexer::new(source_code.clone()), Emitter::new("test.out"), CompilerOptions::default());
+        assert_eq!(ParseError::StructuralMismatch("EVENTSPACE blocks may only be opened outside of other structures".to_string()),
+                   first_error(&mut par));
+    }
+
+    #[test]
+    fn test_event_namespace_indexer() {
+        let source_code = "TAG[4] jam = FALSE\nXIC jam.2\nEMIT cell3.jamEvt".to_string();
+        let mut par = Parser::new(Lexer::new(source_code.clone()), Emitter::new("test.out"), CompilerOptions::default());
+        par.stack.push(enclosing(TokenType::Rung));
+        parse_statements(&mut par);
+        assert_eq!([("cell3.jamEvt".to_string(), 3)], par.emitted_events.as_slice());
+
+        // Only a dot written against the name qualifies it
+        let source_code = "EMIT cell3 .jamEvt".to_string();
+        let mut par = Parser::new(Lexer::new(source_code.clone()), Emitter::new("test.out"), CompilerOptions::default());
+        par.stack.push(enclosing(TokenType::Rung));
+        assert_eq!("Unexpected extra operand '.'. EMIT takes exactly one operand (event)", first_error(&mut par).to_string());
+
+        let source_code = "EMIT cell3. jamEvt".to_string();
+        let mut par = Parser::new(Lexer::new(source_code.clone()), Emitter::new("test.out"), CompilerOptions::default());
+        par.stack.push(enclosing(TokenType::Rung));
+        assert_eq!("Qualified event name must be written as a single word, e.g. cell3.jamEvt", first_error(&mut par).to_string());
+    }
+
+    #[test]
+    fn test_event_namespace_collision() {
+        let source_code = "TASK<EVENT=jamEvt> Jam
+ROUTINE Main
+ENDROUTINE
+ENDTASK
+EVENTSPACE cell3
+TASK<EVENT=jamEvt> Jam3
+ROUTINE Main
+ENDROUTINE
+ENDTASK
+ENDEVENTSPACE".to_string();
+        let mut par = Parser::new(Lexer::new(source_code.clone()), Emitter::new("test.out"), CompilerOptions::default());
+        assert_eq!(ParseError::EventCollision { qualified: "cell3.jamEvt".to_string(), unqualified: "jamEvt".to_string() },
+                   first_error(&mut par));
+    }
+
+    #[test]
+    fn test_statement_timer() {
+        let source_code = "TAG start = FALSE
+TAG motor = FALSE
+TIMER delay = 500
+TASK<CONTINUOUS> task
+ROUTINE Main
+RUNG
+XIC start
+TON delay
+ENDRUNG
+RUNG
+XIC delay.DN
+OTE motor
+ENDRUNG
+ENDROUTINE
+ENDTASK".to_string();
+        let mut par = Parser::new(Lexer::new(source_code.clone()), Emitter::new("test.out"), CompilerOptions::default());
+        par.program().unwrap();
+        assert_eq!(TagKind::Timer, par.tags["delay"].kind);
+        // TON drives the done bit, which the second rung reads
+        assert!(par.cross_reference().impact("start", 10).to_text().contains("motor"));
+    }
+
+    #[test]
+    fn test_statement_timer_errors() {
+        let errors = [
+            ("TIMER delay = 0", "Preset 0 of timer delay must be a whole number of milliseconds greater than zero"),
+            ("TIMER delay = 1.5", "Preset 1.5 of timer delay must be a whole number of milliseconds greater than zero"),
+            ("TIMER delay = 500\nXIC delay", "Timer delay is examined through its done bit, e.g. delay.DN"),
+            ("TIMER delay = 500\nXIC delay.EN", "Timer delay is examined through its done bit, e.g. delay.DN"),
+            ("TIMER delay = 500\nOTE delay.DN", "delay.DN is set by its timer or counter and can't be written by OTE"),
+            ("TAG start = FALSE\nTON start", "start is not a TIMER tag"),
+            ("TON delay", "Referencing tag delay before assignment"),
+            ("XIC delay.DN", "Referencing tag delay before assignment")
+        ];
+        for (source_code, error) in errors {
+            let mut par = Parser::new(Lexer::new(source_code.to_string()), Emitter::new("test.out"), CompilerOptions::default());
+            par.stack.push(enclosing(TokenType::Rung));
+            assert_eq!(error, first_error(&mut par).to_string(), "{}", source_code);
+        }
+    }
+
+    #[test]
+    fn test_statement_int() {
+        let source_code = "TAG start = FALSE
+INT count = 0
+INT total = 10
+TASK<CONTINUOUS> task
+ROUTINE Main
+RUNG
+XIC start
+MOV 5 count
+ADD count total total
+SUB 100 count count
+ENDRUNG
+ENDROUTINE
+ENDTASK".to_string();
+        let mut par = Parser::new(Lexer::new(source_code.clone()), Emitter::new("test.out"), CompilerOptions::default());
+        par.program().unwrap();
+        assert_eq!(TagKind::Int, par.tags["count"].kind);
+        assert_eq!(Some(&1), par.metrics().instructions.get("ADD"));
+    }
+
+    #[test]
+    fn test_statement_int_errors() {
+        let errors = [
+            ("INT count = 1.5", "Initial value 1.5 of INT count must be a whole number"),
+            ("INT count = 0\nMOV 1.5 count", "Operand 1.5 must be a whole number"),
+            ("INT count = 0\nTAG start = FALSE\nADD count start count", "start is not an INT tag"),
+            ("INT count = 0\nTAG start = FALSE\nMOV count start", "start is not an INT tag"),
+            ("TAG[2] a = FALSE\nTAG[2] b = FALSE\nMOV a b",
+             "a is not an INT tag. Tag arrays can't be compared, moved or used in arithmetic as a whole"),
+            ("TIMER delay = 500\nINT count = 0\nMOV delay count", "delay is not an INT tag"),
+            ("INT count = 0\nXIC count", "count is not a BOOL tag"),
+            ("MOV 1 count", "Referencing tag count before assignment")
+        ];
+        for (source_code, error) in errors {
+            let mut par = Parser::new(Lexer::new(source_code.to_string()), Emitter::new("test.out"), CompilerOptions::default());
+            par.stack.push(enclosing(TokenType::Rung));
+            assert_eq!(error, first_error(&mut par).to_string(), "{}", source_code);
+        }
+
+        // Literals can't be written to
+        let mut par = Parser::new(Lexer::new("INT count = 0\nMOV count 5".to_string()), Emitter::new("test.out"), CompilerOptions::default());
+        par.stack.push(enclosing(TokenType::Rung));
+        assert!(matches!(first_error(&mut par), ParseError::UnexpectedToken { expected: TokenType::Identifier, .. }));
+    }
+
+    #[test]
+    fn test_statement_comparisons() {
+        let source_code = "INT count = 0
+INT limit = 10
+TAG full = FALSE
+TASK<CONTINUOUS> task
+ROUTINE Main
+RUNG
+GRT count limit
+NEQ count 0
+OTE full
+ENDRUNG
+RUNG
+XIC full
+LES 3 count
+EQU count limit
+MOV 0 count
+ENDRUNG
+ENDROUTINE
+ENDTASK".to_string();
+        let mut par = Parser::new(Lexer::new(source_code.clone()), Emitter::new("test.out"), CompilerOptions::default());
+        par.program().unwrap();
+        // Comparisons read their tags like any other input
+        assert!(par.cross_reference().impact("limit", 10).to_text().contains("full"));
+    }
+
+    #[test]
+    fn test_statement_comparison_errors() {
+        let errors = [
+            ("TAG[2] alarms = FALSE\nEQU alarms 1",
+             "alarms is not an INT tag. Tag arrays can't be compared, moved or used in arithmetic as a whole"),
+            ("TAG start = FALSE\nINT count = 0\nGRT count start", "start is not an INT tag"),
+            ("INT count = 0\nLES count 2.5", "Operand 2.5 must be a whole number"),
+            ("INT count = 0\nTAG motor = FALSE\nOTE motor\nEQU count 1", "Input instruction Equ appears after an output instruction"),
+            ("NEQ count 1", "Referencing tag count before assignment")
+        ];
+        for (source_code, error) in errors {
+            let mut par = Parser::new(Lexer::new(source_code.to_string()), Emitter::new("test.out"), CompilerOptions::default());
+            par.stack.push(enclosing(TokenType::Rung));
+            assert_eq!(error, first_error(&mut par).to_string(), "{}", source_code);
+        }
+    }
+
+    #[test]
+    fn test_statement_one_shot() {
+        let source_code = "TAG start = FALSE
+TAG pulse = FALSE
+TAG motor = FALSE
+TASK<CONTINUOUS> task
+ROUTINE Main
+RUNG
+XIC start
+ONS pulse
+OTL motor
+ENDRUNG
+RUNG
+XIO start
+ONS pulse
+OTU motor
+ENDRUNG
+ENDROUTINE
+ENDTASK".to_string();
+        let mut par = Parser::new(Lexer::new(source_code.clone()), Emitter::new("test.out"), CompilerOptions::default());
+        par.program().unwrap();
+        assert_eq!(vec!["ONS on line 13 uses pulse, which the ONS on line 8 also uses. They will interfere with each other"],
+                   par.warnings);
+    }
+
+    #[test]
+    fn test_statement_one_shot_errors() {
+        let errors = [
+            ("TEMP pulse\nONS pulse", "ONS can't use TEMP pulse, which doesn't keep its value between scans"),
+            ("INT count = 0\nONS count", "count is not a BOOL tag"),
+            ("TIMER delay = 500\nONS delay.DN", "delay.DN is set by its timer or counter and can't be written by ONS"),
+            ("ONS pulse", "Referencing tag pulse before assignment")
+        ];
+        for (source_code, error) in errors {
+            let mut par = Parser::new(Lexer::new(source_code.to_string()), Emitter::new("test.out"), CompilerOptions::default());
+            par.stack.push(enclosing(TokenType::Rung));
+            assert_eq!(error, first_error(&mut par).to_string(), "{}", source_code);
+        }
+    }
+
+    #[test]
+    fn test_statement_branches() {
+        let source_code = "TAG a = FALSE
+TAG b = FALSE
+TAG c = FALSE
+TAG motor = FALSE
+TASK<CONTINUOUS> task
+ROUTINE Main
+RUNG
+BST
+XIC a
+NXB
+BST
+XIC b
+NXB
+XIO c
+BND
+BND
+OTE motor
+ENDRUNG
+ENDROUTINE
+ENDTASK".to_string();
+        let mut par = Parser::new(Lexer::new(source_code.clone()), Emitter::new("test.out"), CompilerOptions::default());
+        par.program().unwrap();
+        assert!(par.cross_reference().impact("c", 10).to_text().contains("motor"));
+    }
+
+    #[test]
+    fn test_statement_jumps() {
+        let source_code = "TAG stop = FALSE
+TAG motor = FALSE
+TASK<PERIOD=100> cell
+ROUTINE Main
+RUNG
+LBL top
+XIC stop
+JMP skip
+ENDRUNG
+RUNG
+XIO stop
+JMP top
+ENDRUNG
+RUNG
+LBL skip
+OTE motor
+ENDRUNG
+ENDROUTINE
+ENDTASK".to_string();
+        let mut par = Parser::new(Lexer::new(source_code.clone()), Emitter::new("test.out"), CompilerOptions::default());
+        par.program().unwrap();
+    }
+
+    #[test]
+    fn test_statement_jump_errors() {
+        let routine = |body: &str| format!("TAG stop = FALSE\nTASK<PERIOD=100> cell\nROUTINE Main\n{}\nENDROUTINE\nENDTASK", body);
+
+        let mut par = Parser::new(Lexer::new(routine("RUNG\nLBL top\nENDRUNG\nRUNG\nLBL top\nENDRUNG")), Emitter::new("test.out"),
+                                  CompilerOptions::default());
+        let error = first_error(&mut par);
+        assert_eq!(ParseError::DuplicateLabel { label: "top".to_string(), routine: "Main".to_string(), first_line: 5, line: 8 }, error);
+        assert_eq!("Label top is defined more than once in routine Main, on lines 5 and 8", error.to_string());
+
+        let mut par = Parser::new(Lexer::new(routine("RUNG\nJMP skip\nENDRUNG")), Emitter::new("test.out"), CompilerOptions::default());
+        assert_eq!("JMP on line 5 targets label skip, which routine Main doesn't define. Jumps can only go to labels of their own routine",
+                   first_error(&mut par).to_string());
+
+        // Labels belong to their routine
+        let source_code = "TASK<PERIOD=100> cell\nROUTINE Main\nRUNG\nJSR Helper\nJMP skip\nENDRUNG\nENDROUTINE
+ROUTINE Helper\nRUNG\nLBL skip\nENDRUNG\nENDROUTINE\nENDTASK";
+        let mut par = Parser::new(Lexer::new(source_code.to_string()), Emitter::new("test.out"), CompilerOptions::default());
+        assert!(matches!(first_error(&mut par), ParseError::UndefinedLabel { line: 5, .. }));
+
+        for body in ["RUNG\nXIC stop\nLBL top\nENDRUNG", "RUNG\nTEMP t\nLBL top\nENDRUNG", "RUNG\nBST\nLBL top\nBND\nENDRUNG"] {
+            let mut par = Parser::new(Lexer::new(routine(body)), Emitter::new("test.out"), CompilerOptions::default());
+            assert_eq!("LBL must be the first instruction of its rung", first_error(&mut par).to_string(), "{}", body);
+        }
+    }
+
+    #[test]
+    fn test_statement_branch_errors() {
+        let errors = [
+            ("TAG a = FALSE\nBST\nXIC a\nENDRUNG", "Missing matching BND for BST on line 2"),
+            ("TAG a = FALSE\nBST\nXIC a\nBST\nNXB\nBND\nENDRUNG", "Missing matching BND for BST on line 2"),
+            ("NXB", "NXB must be inside of a branch opened by BST"),
+            ("BST\nBND\nBND", "BND must be inside of a branch opened by BST"),
+            ("TAG a = FALSE\nBST\nOTE a", "Output instruction OTE can't be inside of a branch. Close the branch with BND first"),
+            ("TAG a = FALSE\nOTE a\nBST", "BST must come before the output instructions of its rung")
+        ];
+        for (source_code, error) in errors {
+            let mut par = Parser::new(Lexer::new(source_code.to_string()), Emitter::new("test.out"), CompilerOptions::default());
+            par.stack.push(enclosing(TokenType::Rung));
+            assert_eq!(error, first_error(&mut par).to_string(), "{}", source_code);
+        }
+
+        let mut par = Parser::new(Lexer::new("BST".to_string()), Emitter::new("test.out"), CompilerOptions::default());
+        par.stack.push(enclosing(TokenType::Routine));
+        assert_eq!("BST must be inside of a rung", first_error(&mut par).to_string());
+    }
+
+    #[test]
+    fn test_trailing_tokens() {
+        let errors = [
+            ("TAG motor = FALSE\nOTE motor extraJunk", "Unexpected extra operand 'extraJunk'. OTE takes exactly one operand (bool tag)", 2, 11),
+            ("INT count = 0\nADD count 1 count 2", "Unexpected extra operand '2'. ADD takes exactly 3 operands (int value, int value, int tag)", 2, 19),
+            ("RET now", "Unexpected extra operand 'now'. RET takes no operands", 1, 5),
+            ("TAG myTag = TRUE FALSE", "Unexpected 'FALSE' after the initial value. TAG takes a single initial value, e.g. TAG motor = FALSE", 1, 18),
+            ("ENDRUNG\nRUNG name1 name2", "Unexpected 'name2' after the rung name. A rung takes at most one name", 2, 12),
+            ("ENDRUNG later", "Unexpected 'later' at the end of the ENDRUNG statement", 1, 9)
+        ];
+        for (source_code, error, line, column) in errors {
+            let mut par = Parser::new(Lexer::new(source_code.to_string()), Emitter::new("test.out"), CompilerOptions::default());
+            par.stack.push(enclosing(TokenType::Routine));
+            par.stack.push(enclosing(TokenType::Rung));
+            let error_found = first_error(&mut par);
+            assert_eq!(error, error_found.to_string(), "{}", source_code);
+            assert_eq!(Some((line, column)), error_found.location(), "{}", source_code);
+        }
+    }
 
-        par.program();
+    #[test]
+    fn test_statement_counter() {
+        let source_code = "TAG sensor = FALSE
+TAG full = FALSE
+COUNTER parts = 10
+TASK<CONTINUOUS> task
+ROUTINE Main
+RUNG
+XIC sensor
+CTU parts
+ENDRUNG
+RUNG
+XIC parts.DN
+OTE full
+RES parts
+ENDRUNG
+ENDROUTINE
+ENDTASK".to_string();
+        let mut par = Parser::new(Lexer::new(source_code.clone()), Emitter::new("test.out"), CompilerOptions::default());
+        par.program().unwrap();
+        assert_eq!(TagKind::Counter, par.tags["parts"].kind);
+        assert!(par.cross_reference().impact("sensor", 10).to_text().contains("full"));
+    }
+
+    #[test]
+    fn test_statement_counter_errors() {
+        let errors = [
+            ("COUNTER parts = 0", "Preset 0 of counter parts must be a whole number greater than zero"),
+            ("COUNTER parts = 10\nXIC parts", "Counter parts is examined through its done bit, e.g. parts.DN"),
+            ("COUNTER parts = 10\nOTL parts.DN", "parts.DN is set by its timer or counter and can't be written by OTL"),
+            ("TIMER delay = 500\nCTU delay", "delay is not a COUNTER tag"),
+            ("COUNTER parts = 10\nTON parts", "parts is not a TIMER tag"),
+            ("RES parts", "Referencing tag parts before assignment")
+        ];
+        for (source_code, error) in errors {
+            let mut par = Parser::new(Lexer::new(source_code.to_string()), Emitter::new("test.out"), CompilerOptions::default());
+            par.stack.push(enclosing(TokenType::Rung));
+            assert_eq!(error, first_error(&mut par).to_string(), "{}", source_code);
+        }
     }
 
     #[test]
     fn test_statement_end() {
         let source_code = "TASK<CONTINUOUS> task\nROUTINE Main\nRUNG\nENDRUNG\nENDROUTINE\nENDTASK".to_string();
-        let mut par = Parser::new(Lexer::new(source_code.clone()), Emitter::new("test.out"));
+        let mut par = Parser::new(Lexer::new(source_code.clone()), Emitter::new("test.out"), CompilerOptions::default());
 
-        par.program();
+        par.program().unwrap();
+    }
+
+    #[test]
+    fn test_output_size_budget() {
+        let mut source_code = "TAG myTag = FALSE\nTASK<CONTINUOUS> task\nROUTINE Main\nRUNG\nJSR big\nENDRUNG\nENDROUTINE\nROUTINE big\n".to_string();
+        for _ in 0..10 {
+            source_code += "RUNG\nXIC myTag\nOTE myTag\nENDRUNG\n";
+        }
+        source_code += "ENDROUTINE\nENDTASK";
+
+        let options = CompilerOptions { max_output_size: Some(300), ..Default::default() };
+        let mut par = Parser::new(Lexer::new(source_code.clone()), Emitter::new("test.out"), options);
+        let error = first_error(&mut par);
+        assert!(error.to_string().starts_with("Generated output exceeds the size budget of 300 bytes. Largest routines: big ("));
     }
 
     #[test]
     fn test_statement_tag_array_1() {
         let source_code = "TAG[10] array = FALSE\nOTE array.0".to_string();
-        let mut par = Parser::new(Lexer::new(source_code.clone()), Emitter::new("test.out"));
-        par.program();
+        let mut par = Parser::new(Lexer::new(source_code.clone()), Emitter::new("test.out"), CompilerOptions::default());
+        par.stack.push(enclosing(TokenType::Rung));
+        parse_statements(&mut par);
     }
 
     #[test]
-    #[should_panic(expected="Length of tag array must be greater than zero")]
     fn test_statement_tag_array_2() {
         let source_code = "TAG[0] array = FALSE".to_string();
-        let mut par = Parser::new(Lexer::new(source_code.clone()), Emitter::new("test.out"));
-        par.program();
+        let mut par = Parser::new(Lexer::new(source_code.clone()), Emitter::new("test.out"), CompilerOptions::default());
+        assert_eq!(ParseError::InvalidTag("Length of tag array must be greater than zero".to_string()), first_error(&mut par));
     }
 
     #[test]
-    #[should_panic]
     fn test_statement_tag_array_3() {
         let source_code = "TAG[10] array = FALSE\nOTE array".to_string();
-        let mut par = Parser::new(Lexer::new(source_code.clone()), Emitter::new("test.out"));
-        par.program();
+        let mut par = Parser::new(Lexer::new(source_code.clone()), Emitter::new("test.out"), CompilerOptions::default());
+        par.stack.push(enclosing(TokenType::Rung));
+        assert!(matches!(first_error(&mut par), ParseError::UnexpectedToken { expected: TokenType::Indexer, .. }));
     }
 
     #[test]
-    #[should_panic(expected="Referencing tag array before assignment")]
     fn test_statement_tag_array_4() {
         let source_code = "OTE array.2".to_string();
-        let mut par = Parser::new(Lexer::new(source_code.clone()), Emitter::new("test.out"));
-        par.program();
+        let mut par = Parser::new(Lexer::new(source_code.clone()), Emitter::new("test.out"), CompilerOptions::default());
+        par.stack.push(enclosing(TokenType::Rung));
+        assert_eq!(ParseError::UndefinedTag("array".to_string()), first_error(&mut par));
+    }
+
+    #[test]
+    fn test_statement_tag_array_spacing() {
+        let source_code = "TAG[10] array = FALSE\nOTE array . 2".to_string();
+        let mut par = Parser::new(Lexer::new(source_code.clone()), Emitter::new("test.out"), CompilerOptions::default());
+        par.stack.push(enclosing(TokenType::Rung));
+        assert_eq!(ParseError::InvalidIndex("Array index must directly follow the tag name, e.g. array.2".to_string()),
+                   first_error(&mut par));
     }
 
     #[test]
-    #[should_panic]
     fn test_statement_tag_array_5() {
         let source_code = "TAG[10] array = FALSE\nOTE array.10".to_string();
-        let mut par = Parser::new(Lexer::new(source_code.clone()), Emitter::new("test.out"));
-        par.program();
+        let mut par = Parser::new(Lexer::new(source_code.clone()), Emitter::new("test.out"), CompilerOptions::default());
+        par.stack.push(enclosing(TokenType::Rung));
+        assert_eq!(ParseError::InvalidIndex("Index 10 is out of bounds for tag array of length 10".to_string()), first_error(&mut par));
+    }
+
+    #[test]
+    fn test_statement_tag_array_tag_index() {
+        let source_code = "TAG[10] array = FALSE\nINT cursor = 0\nOTE array.cursor".to_string();
+        let mut par = Parser::new(Lexer::new(source_code.clone()), Emitter::new("test.out"), CompilerOptions::default());
+        par.stack.push(enclosing(TokenType::Rung));
+        parse_statements(&mut par);
+    }
+
+    #[test]
+    fn test_statement_tag_array_tag_index_undefined() {
+        let source_code = "TAG[10] array = FALSE\nOTE array.cursor".to_string();
+        let mut par = Parser::new(Lexer::new(source_code.clone()), Emitter::new("test.out"), CompilerOptions::default());
+        par.stack.push(enclosing(TokenType::Rung));
+        assert_eq!(ParseError::UndefinedTag("cursor".to_string()), first_error(&mut par));
+    }
+
+    #[test]
+    fn test_statement_tag_array_bool_index() {
+        let source_code = "TAG[10] array = FALSE\nTAG cursor = FALSE\nOTE array.cursor".to_string();
+        let mut par = Parser::new(Lexer::new(source_code.clone()), Emitter::new("test.out"), CompilerOptions::default());
+        par.stack.push(enclosing(TokenType::Rung));
+        assert_eq!(ParseError::InvalidIndex("Index cursor must be an INT tag or a whole number".to_string()), first_error(&mut par));
+    }
+
+    #[test]
+    fn test_statement_program() {
+        let source_code = "PROGRAM CellThree VERSION 2.4.1\nTAG start = FALSE".to_string();
+        let mut par = Parser::new(Lexer::new(source_code.clone()), Emitter::new("test.out"), CompilerOptions::default());
+        par.program().unwrap();
+        assert_eq!(Some(&("CellThree".to_string(), "2.4.1".to_string())), par.program_declaration());
+
+        let source_code = "PROGRAM CellThree VERSION 2".to_string();
+        let mut par = Parser::new(Lexer::new(source_code.clone()), Emitter::new("test.out"), CompilerOptions::default());
+        par.program().unwrap();
+        assert_eq!(Some(&("CellThree".to_string(), "2".to_string())), par.program_declaration());
+    }
+
+    #[test]
+    fn test_statement_program_duplicate() {
+        let source_code = "PROGRAM CellThree VERSION 2.4\nPROGRAM CellFour VERSION 1.0".to_string();
+        let mut par = Parser::new(Lexer::new(source_code.clone()), Emitter::new("test.out"), CompilerOptions::default());
+        assert_eq!(ParseError::InvalidProgram("There can only be one PROGRAM statement".to_string()), first_error(&mut par));
+    }
+
+    #[test]
+    fn test_statement_program_not_first() {
+        let source_code = "TAG start = FALSE\nPROGRAM CellThree VERSION 2.4".to_string();
+        let mut par = Parser::new(Lexer::new(source_code.clone()), Emitter::new("test.out"), CompilerOptions::default());
+        assert_eq!(ParseError::InvalidProgram("PROGRAM must be the first statement".to_string()), first_error(&mut par));
+    }
+
+    #[test]
+    fn test_statement_program_version_spacing() {
+        let source_code = "PROGRAM CellThree VERSION 2.4. 1".to_string();
+        let mut par = Parser::new(Lexer::new(source_code.clone()), Emitter::new("test.out"), CompilerOptions::default());
+        assert_eq!(ParseError::InvalidProgram("Version number can't contain spaces".to_string()), first_error(&mut par));
+    }
+
+    #[test]
+    fn test_statement_program_strict() {
+        let source_code = "TAG start = FALSE".to_string();
+        let options = CompilerOptions { strict: true, ..Default::default() };
+        let mut par = Parser::new(Lexer::new(source_code.clone()), Emitter::new("test.out"), options);
+        assert_eq!(ParseError::InvalidProgram("A PROGRAM statement is required in strict mode".to_string()), first_error(&mut par));
+    }
+
+    #[test]
+    fn test_policy() {
+        let source_code = "TASK<PERIOD=100> MainTask
+ROUTINE Main
+RUNG
+JSR First
+EMIT done
+ENDRUNG
+ENDROUTINE
+ROUTINE First
+RUNG
+JSR Second
+ENDRUNG
+ENDROUTINE
+ROUTINE Second
+ENDROUTINE
+ENDTASK
+TASK<EVENT=done> DoneTask
+ROUTINE Main
+ENDROUTINE
+ENDTASK".to_string();
+        let policy = Policy::parse("allowed_instructions = [\"JSR\"]\nmax_jsr_depth = 1").unwrap();
+        let options = CompilerOptions { policy: Some(policy), ..Default::default() };
+        let mut par = Parser::new(Lexer::new(source_code.clone()), Emitter::new("test.out"), options);
+        assert_eq!(ParseError::PolicyViolations(vec![
+            "Policy rule allowed_instructions: EMIT is not allowed on line 5".to_string(),
+            "Policy rule max_jsr_depth: task MainTask calls routines 2 deep, but at most 1 is allowed".to_string()
+        ]), first_error(&mut par));
+    }
+
+    #[test]
+    fn test_policy_task_kind() {
+        let source_code = "TASK<CONTINUOUS> MainTask\nROUTINE Main\nENDROUTINE\nENDTASK".to_string();
+        let policy = Policy::parse("banned_task_kinds = [\"CONTINUOUS\"]").unwrap();
+        let options = CompilerOptions { policy: Some(policy), ..Default::default() };
+        let mut par = Parser::new(Lexer::new(source_code.clone()), Emitter::new("test.out"), options);
+        assert_eq!(ParseError::PolicyViolations(vec!["Policy rule banned_task_kinds: CONTINUOUS tasks are not allowed on line 1".to_string()]),
+                   first_error(&mut par));
+    }
+
+    #[test]
+    fn test_policy_with_denied_warnings() {
+        // Denying warnings doesn't hide the policy violations
+        let source_code = "TASK<PERIOD=25> MainTask\nROUTINE Main\nENDROUTINE\nENDTASK".to_string();
+        let policy = Policy::parse("banned_task_kinds = [\"PERIOD\"]").unwrap();
+        let options = CompilerOptions { policy: Some(policy), scheduler_tick: Some(10), deny_warnings: true, ..Default::default() };
+        let mut par = Parser::new(Lexer::new(source_code), Emitter::new("test.out"), options);
+        assert_eq!(vec![
+            Diagnostic::new(ParseError::PolicyViolations(vec!["Policy rule banned_task_kinds: PERIOD tasks are not allowed on line 1".to_string()]), None),
+            Diagnostic::new(ParseError::WarningsDenied(1), None)
+        ], par.program().unwrap_err());
+    }
+
+    #[test]
+    fn test_entry_routine() {
+        // Each task has its own Main
+        let source_code = "TASK<PERIOD=100> First
+ROUTINE Main
+ENDROUTINE
+ENDTASK
+TASK<PERIOD=100> Second
+ROUTINE Main
+ENDROUTINE
+ENDTASK".to_string();
+        let mut par = Parser::new(Lexer::new(source_code.clone()), Emitter::new("test.out"), CompilerOptions::default());
+        par.program().unwrap();
+        assert!(par.warnings.is_empty());
+
+        // ENTRY takes over from a Main in the same task
+        let source_code = "TASK<PERIOD=100> First
+ROUTINE Main
+ENDROUTINE
+ROUTINE<ENTRY> Startup
+ENDROUTINE
+ENDTASK".to_string();
+        let mut par = Parser::new(Lexer::new(source_code.clone()), Emitter::new("test.out"), CompilerOptions::default());
+        par.program().unwrap();
+        assert_eq!(vec!["Task First starts with Startup because it is marked ENTRY, so Main only runs if it is called"],
+                   par.warnings);
+    }
+
+    #[test]
+    fn test_entry_routine_missing() {
+        let source_code = "TASK<PERIOD=100> cell2\nROUTINE Startup\nENDROUTINE\nENDTASK".to_string();
+        let mut par = Parser::new(Lexer::new(source_code.clone()), Emitter::new("test.out"), CompilerOptions::default());
+        assert_eq!(ParseError::InvalidEntryRoutine("Task cell2 has no entry routine. Name one Main or mark it ROUTINE<ENTRY>".to_string()),
+                   first_error(&mut par));
+    }
+
+    #[test]
+    fn test_entry_routine_duplicate() {
+        let source_code = "TASK<PERIOD=100> cell2
+ROUTINE<ENTRY> Startup
+ENDROUTINE
+ROUTINE<ENTRY> Restart
+ENDROUTINE
+ENDTASK".to_string();
+        let mut par = Parser::new(Lexer::new(source_code.clone()), Emitter::new("test.out"), CompilerOptions::default());
+        assert_eq!(ParseError::InvalidEntryRoutine("Task cell2 has more than one entry routine: Startup, Restart".to_string()),
+                   first_error(&mut par));
     }
 }
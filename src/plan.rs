@@ -0,0 +1,154 @@
+use std::fs;
+use std::path::{self, Path, PathBuf};
+
+use serde::Serialize;
+
+use crate::emitter::{self, STDOUT_PATH};
+use crate::schema::{Artifact, SchemaVersion};
+use crate::stats::HistoryEntry;
+
+/// What a build will do to one of its output files
+#[derive(Serialize, Debug, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Action {
+    Create,
+    Overwrite,
+    Append,
+    Stdout
+}
+
+/// A file or directory a build writes
+pub enum Target<'a> {
+    /// Compiled output, replaced as a whole
+    Output(&'a str),
+    /// Stats history, which every build adds a line to
+    StatsHistory(&'a str),
+    /// Directory of the cache entry the build stores its output in
+    CacheEntry(&'a Path)
+}
+
+#[derive(Serialize, Debug, PartialEq)]
+pub struct PlannedOutput {
+    path: String,
+    action: Action
+}
+
+/// Files a build would write, worked out without writing anything
+#[derive(Serialize, Debug, PartialEq)]
+pub struct Plan {
+    #[serde(flatten)]
+    schema: SchemaVersion,
+    outputs: Vec<PlannedOutput>,
+
+    /// Reasons the build would refuse to start
+    errors: Vec<String>
+}
+
+impl Plan {
+    pub fn new(targets: &[Target]) -> Plan {
+        let mut plan = Plan { schema: SchemaVersion::of(Artifact::Plan), outputs: Vec::new(), errors: Vec::new() };
+        let mut written = Vec::<PathBuf>::new();
+        for target in targets {
+            let path = match target {
+                Target::Output(output_file) if *output_file == STDOUT_PATH => {
+                    plan.outputs.push(PlannedOutput { path: output_file.to_string(), action: Action::Stdout });
+                    continue;
+                },
+                Target::Output(path) | Target::StatsHistory(path) => absolute(Path::new(path)),
+                Target::CacheEntry(path) => absolute(path)
+            };
+            let shown = path.to_string_lossy().to_string();
+
+            if written.contains(&path) {
+                plan.errors.push(format!("{} would be written more than once", shown));
+            }
+            if let Some(error) = foreign(target, &path) {
+                plan.errors.push(error);
+            }
+
+            let action = match target {
+                Target::StatsHistory(_) if path.exists() => Action::Append,
+                _ if path.exists() => Action::Overwrite,
+                _ => Action::Create
+            };
+            plan.outputs.push(PlannedOutput { path: shown, action });
+            written.push(path);
+        }
+        plan
+    }
+
+    pub fn errors(&self) -> &[String] {
+        &self.errors
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).unwrap()
+    }
+}
+
+fn absolute(path: &Path) -> PathBuf {
+    path::absolute(path).unwrap_or_else(|_| path.to_path_buf())
+}
+
+/// Reports a file at the path of a target that the compiler didn't write, which a build must leave alone
+fn foreign(target: &Target, path: &Path) -> Option<String> {
+    if !path.is_file() {
+        return None;
+    }
+
+    let text = fs::read_to_string(path).unwrap_or_else(|_| String::from("\0"));
+    match target {
+        Target::Output(_) if !text.is_empty() && !emitter::is_compiled_output(&text) =>
+            Some(format!("{} wasn't written by the compiler, so the build won't overwrite it", path.display())),
+        Target::StatsHistory(_) if text.lines()
+                                       .filter(|line| !line.trim().is_empty())
+                                       .any(|line| serde_json::from_str::<HistoryEntry>(line).is_err()) =>
+            Some(format!("{} isn't a stats history, so the build won't append to it", path.display())),
+        _ => None
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_plan_actions() {
+        let existing = std::env::temp_dir().join("logtext_plan_existing.out");
+        std::fs::write(&existing, "").unwrap();
+        let missing = std::env::temp_dir().join("logtext_plan_missing.out");
+        let _ = std::fs::remove_file(&missing);
+
+        let plan = Plan::new(&[Target::Output(existing.to_str().unwrap()), Target::StatsHistory(existing.to_str().unwrap()),
+                               Target::CacheEntry(&missing)]);
+        assert_eq!(Action::Overwrite, plan.outputs[0].action);
+        assert_eq!(Action::Append, plan.outputs[1].action);
+        assert_eq!(Action::Create, plan.outputs[2].action);
+        assert_eq!(vec![format!("{} would be written more than once", existing.display())], plan.errors);
+
+        // Relative paths are resolved against the working directory
+        let plan = Plan::new(&[Target::Output("Program.out"), Target::Output("-")]);
+        assert!(Path::new(&plan.outputs[0].path).is_absolute());
+        assert_eq!(PlannedOutput { path: "-".to_string(), action: Action::Stdout }, plan.outputs[1]);
+        assert!(plan.errors.is_empty());
+    }
+
+    #[test]
+    fn test_plan_foreign_files() {
+        let notes = std::env::temp_dir().join("logtext_plan_notes.txt");
+        std::fs::write(&notes, "hello\n").unwrap();
+        let notes = notes.to_str().unwrap();
+
+        let plan = Plan::new(&[Target::Output(notes)]);
+        assert_eq!(Action::Overwrite, plan.outputs[0].action);
+        assert_eq!(vec![format!("{} wasn't written by the compiler, so the build won't overwrite it", notes)], plan.errors);
+
+        let plan = Plan::new(&[Target::StatsHistory(notes)]);
+        assert_eq!(vec![format!("{} isn't a stats history, so the build won't append to it", notes)], plan.errors);
+
+        // Earlier output is overwritten without complaint
+        let output = std::env::temp_dir().join("logtext_plan_compiled.out");
+        std::fs::write(&output, "# Compiled by LogTextCompiler\n").unwrap();
+        assert!(Plan::new(&[Target::Output(output.to_str().unwrap())]).errors.is_empty());
+    }
+}
@@ -0,0 +1,106 @@
+use std::fs;
+
+use serde::Deserialize;
+
+use crate::header;
+
+/// Restrictions a program must meet to be built for a safety-certified deliverable
+#[derive(Deserialize, Default, Clone, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct Policy {
+    /// Instruction keywords that may be used. Every instruction is allowed if left out
+    allowed_instructions: Option<Vec<String>>,
+
+    /// Task types, e.g. CONTINUOUS, that may not be used
+    #[serde(default)]
+    banned_task_kinds: Vec<String>,
+
+    /// How many JSRs deep a task may call from its entry routine
+    max_jsr_depth: Option<usize>,
+
+    #[serde(skip)]
+    hash: u64
+}
+
+impl Policy {
+    pub fn load(path: &str) -> Result<Policy, String> {
+        let text = fs::read_to_string(path).map_err(|error| error.to_string())?;
+        Policy::parse(&text)
+    }
+
+    pub fn parse(text: &str) -> Result<Policy, String> {
+        let mut policy: Policy = toml::from_str(text).map_err(|error| format!("Invalid policy file: {}", error))?;
+        policy.hash = header::hash(text);
+        Ok(policy)
+    }
+
+    /// Hash of the policy file, so auditors can tell which policy a build enforced
+    pub fn hash(&self) -> u64 {
+        self.hash
+    }
+
+    pub fn check_instruction(&self, keyword: &str, line: usize) -> Option<String> {
+        let allowed = self.allowed_instructions.as_ref()?;
+        if allowed.iter().any(|instruction| instruction == keyword) {
+            return None;
+        }
+        Some(format!("Policy rule allowed_instructions: {} is not allowed on line {}", keyword, line))
+    }
+
+    pub fn check_task_kind(&self, kind: &str, line: usize) -> Option<String> {
+        if !self.banned_task_kinds.iter().any(|banned| banned == kind) {
+            return None;
+        }
+        Some(format!("Policy rule banned_task_kinds: {} tasks are not allowed on line {}", kind, line))
+    }
+
+    pub fn check_jsr_depth(&self, task: &str, depth: usize) -> Option<String> {
+        let max_depth = self.max_jsr_depth?;
+        if depth <= max_depth {
+            return None;
+        }
+        Some(format!("Policy rule max_jsr_depth: task {} calls routines {} deep, but at most {} is allowed",
+                     task, depth, max_depth))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const POLICY: &str = "allowed_instructions = [\"XIC\", \"XIO\", \"OTE\", \"JSR\"]
+banned_task_kinds = [\"CONTINUOUS\"]
+max_jsr_depth = 2
+";
+
+    #[test]
+    fn test_policy_rules() {
+        let policy = Policy::parse(POLICY).unwrap();
+
+        assert_eq!(None, policy.check_instruction("XIC", 3));
+        assert_eq!(Some("Policy rule allowed_instructions: EMIT is not allowed on line 3".to_string()),
+                   policy.check_instruction("EMIT", 3));
+
+        assert_eq!(None, policy.check_task_kind("PERIOD", 1));
+        assert!(policy.check_task_kind("CONTINUOUS", 1).is_some());
+
+        assert_eq!(None, policy.check_jsr_depth("MainTask", 2));
+        assert!(policy.check_jsr_depth("MainTask", 3).is_some());
+
+        assert_eq!(header::hash(POLICY), policy.hash());
+    }
+
+    #[test]
+    fn test_policy_defaults() {
+        let policy = Policy::parse("").unwrap();
+        assert_eq!(None, policy.check_instruction("EMIT", 1));
+        assert_eq!(None, policy.check_task_kind("CONTINUOUS", 1));
+        assert_eq!(None, policy.check_jsr_depth("MainTask", 100));
+    }
+
+    #[test]
+    fn test_policy_unknown_rule() {
+        assert!(Policy::parse("max_depth = 2").unwrap_err().starts_with("Invalid policy file: "));
+        assert!(Policy::load("missing_policy.toml").is_err());
+    }
+}
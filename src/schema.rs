@@ -0,0 +1,213 @@
+use serde::{Deserialize, Serialize};
+
+/// JSON files and outputs of the compiler, each described by a JSON Schema in the schemas directory
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq)]
+pub enum Artifact {
+    About,
+    Plan,
+    StatsHistory,
+    CacheDiagnostics,
+    LastWriter
+}
+
+impl Artifact {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Artifact::About => "about",
+            Artifact::Plan => "plan",
+            Artifact::StatsHistory => "stats-history",
+            Artifact::CacheDiagnostics => "cache-diagnostics",
+            Artifact::LastWriter => "last-writer"
+        }
+    }
+
+    /// Bumped whenever the schema changes, so that consumers pinned to a version notice
+    pub fn version(&self) -> u32 {
+        match self {
+            Artifact::About => 3,
+            Artifact::Plan => 2,
+            Artifact::StatsHistory => 1,
+            Artifact::CacheDiagnostics => 1,
+            Artifact::LastWriter => 1
+        }
+    }
+
+    pub fn id(&self) -> String {
+        format!("urn:logtextcompiler:schema:{}:{}", self.name(), self.version())
+    }
+
+    /// The JSON Schema document of the artifact
+    pub fn schema(&self) -> &'static str {
+        match self {
+            Artifact::About => include_str!("../schemas/about.json"),
+            Artifact::Plan => include_str!("../schemas/plan.json"),
+            Artifact::StatsHistory => include_str!("../schemas/stats-history.json"),
+            Artifact::CacheDiagnostics => include_str!("../schemas/cache-diagnostics.json"),
+            Artifact::LastWriter => include_str!("../schemas/last-writer.json")
+        }
+    }
+}
+
+/// Names the schema an artifact follows. Flattened into the top level of every artifact
+#[derive(Serialize, Deserialize, Debug, Default, PartialEq)]
+pub struct SchemaVersion {
+    // Older stats history lines were written before artifacts carried their schema
+    #[serde(default)]
+    schema_id: String,
+    #[serde(default)]
+    schema_version: u32
+}
+
+impl SchemaVersion {
+    pub fn of(artifact: Artifact) -> SchemaVersion {
+        SchemaVersion { schema_id: artifact.id(), schema_version: artifact.version() }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::BTreeMap;
+
+    use clap::ValueEnum;
+    use serde_json::Value;
+
+    use super::*;
+    use crate::{about::About, cache::Entry, plan::{Plan, Target}, stats::HistoryEntry, header, lexer::Lexer, emitter::Emitter,
+                options::CompilerOptions, parse::Parser};
+
+    /// Fingerprint of each schema at its current version. A schema can only change along with its version,
+    /// after which the new fingerprint goes here
+    const FINGERPRINTS: [(Artifact, u32, u64); 5] = [
+        (Artifact::About, 3, 0xc15adfdf9485992e),
+        (Artifact::Plan, 2, 0x930701c6041ef684),
+        (Artifact::StatsHistory, 1, 0x7366b5fee3078d7b),
+        (Artifact::CacheDiagnostics, 1, 0xd915c2f4dd844b69),
+        (Artifact::LastWriter, 1, 0x2e1fc074d281a6be)
+    ];
+
+    /// Checks a value against the parts of JSON Schema the schemas use, returning what doesn't match
+    fn validate(schema: &Value, value: &Value, path: &str) -> Vec<String> {
+        let mut errors = Vec::new();
+
+        if let Some(expected) = schema.get("type").and_then(Value::as_str) {
+            let matches = match expected {
+                "object" => value.is_object(),
+                "array" => value.is_array(),
+                "string" => value.is_string(),
+                "integer" => value.is_u64() || value.is_i64(),
+                "boolean" => value.is_boolean(),
+                _ => panic!("Unsupported type {} in schema", expected)
+            };
+            if !matches {
+                return vec![format!("{} is not of type {}", path, expected)];
+            }
+        }
+        if let Some(constant) = schema.get("const") {
+            if constant != value {
+                errors.push(format!("{} is {}, not {}", path, value, constant));
+            }
+        }
+        if let Some(Value::Array(values)) = schema.get("enum") {
+            if !values.contains(value) {
+                errors.push(format!("{} is {}, which isn't one of {:?}", path, value, values));
+            }
+        }
+        if let (Some(minimum), Some(number)) = (schema.get("minimum").and_then(Value::as_i64), value.as_i64()) {
+            if number < minimum {
+                errors.push(format!("{} is {}, below the minimum of {}", path, number, minimum));
+            }
+        }
+
+        if let Value::Object(object) = value {
+            for required in schema.get("required").and_then(Value::as_array).into_iter().flatten() {
+                if !object.contains_key(required.as_str().unwrap()) {
+                    errors.push(format!("{} is missing {}", path, required));
+                }
+            }
+            let properties = schema.get("properties").and_then(Value::as_object);
+            for (key, property) in object {
+                let property_path = format!("{}.{}", path, key);
+                match (properties.and_then(|properties| properties.get(key)), schema.get("additionalProperties")) {
+                    (Some(property_schema), _) => errors.extend(validate(property_schema, property, &property_path)),
+                    (None, Some(Value::Bool(false))) => errors.push(format!("{} isn't in the schema", property_path)),
+                    (None, Some(additional)) => errors.extend(validate(additional, property, &property_path)),
+                    (None, None) => ()
+                }
+            }
+        }
+        if let (Value::Array(items), Some(item_schema)) = (value, schema.get("items")) {
+            for (index, item) in items.iter().enumerate() {
+                errors.extend(validate(item_schema, item, &format!("{}[{}]", path, index)));
+            }
+        }
+        errors
+    }
+
+    fn assert_valid(artifact: Artifact, json: &str) {
+        let schema: Value = serde_json::from_str(artifact.schema()).unwrap();
+        let value: Value = serde_json::from_str(json).unwrap();
+        let errors = validate(&schema, &value, "$");
+        assert!(errors.is_empty(), "{} doesn't match its schema: {}", artifact.name(), errors.join(", "));
+
+        assert_eq!(Value::from(artifact.id()), value["schema_id"]);
+        assert_eq!(Value::from(artifact.version()), value["schema_version"]);
+    }
+
+    #[test]
+    fn test_schemas_match_versions() {
+        for artifact in Artifact::value_variants() {
+            let schema: Value = serde_json::from_str(artifact.schema()).unwrap();
+            assert_eq!(Value::from(artifact.id()), schema["$id"], "{}", artifact.name());
+            assert_eq!(Value::from(artifact.id()), schema["properties"]["schema_id"]["const"], "{}", artifact.name());
+            assert_eq!(Value::from(artifact.version()), schema["properties"]["schema_version"]["const"], "{}", artifact.name());
+
+            let (_, version, fingerprint) = FINGERPRINTS.iter().find(|(pinned, _, _)| pinned == artifact).unwrap();
+            assert_eq!(*version, artifact.version(), "Update the fingerprint of the {} schema for its new version", artifact.name());
+            assert_eq!(*fingerprint, header::hash(artifact.schema()),
+                       "The {} schema changed without its version being bumped", artifact.name());
+        }
+    }
+
+    #[test]
+    fn test_artifacts_match_schemas() {
+        assert_valid(Artifact::About, &About::new().to_json());
+        assert_valid(Artifact::Plan, &Plan::new(&[Target::Output("Program.out"), Target::Output("-")]).to_json());
+
+        let mut parser = Parser::new(Lexer::new(include_str!("../examples/example1.txt").to_string()),
+                                     Emitter::new("test.out"), CompilerOptions::default());
+        parser.program().unwrap();
+        let entry = HistoryEntry {
+            schema: SchemaVersion::of(Artifact::StatsHistory),
+            timestamp: Some("2024-03-05T14:07:09Z".to_string()),
+            source_hash: None,
+            program: "Cell".to_string(),
+            program_version: "1.0".to_string(),
+            metrics: parser.metrics()
+        };
+        assert_valid(Artifact::StatsHistory, &serde_json::to_string(&entry).unwrap());
+
+        let entry = Entry::new("0123456789abcdef", "# Compiled by LogTextCompiler\n".to_string(), vec!["Unused tag".to_string()],
+                               Some(("Cell".to_string(), "1.0".to_string())), parser.metrics(), parser.routine_sizes().to_vec());
+        assert_valid(Artifact::CacheDiagnostics, &serde_json::to_string(&entry.diagnostics).unwrap());
+        assert_valid(Artifact::LastWriter, &parser.cross_reference().last_writer(parser.call_graph(), "MyTag").unwrap().to_json());
+    }
+
+    #[test]
+    fn test_validate() {
+        let schema: Value = serde_json::from_str(Artifact::StatsHistory.schema()).unwrap();
+        let mut entry = serde_json::json!({
+            "schema_id": Artifact::StatsHistory.id(),
+            "schema_version": 1,
+            "program": "Cell",
+            "program_version": "",
+            "metrics": { "tasks": 1, "routines": 1, "rungs": 2, "instructions": BTreeMap::from([("XIC", 2)]), "generated_bytes": 10 }
+        });
+        assert!(validate(&schema, &entry, "$").is_empty());
+
+        entry["metrics"]["instructions"]["XIC"] = Value::from(-1);
+        entry["lines"] = Value::from(3);
+        entry.as_object_mut().unwrap().remove("program");
+        assert_eq!(vec!["$ is missing \"program\"", "$.lines isn't in the schema", "$.metrics.instructions.XIC is -1, below the minimum of 0"],
+                   validate(&schema, &entry, "$"));
+    }
+}
@@ -0,0 +1,211 @@
+use std::collections::BTreeMap;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+
+use serde::{Deserialize, Serialize};
+
+use crate::instructions::INSTRUCTIONS;
+use crate::schema::SchemaVersion;
+
+/// Size of a program, tracked from release to release
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct Metrics {
+    pub tasks: usize,
+    pub routines: usize,
+    pub rungs: usize,
+    /// Number of uses of each instruction, by keyword
+    pub instructions: BTreeMap<String, usize>,
+    pub generated_bytes: usize
+}
+
+impl Metrics {
+    /// Looks up a metric by the name a budget gives it. Instructions are named by their keyword
+    fn get(&self, name: &str) -> Option<usize> {
+        match name {
+            "tasks" => Some(self.tasks),
+            "routines" => Some(self.routines),
+            "rungs" => Some(self.rungs),
+            "generated_bytes" => Some(self.generated_bytes),
+            keyword if INSTRUCTIONS.iter().any(|spec| spec.keyword == keyword) => {
+                Some(self.instructions.get(keyword).copied().unwrap_or(0))
+            },
+            _ => None
+        }
+    }
+}
+
+/// One line of the stats history file
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct HistoryEntry {
+    #[serde(flatten)]
+    pub schema: SchemaVersion,
+
+    /// RFC 3339 time of the build in UTC, left out of reproducible builds
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timestamp: Option<String>,
+
+    /// Identifies the source of reproducible builds in place of the timestamp
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub source_hash: Option<String>,
+
+    pub program: String,
+    pub program_version: String,
+    pub metrics: Metrics
+}
+
+/// Every entry of the stats history, which has none until the first build writes it
+pub fn read_history(path: &str) -> Result<Vec<HistoryEntry>, String> {
+    let text = match fs::read_to_string(path) {
+        Ok(text) => text,
+        Err(_) => return Ok(Vec::new())
+    };
+
+    text.lines().enumerate().filter(|(_, line)| !line.trim().is_empty()).map(|(line_number, line)| {
+        serde_json::from_str(line).map_err(|error| format!("Invalid line {} of the stats history: {}", line_number + 1, error))
+    }).collect()
+}
+
+pub fn append_history(path: &str, entry: &HistoryEntry) {
+    let mut file = OpenOptions::new()
+        .append(true)
+        .create(true)
+        .open(path)
+        .expect("Couldn't open stats history");
+
+    // A single write in append mode lands whole at the end of the file, even with other builds appending at once
+    let line = format!("{}\n", serde_json::to_string(entry).unwrap());
+    if let Err(why) = file.write_all(line.as_bytes()) {
+        panic!("Couldn't write to {}: {}", path, why);
+    }
+}
+
+/// Metrics of the latest build of a different version of the program, or of its latest build
+/// when the program doesn't declare a version. Builds of other programs sharing the history are skipped
+pub fn previous_release<'a>(history: &'a [HistoryEntry], program: &str, program_version: &str) -> Option<&'a Metrics> {
+    history.iter()
+           .rev()
+           .filter(|entry| entry.program == program)
+           .find(|entry| program_version.is_empty() || entry.program_version != program_version)
+           .map(|entry| &entry.metrics)
+}
+
+/// Largest values the metrics may reach, as warnings or errors
+#[derive(Deserialize, Default, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct Budget {
+    #[serde(default)]
+    warning: BTreeMap<String, usize>,
+
+    #[serde(default)]
+    error: BTreeMap<String, usize>
+}
+
+/// Metrics over budget, split by how severe the budget says they are
+#[derive(Debug, Default, PartialEq)]
+pub struct Exceedances {
+    pub warnings: Vec<String>,
+    pub errors: Vec<String>
+}
+
+impl Budget {
+    pub fn load(path: &str) -> Result<Budget, String> {
+        let text = fs::read_to_string(path).map_err(|error| error.to_string())?;
+        Budget::parse(&text)
+    }
+
+    pub fn parse(text: &str) -> Result<Budget, String> {
+        let budget: Budget = toml::from_str(text).map_err(|error| format!("Invalid stats budget: {}", error))?;
+        for metric in budget.warning.keys().chain(budget.error.keys()) {
+            if Metrics::default().get(metric).is_none() {
+                return Err(format!("Invalid stats budget: unknown metric {}", metric));
+            }
+        }
+        Ok(budget)
+    }
+
+    pub fn check(&self, metrics: &Metrics, previous: Option<&Metrics>) -> Exceedances {
+        let exceeded = |maxima: &BTreeMap<String, usize>| {
+            maxima.iter().filter_map(|(metric, &maximum)| {
+                let value = metrics.get(metric).unwrap();
+                if value <= maximum {
+                    return None;
+                }
+                let previous = match previous.and_then(|previous| previous.get(metric)) {
+                    Some(previous) => format!("{} in the previous release", previous),
+                    None => "no previous release".to_string()
+                };
+                Some(format!("Stats budget: {} is {}, over the budget of {} ({})", metric, value, maximum, previous))
+            }).collect()
+        };
+
+        Exceedances { warnings: exceeded(&self.warning), errors: exceeded(&self.error) }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn metrics(rungs: usize, jsr_count: usize) -> Metrics {
+        Metrics {
+            tasks: 1,
+            routines: 2,
+            rungs,
+            instructions: BTreeMap::from([("JSR".to_string(), jsr_count)]),
+            generated_bytes: 1000
+        }
+    }
+
+    #[test]
+    fn test_budget() {
+        let budget = Budget::parse("[warning]\nrungs = 10\n\n[error]\nJSR = 3\nOTE = 5\n").unwrap();
+
+        let exceedances = budget.check(&metrics(12, 4), Some(&metrics(9, 2)));
+        assert_eq!(vec!["Stats budget: rungs is 12, over the budget of 10 (9 in the previous release)"], exceedances.warnings);
+        assert_eq!(vec!["Stats budget: JSR is 4, over the budget of 3 (2 in the previous release)"], exceedances.errors);
+
+        let exceedances = budget.check(&metrics(11, 0), None);
+        assert_eq!(vec!["Stats budget: rungs is 11, over the budget of 10 (no previous release)"], exceedances.warnings);
+        assert!(exceedances.errors.is_empty());
+    }
+
+    #[test]
+    fn test_budget_unknown_metric() {
+        assert_eq!("Invalid stats budget: unknown metric lines", Budget::parse("[error]\nlines = 10\n").unwrap_err());
+        assert!(Budget::parse("[error]\nrungs = \"ten\"\n").unwrap_err().starts_with("Invalid stats budget: "));
+    }
+
+    #[test]
+    fn test_previous_release() {
+        let entry = |program: &str, program_version: &str, rungs| HistoryEntry {
+            schema: SchemaVersion::default(),
+            timestamp: Some("1970-01-01T00:00:00Z".to_string()),
+            source_hash: None,
+            program: program.to_string(),
+            program_version: program_version.to_string(),
+            metrics: metrics(rungs, 0)
+        };
+        let history = [entry("Cell", "1.0", 5), entry("Cell", "1.1", 6), entry("Cell", "1.1", 7), entry("Press", "3.0", 40)];
+
+        // Rebuilds of the current version don't count as a release
+        assert_eq!(Some(&metrics(5, 0)), previous_release(&history, "Cell", "1.1"));
+        assert_eq!(Some(&metrics(7, 0)), previous_release(&history, "Cell", "1.2"));
+        assert_eq!(Some(&metrics(7, 0)), previous_release(&history, "Cell", ""));
+        assert_eq!(None, previous_release(&history[..1], "Cell", "1.0"));
+
+        // Other programs sharing the history are never compared against
+        assert_eq!(Some(&metrics(40, 0)), previous_release(&history, "Press", "3.1"));
+        assert_eq!(None, previous_release(&history, "Mixer", ""));
+    }
+
+    #[test]
+    fn test_read_history() {
+        let path = std::env::temp_dir().join("logtext_stats_read_history.jsonl");
+        let _ = fs::remove_file(&path);
+        let path = path.to_str().unwrap();
+        assert_eq!(Ok(Vec::new()), read_history(path));
+
+        fs::write(path, "\n{\"program\": 1}\n").unwrap();
+        assert!(read_history(path).unwrap_err().starts_with("Invalid line 2 of the stats history: "));
+    }
+}
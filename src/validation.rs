@@ -0,0 +1,143 @@
+/// How a flag interacts with another flag given alongside it
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Interaction {
+    /// The combination would silently do something other than what was asked, so it's rejected
+    Conflict,
+    /// One of the flags has no effect, which is worth a note but not an error
+    Redundant
+}
+
+/// Interaction of a flag with each of the `others` when given together. The message
+/// refers to the other flag as {other}
+pub struct Rule {
+    pub flag: &'static str,
+    pub others: &'static [&'static str],
+    pub interaction: Interaction,
+    pub message: &'static str,
+    /// Flags that make the rule not apply when any of them is given too
+    pub unless: &'static [&'static str]
+}
+
+/// Flags that only have an effect on the generated output
+const OUTPUT_FLAGS: [&str; 8] = ["out", "header-template", "reproducible", "sort-tags", "legacy-tag-declarations", "index-bounds",
+                                 "cache-dir", "cache-mode"];
+
+/// Flags that only have an effect while compiling
+const COMPILE_FLAGS: [&str; 7] = ["input-encoding", "scheduler-tick", "deny-warnings", "strict", "max-output-size", "max-errors",
+                                  "policy"];
+
+pub const RULES: [Rule; 8] = [
+    Rule { flag: "out", others: &["legacy-out-name"], interaction: Interaction::Conflict,
+           message: "--out names the output file, so {other} can't pick the default name", unless: &[] },
+    Rule { flag: "check", others: &["plan"], interaction: Interaction::Conflict,
+           message: "--check compiles without planning any output, while {other} plans output without compiling", unless: &[] },
+    Rule { flag: "check", others: &["stats", "stats-history", "stats-budget"], interaction: Interaction::Conflict,
+           message: "--check stops before the output is generated, so {other} would have nothing to measure", unless: &[] },
+    Rule { flag: "plan", others: &["stats", "stats-budget"], interaction: Interaction::Conflict,
+           message: "--plan doesn't compile the program, so {other} would have nothing to measure", unless: &[] },
+    Rule { flag: "check", others: &OUTPUT_FLAGS, interaction: Interaction::Redundant,
+           message: "--check writes no output, so {other} has no effect", unless: &[] },
+    Rule { flag: "check", others: &["legacy-out-name"], interaction: Interaction::Redundant,
+           message: "--check writes no output, so {other} has no effect", unless: &[] },
+    // Everything that goes into the key of the cache entry decides which entry the plan lists
+    Rule { flag: "plan", others: &["header-template", "reproducible", "sort-tags", "legacy-tag-declarations", "index-bounds"],
+           interaction: Interaction::Redundant, message: "--plan only lists the output files, so {other} has no effect",
+           unless: &["cache-dir"] },
+    Rule { flag: "plan", others: &COMPILE_FLAGS, interaction: Interaction::Redundant,
+           message: "--plan doesn't compile the program, so {other} has no effect", unless: &["cache-dir"] }
+];
+
+/// Flags that have been checked against every other flag and found not to interact with any of them.
+/// Only needed to show that no flag was forgotten
+#[cfg(test)]
+const INDEPENDENT_FLAGS: [&str; 1] = ["source-file"];
+
+/// Problems with a combination of flags, as messages
+#[derive(Debug, Default, PartialEq)]
+pub struct Findings {
+    pub conflicts: Vec<String>,
+    pub notes: Vec<String>
+}
+
+/// Checks the flags given on the command line, by long name, against every rule
+pub fn validate(given: &[&str]) -> Findings {
+    let mut findings = Findings::default();
+    let applies = |rule: &&Rule| given.contains(&rule.flag) && !rule.unless.iter().any(|flag| given.contains(flag));
+    for rule in RULES.iter().filter(applies) {
+        for other in rule.others.iter().filter(|other| given.contains(other)) {
+            let message = rule.message.replace("{other}", &format!("--{}", other));
+            match rule.interaction {
+                Interaction::Conflict => findings.conflicts.push(message),
+                Interaction::Redundant => findings.notes.push(message)
+            }
+        }
+    }
+    findings
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use clap::Args;
+    use crate::BuildArgs;
+
+    #[test]
+    fn test_rules_complete() {
+        let command = BuildArgs::augment_args(clap::Command::new("build"));
+        // --help and --version are added by clap and exit before anything else
+        let flags = command.get_arguments()
+                           .filter_map(|arg| arg.get_long())
+                           .filter(|flag| !["help", "version"].contains(flag))
+                           .collect::<Vec<&str>>();
+
+        // Every flag must have had its interactions declared, and every declared flag must exist
+        let declared = RULES.iter()
+                            .flat_map(|rule| rule.others.iter().chain([&rule.flag]))
+                            .chain(INDEPENDENT_FLAGS.iter())
+                            .copied()
+                            .collect::<Vec<&str>>();
+        for flag in &flags {
+            assert!(declared.contains(flag), "--{} has no rules. Add it to RULES or INDEPENDENT_FLAGS", flag);
+        }
+        for flag in &declared {
+            assert!(flags.contains(flag), "--{} is in the rules but isn't a flag", flag);
+        }
+    }
+
+    #[test]
+    fn test_conflicts() {
+        let pairs = [
+            (["out", "legacy-out-name"], "--out names the output file, so --legacy-out-name can't pick the default name"),
+            (["check", "plan"], "--check compiles without planning any output, while --plan plans output without compiling"),
+            (["check", "stats"], "--check stops before the output is generated, so --stats would have nothing to measure"),
+            (["stats-budget", "check"], "--check stops before the output is generated, so --stats-budget would have nothing to measure"),
+            (["plan", "stats"], "--plan doesn't compile the program, so --stats would have nothing to measure")
+        ];
+        for (given, conflict) in pairs {
+            assert_eq!(vec![conflict.to_string()], validate(&given).conflicts, "{:?}", given);
+        }
+    }
+
+    #[test]
+    fn test_redundant() {
+        let findings = validate(&["check", "out"]);
+        assert!(findings.conflicts.is_empty());
+        assert_eq!(vec!["--check writes no output, so --out has no effect"], findings.notes);
+
+        assert_eq!(vec!["--plan doesn't compile the program, so --policy has no effect"], validate(&["policy", "plan"]).notes);
+
+        // The plan lists the cache entry, whose key depends on the options
+        assert_eq!(Findings::default(), validate(&["plan", "policy", "sort-tags", "cache-dir"]));
+        assert_eq!(Findings::default(), validate(&["plan", "stats-history"]));
+    }
+
+    #[test]
+    fn test_every_conflict_reported() {
+        let findings = validate(&["check", "plan", "stats", "out", "legacy-out-name"]);
+        assert_eq!(4, findings.conflicts.len());
+        assert_eq!(vec!["--check writes no output, so --out has no effect",
+                        "--check writes no output, so --legacy-out-name has no effect"], findings.notes);
+
+        assert_eq!(Findings::default(), validate(&["out", "stats", "reproducible", "policy"]));
+    }
+}
@@ -0,0 +1,565 @@
+use std::collections::HashSet;
+
+use serde::Serialize;
+
+use crate::call_graph::CallGraph;
+use crate::instructions;
+use crate::lexer::TokenType;
+use crate::schema::{Artifact, SchemaVersion};
+
+/// Where a rung lives in the program
+#[derive(Serialize, Debug, Clone, PartialEq)]
+struct RungLocation {
+    task: String,
+    routine: String,
+    rung: String
+}
+
+impl std::fmt::Display for RungLocation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}/{}/{}", self.task, self.routine, self.rung)
+    }
+}
+
+/// Something a rung does to the rest of the program, in the order of its instructions
+#[derive(Debug, Clone)]
+enum Effect {
+    /// Writes the tag. Unconditional writes happen whether the rung is true or not
+    Write { tag: String, instruction: &'static str, unconditional: bool },
+    /// Runs the routine when the rung is true
+    Call(String)
+}
+
+/// Tags read and written by a single rung
+#[derive(Debug, Clone)]
+struct RungReference {
+    location: RungLocation,
+    reads: Vec<String>,
+    writes: Vec<String>,
+    emits: Vec<String>,
+    effects: Vec<Effect>,
+    /// Whether anything can make the rung false
+    conditional: bool,
+    /// Whether a JMP or RET in the rung can skip the rest of its routine
+    skips: bool
+}
+
+#[derive(Debug, Clone)]
+struct TaskReference {
+    name: String,
+    event: Option<String>
+}
+
+/// Classifies every instruction operand in the program as a read or a write
+#[derive(Default)]
+pub struct CrossReference {
+    tasks: Vec<TaskReference>,
+    rungs: Vec<RungReference>,
+    current_routine: String,
+    rung_number: u32
+}
+
+/// A node in the impact graph, either a tag or an event driven task
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum ImpactNode {
+    Tag(String),
+    Task(String)
+}
+
+impl std::fmt::Display for ImpactNode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ImpactNode::Tag(name) => write!(f, "{}", name),
+            ImpactNode::Task(name) => write!(f, "task {}", name)
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct ImpactEdge {
+    from: ImpactNode,
+    to: ImpactNode,
+    location: RungLocation
+}
+
+/// Everything downstream of a single tag
+pub struct Impact {
+    root: ImpactNode,
+    edges: Vec<ImpactEdge>
+}
+
+/// A write to a tag, at its place in the scan
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct WriteSite {
+    #[serde(flatten)]
+    location: RungLocation,
+    /// Routines from the entry routine of the task down to the one with the write
+    call_path: Vec<String>,
+    instruction: String,
+    tag: String,
+    /// Whether the write only happens on some scans
+    conditional: bool
+}
+
+/// Every write to a tag in the order a scan of its task runs them
+#[derive(Serialize, Debug, PartialEq)]
+pub struct LastWriter {
+    #[serde(flatten)]
+    schema: SchemaVersion,
+    tag: String,
+    task: String,
+    writes: Vec<WriteSite>,
+    /// Index into the writes of the last one in the scan
+    last_writer: Option<usize>,
+    /// Index into the writes of the last one that happens on every scan
+    last_unconditional_writer: Option<usize>
+}
+
+/// Whether writing `written` can change `tag`, and if so whether it certainly does. `tag` is either a whole tag,
+/// which covers its elements and bits, or an element, which a write to an element indexed by an INT tag may be
+fn write_matches(tag: &str, written: &str) -> Option<bool> {
+    if written == tag || written.starts_with(&format!("{}.", tag)) {
+        return Some(true);
+    }
+    let is_number = |index: &str| index.chars().all(|character| character.is_ascii_digit());
+    match (tag.split_once('.'), written.split_once('.')) {
+        (Some((array, index)), Some((written_array, written_index))) if array == written_array && is_number(index) && !is_number(written_index) => {
+            Some(false)
+        },
+        _ => None
+    }
+}
+
+impl CrossReference {
+    pub fn new() -> CrossReference {
+        CrossReference::default()
+    }
+
+    pub fn start_task(&mut self, name: &str, event: Option<&str>) {
+        self.tasks.push(TaskReference {
+            name: name.to_string(),
+            event: event.map(str::to_string)
+        });
+    }
+
+    pub fn start_routine(&mut self, name: &str) {
+        self.current_routine = name.to_string();
+        self.rung_number = 0;
+    }
+
+    pub fn start_rung(&mut self, name: &str) {
+        let rung = if name.is_empty() {
+            format!("rung {}", self.rung_number)
+        } else {
+            format!("rung {}", name)
+        };
+        self.rung_number += 1;
+
+        let task = self.tasks.last().map(|task| task.name.clone()).unwrap_or_default();
+        self.rungs.push(RungReference {
+            location: RungLocation { task, routine: self.current_routine.clone(), rung },
+            reads: Vec::new(),
+            writes: Vec::new(),
+            emits: Vec::new(),
+            effects: Vec::new(),
+            conditional: false,
+            skips: false
+        });
+    }
+
+    /// Makes the current rung depend on a condition that isn't in the cross reference, like a TEMP
+    pub fn add_condition(&mut self) {
+        if let Some(rung) = self.rungs.last_mut() {
+            rung.conditional = true;
+        }
+    }
+
+    pub fn add_instruction(&mut self, instruction: TokenType, operands: &[&str]) {
+        let rung = match self.rungs.last_mut() {
+            Some(rung) => rung,
+            None => return
+        };
+        let target = operands.last().copied().unwrap_or_default();
+
+        // Number literals are the only operands that don't start with a letter
+        let tags = |operands: &[&str]| {
+            operands.iter().filter(|operand| !operand.starts_with(|character: char| character.is_ascii_digit()))
+                           .map(|operand| operand.to_string())
+                           .collect::<Vec<String>>()
+        };
+
+        // Outputs that only act when the rung is true write conditionally. The others write either way
+        let spec = instructions::spec(instruction).unwrap();
+        if spec.class == instructions::InstructionClass::Input && instruction != TokenType::Lbl {
+            rung.conditional = true;
+        }
+        if spec.writes_tag() || matches!(instruction, TokenType::Ton | TokenType::Tof | TokenType::Ctu | TokenType::Ctd | TokenType::Res) {
+            let unconditional = !rung.conditional || matches!(instruction, TokenType::Ote | TokenType::Ons | TokenType::Ton | TokenType::Tof);
+            let tag = if spec.writes_tag() { target.to_string() } else { format!("{}.DN", target) };
+            rung.effects.push(Effect::Write { tag, instruction: spec.keyword, unconditional });
+        }
+
+        match instruction {
+            TokenType::Jsr => rung.effects.push(Effect::Call(target.to_string())),
+            TokenType::Jmp | TokenType::Ret => rung.skips = true,
+            TokenType::Xic | TokenType::Xio => rung.reads.push(target.to_string()),
+            TokenType::Equ | TokenType::Neq | TokenType::Grt | TokenType::Les => rung.reads.extend(tags(operands)),
+            // ONS stores whether the conditions before it were true
+            TokenType::Ote | TokenType::Otl | TokenType::Otu | TokenType::Ons => rung.writes.push(target.to_string()),
+            TokenType::Mov | TokenType::Add | TokenType::Sub => {
+                rung.reads.extend(tags(&operands[..operands.len() - 1]));
+                rung.writes.push(target.to_string())
+            },
+            TokenType::Emit => rung.emits.push(target.to_string()),
+            // Timers and counters are only read through their done bit
+            TokenType::Ton | TokenType::Tof | TokenType::Ctu | TokenType::Ctd | TokenType::Res => {
+                rung.writes.push(format!("{}.DN", target))
+            },
+            _ => ()
+        }
+    }
+
+    /// Follows the outputs of every rung that reads `tag`, up to `depth` tags away
+    pub fn impact(&self, tag: &str, depth: usize) -> Impact {
+        let root = ImpactNode::Tag(tag.to_string());
+        let mut edges = Vec::new();
+        let mut visited = HashSet::new();
+        let mut frontier = vec![root.clone()];
+        visited.insert(root.clone());
+
+        for _ in 0..depth {
+            let mut next_frontier = Vec::new();
+            for node in &frontier {
+                for (location, downstream) in self.downstream(node) {
+                    edges.push(ImpactEdge { from: node.clone(), to: downstream.clone(), location });
+
+                    // Seal-in loops lead back to tags we've already seen
+                    if visited.insert(downstream.clone()) {
+                        next_frontier.push(downstream);
+                    }
+                }
+            }
+            frontier = next_frontier;
+        }
+
+        Impact { root, edges }
+    }
+
+    /// Lists the writes to `tag` in the order one scan of the task writing it runs them, following JSRs into the
+    /// routines they call. Only works when a single periodic or continuous task writes the tag, since tasks
+    /// run independently of each other
+    pub fn last_writer(&self, call_graph: &CallGraph, tag: &str) -> Result<LastWriter, String> {
+        let writes_tag = |task: &TaskReference| self.rungs.iter().any(|rung| {
+            rung.location.task == task.name && rung.effects.iter().any(|effect| {
+                matches!(effect, Effect::Write { tag: written, .. } if write_matches(tag, written).is_some())
+            })
+        });
+        let tasks = self.tasks.iter().filter(|task| writes_tag(task)).collect::<Vec<&TaskReference>>();
+
+        if let Some(TaskReference { name, event: Some(event) }) = tasks.iter().find(|task| task.event.is_some()) {
+            return Err(format!("{} is written by event task {}, which runs whenever {} is emitted, so the order of its writes \
+                                can't be resolved", tag, name, event));
+        }
+        let task = match tasks.as_slice() {
+            [] => return Err(format!("{} is never written", tag)),
+            [task] => task.name.clone(),
+            tasks => {
+                let names = tasks.iter().map(|task| task.name.as_str()).collect::<Vec<&str>>();
+                return Err(format!("{} is written by tasks {}, which run independently of each other, so the order of its writes \
+                                    can't be resolved", tag, names.join(", ")));
+            }
+        };
+
+        let mut writes = Vec::new();
+        let (_, entry) = call_graph.entry(&task);
+        self.scan(call_graph, &task, &mut vec![entry], false, tag, &mut writes);
+
+        let last_writer = writes.len().checked_sub(1);
+        let last_unconditional_writer = writes.iter().rposition(|write| !write.conditional);
+        Ok(LastWriter { schema: SchemaVersion::of(Artifact::LastWriter), tag: tag.to_string(), task, writes, last_writer,
+                        last_unconditional_writer })
+    }
+
+    /// Adds the writes to `tag` made by the routine at the end of `path`, and by the routines it calls
+    fn scan(&self, call_graph: &CallGraph, task: &str, path: &mut Vec<String>, conditional: bool, tag: &str, writes: &mut Vec<WriteSite>) {
+        let routine = path.last().unwrap().clone();
+        let mut skippable = false;
+        for rung in self.rungs.iter().filter(|rung| rung.location.task == task && rung.location.routine == routine) {
+            for effect in &rung.effects {
+                match effect {
+                    Effect::Write { tag: written, instruction, unconditional } => {
+                        if let Some(certain) = write_matches(tag, written) {
+                            writes.push(WriteSite {
+                                location: rung.location.clone(),
+                                call_path: path.clone(),
+                                instruction: instruction.to_string(),
+                                tag: written.clone(),
+                                conditional: conditional || skippable || !unconditional || !certain
+                            });
+                        }
+                    },
+                    Effect::Call(callee) => {
+                        // Recursive calls don't add anything that hasn't been seen already
+                        if let Some((owner, callee)) = call_graph.resolve(task, callee) {
+                            if owner == task && !path.contains(&callee) {
+                                path.push(callee);
+                                self.scan(call_graph, task, path, conditional || skippable || rung.conditional, tag, writes);
+                                path.pop();
+                            }
+                        }
+                    }
+                }
+            }
+
+            // A JMP or RET may skip the rest of the routine
+            skippable |= rung.skips;
+        }
+    }
+
+    fn downstream(&self, node: &ImpactNode) -> Vec<(RungLocation, ImpactNode)> {
+        let mut result = Vec::new();
+        match node {
+            ImpactNode::Tag(tag) => {
+                let reads_tag = |read: &String| read == tag || read.starts_with(&format!("{}.", tag));
+                for rung in self.rungs.iter().filter(|rung| rung.reads.iter().any(reads_tag)) {
+                    for write in &rung.writes {
+                        result.push((rung.location.clone(), ImpactNode::Tag(write.clone())));
+                    }
+
+                    // An emitted event carries across to the tasks bound to it
+                    for event in &rung.emits {
+                        for task in self.tasks.iter().filter(|task| task.event.as_ref() == Some(event)) {
+                            result.push((rung.location.clone(), ImpactNode::Task(task.name.clone())));
+                        }
+                    }
+                }
+            },
+            ImpactNode::Task(task) => {
+                // Everything written by an event task is affected when it runs
+                for rung in self.rungs.iter().filter(|rung| &rung.location.task == task) {
+                    for write in &rung.writes {
+                        result.push((rung.location.clone(), ImpactNode::Tag(write.clone())));
+                    }
+                }
+            }
+        }
+        result
+    }
+}
+
+impl Impact {
+    pub fn to_text(&self) -> String {
+        let mut text = format!("{}\n", self.root);
+        let mut printed = HashSet::new();
+        printed.insert(self.root.clone());
+        self.text_children(&self.root, 1, &mut printed, &mut text);
+        text
+    }
+
+    fn text_children(&self, node: &ImpactNode, indentation_level: usize, printed: &mut HashSet<ImpactNode>, text: &mut String) {
+        for edge in self.edges.iter().filter(|edge| &edge.from == node) {
+            let indentation = "    ".repeat(indentation_level);
+            if printed.insert(edge.to.clone()) {
+                text.push_str(&format!("{}{} ({})\n", indentation, edge.to, edge.location));
+                self.text_children(&edge.to, indentation_level + 1, printed, text);
+            } else {
+                text.push_str(&format!("{}{} ({}) [see above]\n", indentation, edge.to, edge.location));
+            }
+        }
+    }
+
+    pub fn to_dot(&self) -> String {
+        let mut dot = String::from("digraph impact {\n");
+        dot.push_str(&format!("    \"{}\";\n", self.root));
+        for edge in &self.edges {
+            dot.push_str(&format!("    \"{}\" -> \"{}\" [label=\"{}\"];\n", edge.from, edge.to, edge.location));
+        }
+        dot.push('}');
+        dot
+    }
+}
+
+impl LastWriter {
+    pub fn to_text(&self) -> String {
+        let mut text = format!("Writes to {} in the scan of task {}:\n", self.tag, self.task);
+        for (index, write) in self.writes.iter().enumerate() {
+            let via = match write.call_path.as_slice() {
+                [_] => String::new(),
+                call_path => format!(" (via {})", call_path.join(" > "))
+            };
+            let condition = if write.conditional { "conditional" } else { "unconditional" };
+            text += &format!("    {}. {}{}: {} {}, {}\n", index + 1, write.location, via, write.instruction, write.tag, condition);
+        }
+
+        let site = |index: usize| format!("{}. {}", index + 1, self.writes[index].location);
+        match (self.last_writer, self.last_unconditional_writer) {
+            (None, _) => text += &format!("No routine that task {} runs writes {}\n", self.task, self.tag),
+            (Some(last), Some(unconditional)) if last == unconditional => {
+                text += &format!("Last writer: {}, which writes on every scan\n", site(last));
+            },
+            (Some(last), Some(unconditional)) => {
+                let conditional = match unconditional + 2 == last + 1 {
+                    true => format!("Write {} is conditional", last + 1),
+                    false => format!("Writes {} to {} are conditional", unconditional + 2, last + 1)
+                };
+                text += &format!("Last writer: {} when it writes. {}, so on other scans it's the last of them that writes, \
+                                  or else {}, which writes on every scan\n", site(last), conditional, site(unconditional));
+            },
+            (Some(last), None) => {
+                text += &format!("Last writer: {} when it writes. None of the writes happen on every scan, so {} can keep \
+                                  its value from the previous scan\n", site(last), self.tag);
+            }
+        }
+        text
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).unwrap()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{emitter::Emitter, lexer::Lexer, options::CompilerOptions, parse::Parser};
+
+    const WRITERS: &str = "TAG start = FALSE
+TAG motor = FALSE
+TASK<PERIOD=100> MainTask
+ROUTINE Main
+RUNG
+OTE motor
+ENDRUNG
+RUNG
+XIC start
+JSR Helper
+ENDRUNG
+RUNG
+XIC start
+OTU motor
+ENDRUNG
+ENDROUTINE
+ROUTINE Helper
+RUNG
+OTL motor
+ENDRUNG
+ENDROUTINE
+ENDTASK
+";
+
+    fn last_writer(source_code: &str, tag: &str) -> Result<LastWriter, String> {
+        let mut parser = Parser::new(Lexer::new(source_code.to_string()), Emitter::new("test.out"), CompilerOptions::default());
+        parser.program().unwrap();
+        parser.cross_reference().last_writer(parser.call_graph(), tag)
+    }
+
+    fn build_cross_reference() -> CrossReference {
+        let mut xref = CrossReference::new();
+
+        xref.start_task("MainTask", None);
+        xref.start_routine("Main");
+        xref.start_rung("");
+        xref.add_instruction(TokenType::Xic, &["start"]);
+        xref.add_instruction(TokenType::Xic, &["motor"]);
+        xref.add_instruction(TokenType::Ote, &["motor"]);
+        xref.start_rung("lamp");
+        xref.add_instruction(TokenType::Xio, &["motor"]);
+        xref.add_instruction(TokenType::Ote, &["lamp"]);
+        xref.add_instruction(TokenType::Emit, &["lampOff"]);
+        xref.start_rung("");
+        xref.add_instruction(TokenType::Xic, &["other"]);
+        xref.add_instruction(TokenType::Ote, &["unrelated"]);
+
+        xref.start_task("EventTask", Some("lampOff"));
+        xref.start_routine("Main");
+        xref.start_rung("");
+        xref.add_instruction(TokenType::Otl, &["alarm.2"]);
+        xref
+    }
+
+    #[test]
+    fn test_impact_transitive() {
+        let xref = build_cross_reference();
+        let impact = xref.impact("start", 10);
+
+        let expected_text = "start
+    motor (MainTask/Main/rung 0)
+        motor (MainTask/Main/rung 0) [see above]
+        lamp (MainTask/Main/rung lamp)
+        task EventTask (MainTask/Main/rung lamp)
+            alarm.2 (EventTask/Main/rung 0)
+";
+        assert_eq!(expected_text, impact.to_text());
+        assert!(!impact.edges.iter().any(|edge| edge.to == ImpactNode::Tag("unrelated".to_string())));
+    }
+
+    #[test]
+    fn test_impact_depth() {
+        let xref = build_cross_reference();
+        let impact = xref.impact("start", 1);
+
+        assert_eq!(1, impact.edges.len());
+        assert_eq!(ImpactNode::Tag("motor".to_string()), impact.edges[0].to);
+    }
+
+    #[test]
+    fn test_impact_dot() {
+        let xref = build_cross_reference();
+        let impact = xref.impact("lamp", 10);
+        assert_eq!("digraph impact {\n    \"lamp\";\n}", impact.to_dot());
+
+        let impact = xref.impact("motor", 1);
+        let expected_dot = "digraph impact {
+    \"motor\";
+    \"motor\" -> \"motor\" [label=\"MainTask/Main/rung 0\"];
+    \"motor\" -> \"lamp\" [label=\"MainTask/Main/rung lamp\"];
+    \"motor\" -> \"task EventTask\" [label=\"MainTask/Main/rung lamp\"];
+}";
+        assert_eq!(expected_dot, impact.to_dot());
+    }
+
+    #[test]
+    fn test_last_writer() {
+        let last_writer = last_writer(WRITERS, "motor").unwrap();
+        assert_eq!((Some(2), Some(0)), (last_writer.last_writer, last_writer.last_unconditional_writer));
+
+        // Writes in a called routine happen where the JSR is, and only when its rung is true
+        let expected_text = "Writes to motor in the scan of task MainTask:
+    1. MainTask/Main/rung 0: OTE motor, unconditional
+    2. MainTask/Helper/rung 0 (via Main > Helper): OTL motor, conditional
+    3. MainTask/Main/rung 2: OTU motor, conditional
+Last writer: 3. MainTask/Main/rung 2 when it writes. Writes 2 to 3 are conditional, so on other scans it's the last of them \
+that writes, or else 1. MainTask/Main/rung 0, which writes on every scan
+";
+        assert_eq!(expected_text, last_writer.to_text());
+    }
+
+    #[test]
+    fn test_last_writer_element() {
+        let source_code = "TAG[4] lights = FALSE\nINT cursor = 0\nTASK<PERIOD=100> MainTask\nROUTINE Main
+RUNG\nOTL lights.2\nENDRUNG\nRUNG\nOTE lights.cursor\nENDRUNG\nRUNG\nOTE lights.3\nENDRUNG\nENDROUTINE\nENDTASK";
+
+        // An element indexed by a tag may or may not be the one asked about
+        let element = last_writer(source_code, "lights.2").unwrap();
+        assert_eq!(vec![("lights.2", false), ("lights.cursor", true)],
+                   element.writes.iter().map(|write| (write.tag.as_str(), write.conditional)).collect::<Vec<_>>());
+        assert!(element.to_text().ends_with("Last writer: 2. MainTask/Main/rung 1 when it writes. Write 2 is conditional, \
+                                                 so on other scans it's the last of them that writes, or else 1. MainTask/Main/rung 0, \
+                                                 which writes on every scan\n"));
+
+        assert_eq!(3, last_writer(source_code, "lights").unwrap().writes.len());
+    }
+
+    #[test]
+    fn test_last_writer_ambiguous() {
+        let source_code = format!("{}TASK<EVENT=stopped> StopTask\nROUTINE Main\nRUNG\nOTU motor\nENDRUNG\nENDROUTINE\nENDTASK", WRITERS);
+        assert_eq!(Err("motor is written by event task StopTask, which runs whenever stopped is emitted, so the order of its writes \
+                        can't be resolved".to_string()), last_writer(&source_code, "motor"));
+
+        let source_code = format!("{}TASK<PERIOD=500> SlowTask\nROUTINE Main\nRUNG\nOTU motor\nENDRUNG\nENDROUTINE\nENDTASK", WRITERS);
+        assert_eq!(Err("motor is written by tasks MainTask, SlowTask, which run independently of each other, so the order of its \
+                        writes can't be resolved".to_string()), last_writer(&source_code, "motor"));
+
+        assert_eq!(Err("start is never written".to_string()), last_writer(WRITERS, "start"));
+    }
+}
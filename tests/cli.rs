@@ -0,0 +1,1011 @@
+use std::fs;
+use std::path::PathBuf;
+use assert_cmd::Command;
+
+const EXAMPLE: &str = "examples/example1.txt";
+
+fn output_path(name: &str) -> PathBuf {
+    let path = std::env::temp_dir().join(format!("logtext_cli_{}.out", name));
+    let _ = fs::remove_file(&path);
+    path
+}
+
+#[test]
+fn test_build_without_subcommand() {
+    let out = output_path("alias");
+    Command::cargo_bin("LogTextCompiler").unwrap()
+        .args(["-s", EXAMPLE, "-o", out.to_str().unwrap()])
+        .assert()
+        .success();
+
+    let alias_output = fs::read_to_string(&out).unwrap();
+
+    let out = output_path("build");
+    Command::cargo_bin("LogTextCompiler").unwrap()
+        .args(["build", "-s", EXAMPLE, "-o", out.to_str().unwrap()])
+        .assert()
+        .success();
+
+    assert_eq!(alias_output, fs::read_to_string(&out).unwrap());
+}
+
+fn temp_dir(name: &str) -> PathBuf {
+    let path = std::env::temp_dir().join(format!("logtext_cli_{}", name));
+    let _ = fs::remove_dir_all(&path);
+    fs::create_dir_all(&path).unwrap();
+    path
+}
+
+/// Runs the tag declarations and routines of compiled output in Python, followed by `script_suffix`,
+/// which scans the task by calling its entry routine. Gives what the script printed, or None without an interpreter
+fn run_compiled_python(output: &str, script_suffix: &str) -> Option<String> {
+    let mut script = output.split_once("TASK ").unwrap().0.to_string();
+    // The task body ends by calling the entry routine, which is left to the suffix
+    script += output.split_once("{\n").unwrap().1.rsplit_once("\n}").unwrap().0.rsplit_once('\n').unwrap().0;
+    script += "\n";
+    script += script_suffix;
+
+    let python = std::process::Command::new("python3").arg("-c").arg(&script).output().ok()?;
+    assert!(python.status.success(), "{}", String::from_utf8_lossy(&python.stderr));
+    Some(String::from_utf8(python.stdout).unwrap())
+}
+
+#[test]
+fn test_build_default_output_name() {
+    let directory = temp_dir("default_name");
+    let source_file = directory.join("conveyor.lt");
+    fs::copy(EXAMPLE, &source_file).unwrap();
+
+    Command::cargo_bin("LogTextCompiler").unwrap()
+        .current_dir(&directory)
+        .args(["-s", "conveyor.lt"])
+        .assert()
+        .success();
+    assert!(directory.join("conveyor.out").exists());
+    assert!(!directory.join("Program.out").exists());
+
+    Command::cargo_bin("LogTextCompiler").unwrap()
+        .current_dir(&directory)
+        .args(["-s", "conveyor.lt", "-o", "explicit.out"])
+        .assert()
+        .success();
+    assert!(directory.join("explicit.out").exists());
+
+    Command::cargo_bin("LogTextCompiler").unwrap()
+        .current_dir(&directory)
+        .args(["-s", "conveyor.lt", "--legacy-out-name"])
+        .assert()
+        .success();
+    assert!(directory.join("Program.out").exists());
+}
+
+#[test]
+fn test_build_program_header() {
+    let directory = temp_dir("program_header");
+    fs::write(directory.join("cell.lt"), "PROGRAM CellThree VERSION 2.4.1\nTAG start = FALSE\n").unwrap();
+    fs::write(directory.join("header.txt"), "{program} {program_version}").unwrap();
+
+    Command::cargo_bin("LogTextCompiler").unwrap()
+        .current_dir(&directory)
+        .args(["-s", "cell.lt", "--header-template", "header.txt"])
+        .assert()
+        .success();
+
+    let output = fs::read_to_string(directory.join("cell.out")).unwrap();
+    assert!(output.contains("# CellThree 2.4.1\n"));
+    assert!(output.contains("PROGRAM CellThree VERSION 2.4.1\n"));
+
+    fs::write(directory.join("header.txt"), "{program} by {author}").unwrap();
+    Command::cargo_bin("LogTextCompiler").unwrap()
+        .current_dir(&directory)
+        .args(["-s", "cell.lt", "--header-template", "header.txt"])
+        .assert()
+        .code(1)
+        .stderr("Error: header.txt: Unknown variable {author} on line 1 of the header template\n");
+    assert_eq!(output, fs::read_to_string(directory.join("cell.out")).unwrap());
+}
+
+#[test]
+fn test_build_policy_hash() {
+    let directory = temp_dir("policy");
+    fs::write(directory.join("policy.toml"), "max_jsr_depth = 2\n").unwrap();
+
+    Command::cargo_bin("LogTextCompiler").unwrap()
+        .current_dir(&directory)
+        .args(["-s", &format!("{}/{}", env!("CARGO_MANIFEST_DIR"), EXAMPLE), "-o", "example.out", "--policy", "policy.toml"])
+        .assert()
+        .success();
+
+    // FNV-1a of the policy file
+    let output = fs::read_to_string(directory.join("example.out")).unwrap();
+    assert!(output.contains("# Enforced policy 7a1b42d48151c900\n"), "{}", output);
+
+    fs::write(directory.join("policy.toml"), "max_depth = 2\n").unwrap();
+    let output = Command::cargo_bin("LogTextCompiler").unwrap()
+        .current_dir(&directory)
+        .args(["-s", &format!("{}/{}", env!("CARGO_MANIFEST_DIR"), EXAMPLE), "-o", "example.out", "--policy", "policy.toml"])
+        .assert()
+        .code(1)
+        .get_output()
+        .stderr
+        .clone();
+    assert!(String::from_utf8(output).unwrap().starts_with("Error: policy.toml: Invalid policy file: "));
+}
+
+#[test]
+fn test_build_utf16_source() {
+    let directory = temp_dir("utf16");
+    let mut bytes = vec![0xFF, 0xFE];
+    for unit in fs::read_to_string(EXAMPLE).unwrap().encode_utf16() {
+        bytes.extend(unit.to_le_bytes());
+    }
+    fs::write(directory.join("example.lt"), bytes).unwrap();
+
+    let out = output_path("utf8_source");
+    Command::cargo_bin("LogTextCompiler").unwrap()
+        .args(["-s", EXAMPLE, "-o", out.to_str().unwrap()])
+        .assert()
+        .success();
+
+    let output = Command::cargo_bin("LogTextCompiler").unwrap()
+        .current_dir(&directory)
+        .args(["-s", "example.lt"])
+        .assert()
+        .success()
+        .get_output()
+        .stderr
+        .clone();
+
+    assert!(String::from_utf8(output).unwrap().contains("Note: example.lt: Source is encoded as UTF-16LE with a byte order mark"));
+    assert_eq!(fs::read_to_string(&out).unwrap(), fs::read_to_string(directory.join("example.out")).unwrap());
+}
+
+#[test]
+fn test_build_invalid_encoding() {
+    let directory = temp_dir("invalid_encoding");
+    fs::write(directory.join("invalid.lt"), b"TAG a = FALSE\x81\x8d\n").unwrap();
+    fs::write(directory.join("odd.lt"), b"\xFF\xFET\x00A\x00G").unwrap();
+
+    let build = |source_file: &str| {
+        let output = Command::cargo_bin("LogTextCompiler").unwrap()
+            .current_dir(&directory)
+            .args(["-s", source_file])
+            .assert()
+            .code(1)
+            .get_output()
+            .stderr
+            .clone();
+        String::from_utf8(output).unwrap()
+    };
+
+    assert_eq!("Error: invalid.lt: Invalid Windows-1252 at byte offset 13: 81 8d 0a\n", build("invalid.lt"));
+    assert!(build("odd.lt").starts_with("Error: odd.lt: Invalid UTF-16LE at byte offset 6: "));
+    assert!(build("missing.lt").starts_with("Error: missing.lt: "));
+    assert_eq!(2, fs::read_dir(&directory).unwrap().count());
+}
+
+#[test]
+fn test_build_compiled_output() {
+    let out = output_path("compiled");
+    Command::cargo_bin("LogTextCompiler").unwrap()
+        .args(["-s", EXAMPLE, "-o", out.to_str().unwrap()])
+        .assert()
+        .success();
+
+    let output = Command::cargo_bin("LogTextCompiler").unwrap()
+        .args(["-s", out.to_str().unwrap(), "-o", output_path("recompiled").to_str().unwrap()])
+        .assert()
+        .code(2)
+        .get_output()
+        .stderr
+        .clone();
+
+    assert!(String::from_utf8(output).unwrap().contains("looks like compiled output, not LogText source"));
+}
+
+#[test]
+fn test_build_requires_source() {
+    Command::cargo_bin("LogTextCompiler").unwrap()
+        .assert()
+        .failure();
+}
+
+#[test]
+fn test_xref() {
+    let output = Command::cargo_bin("LogTextCompiler").unwrap()
+        .args(["xref", "-s", EXAMPLE, "--impact", "array"])
+        .assert()
+        .success()
+        .get_output()
+        .stdout
+        .clone();
+
+    assert!(String::from_utf8(output).unwrap().starts_with("array\n    task OtherTask"));
+}
+
+#[test]
+fn test_xref_last_writer() {
+    let output = Command::cargo_bin("LogTextCompiler").unwrap()
+        .args(["xref", "-s", EXAMPLE, "--last-writer", "MyTag"])
+        .assert()
+        .success()
+        .get_output()
+        .stdout
+        .clone();
+
+    assert_eq!("Writes to MyTag in the scan of task MainTask:\n    1. MainTask/Main/rung 1: OTE MyTag, unconditional\n\
+                Last writer: 1. MainTask/Main/rung 1, which writes on every scan\n", String::from_utf8(output).unwrap());
+
+    let output = Command::cargo_bin("LogTextCompiler").unwrap()
+        .args(["xref", "-s", EXAMPLE, "--last-writer", "MyTag", "--last-writer-format", "json"])
+        .assert()
+        .success()
+        .get_output()
+        .stdout
+        .clone();
+
+    let last_writer: serde_json::Value = serde_json::from_slice(&output).unwrap();
+    assert_eq!(serde_json::json!(0), last_writer["last_writer"]);
+    assert_eq!(serde_json::json!("OTE"), last_writer["writes"][0]["instruction"]);
+
+    Command::cargo_bin("LogTextCompiler").unwrap()
+        .args(["xref", "-s", EXAMPLE, "--last-writer", "NeverWritten"])
+        .assert()
+        .failure()
+        .stderr("Error: NeverWritten is never written\n");
+}
+
+#[test]
+fn test_completions() {
+    for shell in ["bash", "zsh", "fish", "powershell"] {
+        let output = Command::cargo_bin("LogTextCompiler").unwrap()
+            .args(["completions", shell])
+            .assert()
+            .success()
+            .get_output()
+            .stdout
+            .clone();
+
+        assert!(String::from_utf8(output).unwrap().contains("scheduler-tick"), "{}", shell);
+    }
+}
+
+#[test]
+fn test_about_json() {
+    let output = Command::cargo_bin("LogTextCompiler").unwrap()
+        .arg("--about-json")
+        .assert()
+        .success()
+        .get_output()
+        .stdout
+        .clone();
+
+    let about: serde_json::Value = serde_json::from_slice(&output).unwrap();
+    assert_eq!(env!("CARGO_PKG_VERSION"), about["version"]);
+}
+
+#[test]
+fn test_schema() {
+    let json = |args: &[&str]| {
+        let output = Command::cargo_bin("LogTextCompiler").unwrap()
+            .args(args)
+            .assert()
+            .success()
+            .get_output()
+            .stdout
+            .clone();
+        serde_json::from_slice::<serde_json::Value>(&output).unwrap()
+    };
+
+    // Each artifact names the schema that is printed for it
+    let about = json(&["--about-json"]);
+    assert_eq!(json(&["schema", "about"])["$id"], about["schema_id"]);
+    let plan = json(&["-s", EXAMPLE, "--plan"]);
+    assert_eq!(json(&["schema", "plan"])["$id"], plan["schema_id"]);
+    assert_eq!(json(&["schema", "stats-history"])["$id"], "urn:logtextcompiler:schema:stats-history:1");
+
+    Command::cargo_bin("LogTextCompiler").unwrap()
+        .args(["schema", "manifest"])
+        .assert()
+        .code(2);
+}
+
+#[test]
+fn test_docs() {
+    let out = output_path("docs");
+    Command::cargo_bin("LogTextCompiler").unwrap()
+        .args(["docs", "--format", "markdown", "-o", out.to_str().unwrap()])
+        .assert()
+        .success();
+
+    let reference = fs::read_to_string(&out).unwrap();
+    assert!(reference.starts_with("# LogText language reference\n"));
+    assert!(reference.contains("### EMIT\n"));
+}
+
+#[test]
+fn test_build_plan() {
+    let directory = temp_dir("plan");
+    let source_file = directory.join("conveyor.lt");
+    fs::copy(EXAMPLE, &source_file).unwrap();
+
+    let plan = || {
+        let output = Command::cargo_bin("LogTextCompiler").unwrap()
+            .current_dir(&directory)
+            .args(["-s", "conveyor.lt", "--plan"])
+            .assert()
+            .success()
+            .get_output()
+            .stdout
+            .clone();
+        serde_json::from_slice::<serde_json::Value>(&output).unwrap()
+    };
+
+    // Planning writes nothing
+    let planned = plan();
+    assert_eq!(vec!["conveyor.lt"], fs::read_dir(&directory).unwrap()
+                                          .map(|entry| entry.unwrap().file_name().to_string_lossy().to_string())
+                                          .collect::<Vec<String>>());
+    assert_eq!("create", planned["outputs"][0]["action"]);
+
+    Command::cargo_bin("LogTextCompiler").unwrap()
+        .current_dir(&directory)
+        .args(["-s", "conveyor.lt"])
+        .assert()
+        .success();
+
+    // The build wrote exactly the planned file
+    let output_file = directory.join("conveyor.out").canonicalize().unwrap();
+    assert_eq!(1, planned["outputs"].as_array().unwrap().len());
+    assert_eq!(output_file, PathBuf::from(planned["outputs"][0]["path"].as_str().unwrap()).canonicalize().unwrap());
+    assert_eq!("overwrite", plan()["outputs"][0]["action"]);
+}
+
+#[test]
+fn test_build_plan_every_file() {
+    let directory = temp_dir("plan_every_file");
+    fs::copy(EXAMPLE, directory.join("conveyor.lt")).unwrap();
+    let args = ["-s", "conveyor.lt", "--stats-history", "history.jsonl", "--cache-dir", "cache"];
+
+    let output = Command::cargo_bin("LogTextCompiler").unwrap()
+        .current_dir(&directory)
+        .args(args)
+        .arg("--plan")
+        .assert()
+        .success()
+        .get_output()
+        .stdout
+        .clone();
+    let planned = serde_json::from_slice::<serde_json::Value>(&output).unwrap();
+    let mut planned = planned["outputs"].as_array().unwrap().iter()
+                                        .map(|output| PathBuf::from(output["path"].as_str().unwrap()))
+                                        .collect::<Vec<PathBuf>>();
+
+    Command::cargo_bin("LogTextCompiler").unwrap()
+        .current_dir(&directory)
+        .args(args)
+        .assert()
+        .success();
+
+    // The build wrote exactly the planned files, besides the source
+    let mut written = vec![directory.join("conveyor.out"), directory.join("history.jsonl")];
+    written.extend(fs::read_dir(directory.join("cache")).unwrap().map(|entry| entry.unwrap().path()));
+    let canonical = |paths: &mut Vec<PathBuf>| {
+        paths.iter_mut().for_each(|path| *path = path.canonicalize().unwrap());
+        paths.sort();
+    };
+    canonical(&mut planned);
+    canonical(&mut written);
+    assert_eq!(written, planned);
+    assert_eq!(4, fs::read_dir(&directory).unwrap().count());
+}
+
+#[test]
+fn test_build_plan_foreign_output() {
+    let directory = temp_dir("plan_foreign_output");
+    fs::copy(EXAMPLE, directory.join("conveyor.lt")).unwrap();
+    fs::write(directory.join("conveyor.out"), "hello\n").unwrap();
+
+    let output = Command::cargo_bin("LogTextCompiler").unwrap()
+        .current_dir(&directory)
+        .args(["-s", "conveyor.lt", "--plan"])
+        .assert()
+        .code(1)
+        .get_output()
+        .clone();
+    let planned = serde_json::from_slice::<serde_json::Value>(&output.stdout).unwrap();
+    let error = "conveyor.out wasn't written by the compiler, so the build won't overwrite it";
+    assert!(planned["errors"][0].as_str().unwrap().ends_with(error));
+    assert!(String::from_utf8(output.stderr).unwrap().contains(error));
+
+    // The build refuses in the same way rather than overwriting the file
+    let output = Command::cargo_bin("LogTextCompiler").unwrap()
+        .current_dir(&directory)
+        .args(["-s", "conveyor.lt"])
+        .assert()
+        .code(1)
+        .get_output()
+        .stderr
+        .clone();
+    assert!(String::from_utf8(output).unwrap().contains(error));
+    assert_eq!("hello\n", fs::read_to_string(directory.join("conveyor.out")).unwrap());
+}
+
+#[test]
+fn test_build_error_location() {
+    let directory = temp_dir("error_location");
+    fs::write(directory.join("broken.lt"), "TAG start = FALSE\nTAG = TRUE\n").unwrap();
+
+    let output = Command::cargo_bin("LogTextCompiler").unwrap()
+        .current_dir(&directory)
+        .args(["-s", "broken.lt"])
+        .assert()
+        .code(1)
+        .get_output()
+        .stderr
+        .clone();
+    assert!(String::from_utf8(output).unwrap().starts_with("Error: broken.lt:2:5: Expected Identifier"));
+
+    // Text the lexer can't read is reported the same way
+    fs::write(directory.join("unreadable.lt"), "TAG start = FALSE\nTAG my_Tag = FALSE\n").unwrap();
+    Command::cargo_bin("LogTextCompiler").unwrap()
+        .current_dir(&directory)
+        .args(["-s", "unreadable.lt"])
+        .assert()
+        .code(1)
+        .stderr("Error: unreadable.lt:2:7: Unknown token: _\n");
+}
+
+#[test]
+fn test_build_multiple_errors() {
+    let directory = temp_dir("multiple_errors");
+    fs::write(directory.join("broken.lt"), "TAG start = FALSE\nTAG = TRUE\nTASK<PERIOD=100> MainTask\nROUTINE Main\nRUNG\nXIC strt
+JSR Helper\nENDRUNG\nENDROUTINE\nENDTASK\n").unwrap();
+    let build = |args: &[&str]| {
+        let output = Command::cargo_bin("LogTextCompiler").unwrap()
+            .current_dir(&directory)
+            .args(["-s", "broken.lt"])
+            .args(args)
+            .assert()
+            .code(1)
+            .get_output()
+            .stderr
+            .clone();
+        String::from_utf8(output).unwrap()
+    };
+
+    let stderr = build(&[]);
+    let lines = stderr.lines().collect::<Vec<&str>>();
+    assert_eq!(3, lines.len(), "{}", stderr);
+    assert!(lines[0].starts_with("Error: broken.lt:2:5: Expected Identifier"));
+    assert_eq!("Error: broken.lt:6: Referencing tag strt before assignment", lines[1]);
+    assert_eq!("Error: broken.lt:7: Routine Helper does not exist", lines[2]);
+
+    for max_errors in ["1", "2"] {
+        let stderr = build(&["--max-errors", max_errors]);
+        let note = format!("Note: Stopped after {} error(s). Raise --max-errors to see the rest\n", max_errors);
+        assert!(stderr.ends_with(&note), "{}", stderr);
+    }
+}
+
+#[test]
+fn test_build_sort_tags() {
+    let directory = temp_dir("sort_tags");
+    let program = "TASK<CONTINUOUS> MainTask\nROUTINE Main\nRUNG\nXIC start\nOTE motor\nENDRUNG\nENDROUTINE\nENDTASK\n";
+    fs::write(directory.join("first.lt"), format!("TAG start = FALSE\nTAG[4] alarms = FALSE\nTAG motor = TRUE\n{}", program)).unwrap();
+    fs::write(directory.join("second.lt"), format!("TAG motor = TRUE\nTAG start = FALSE\nTAG[4] alarms = FALSE\n{}", program)).unwrap();
+
+    for source_file in ["first.lt", "second.lt"] {
+        Command::cargo_bin("LogTextCompiler").unwrap()
+            .current_dir(&directory)
+            .args(["-s", source_file, "--sort-tags", "name"])
+            .assert()
+            .success();
+    }
+
+    let output = fs::read_to_string(directory.join("first.out")).unwrap();
+    assert_eq!(output, fs::read_to_string(directory.join("second.out")).unwrap());
+    assert!(output.contains("\nalarms = [False] * 4\nmotor = True\nstart = False\nTASK "));
+}
+
+#[test]
+fn test_build_stdout() {
+    let directory = temp_dir("stdout");
+    fs::copy(EXAMPLE, directory.join("conveyor.lt")).unwrap();
+
+    let output = Command::cargo_bin("LogTextCompiler").unwrap()
+        .current_dir(&directory)
+        .args(["-s", "conveyor.lt", "-o", "-", "--stats"])
+        .assert()
+        .success()
+        .get_output()
+        .clone();
+
+    let out = output_path("stdout_file");
+    Command::cargo_bin("LogTextCompiler").unwrap()
+        .args(["-s", EXAMPLE, "-o", out.to_str().unwrap()])
+        .assert()
+        .success();
+
+    // The compiled code is the only thing on stdout, and nothing is written to disk
+    assert_eq!(fs::read_to_string(&out).unwrap(), String::from_utf8(output.stdout).unwrap());
+    assert!(String::from_utf8(output.stderr).unwrap().starts_with("Generated size per routine:\n"));
+    assert_eq!(1, fs::read_dir(&directory).unwrap().count());
+}
+
+#[test]
+fn test_build_check() {
+    let directory = temp_dir("check");
+    fs::copy(EXAMPLE, directory.join("valid.lt")).unwrap();
+    fs::write(directory.join("invalid.lt"), "TASK<PERIOD=100> MainTask\nROUTINE Main\nRUNG\nJSR missing\nENDRUNG\nENDROUTINE\nENDTASK\n").unwrap();
+
+    Command::cargo_bin("LogTextCompiler").unwrap()
+        .current_dir(&directory)
+        .args(["-s", "valid.lt", "--check"])
+        .assert()
+        .success()
+        .stdout("");
+
+    let output = Command::cargo_bin("LogTextCompiler").unwrap()
+        .current_dir(&directory)
+        .args(["-s", "invalid.lt", "--check"])
+        .assert()
+        .code(1)
+        .get_output()
+        .stderr
+        .clone();
+    assert_eq!("Error: invalid.lt:4: Routine missing does not exist\n", String::from_utf8(output).unwrap());
+
+    assert_eq!(2, fs::read_dir(&directory).unwrap().count());
+}
+
+#[test]
+fn test_check_subcommand() {
+    let directory = temp_dir("check_subcommand");
+    fs::copy(EXAMPLE, directory.join("valid.lt")).unwrap();
+    fs::write(directory.join("invalid.lt"), "TASK<PERIOD=100> MainTask\nROUTINE Main\nRUNG\nJSR missing\nENDRUNG\nENDROUTINE\nENDTASK\n").unwrap();
+
+    Command::cargo_bin("LogTextCompiler").unwrap()
+        .current_dir(&directory)
+        .args(["check", "-s", "valid.lt"])
+        .assert()
+        .success()
+        .stdout("");
+
+    Command::cargo_bin("LogTextCompiler").unwrap()
+        .current_dir(&directory)
+        .args(["check", "-s", "invalid.lt"])
+        .assert()
+        .code(1)
+        .stderr("Error: invalid.lt:4: Routine missing does not exist\n");
+
+    let output = Command::cargo_bin("LogTextCompiler").unwrap()
+        .current_dir(&directory)
+        .args(["check", "-s", "valid.lt", "--out", "valid.out"])
+        .assert()
+        .success()
+        .get_output()
+        .stderr
+        .clone();
+    assert_eq!("Note: --check writes no output, so --out has no effect\n", String::from_utf8(output).unwrap());
+
+    assert_eq!(2, fs::read_dir(&directory).unwrap().count());
+}
+
+#[test]
+fn test_build_python_tags_are_global() {
+    let directory = temp_dir("python_globals");
+    fs::write(directory.join("cell.lt"), "TAG start = TRUE
+TAG motor = FALSE
+TAG done = FALSE
+TASK<PERIOD=100> MainTask
+ROUTINE Main
+RUNG
+XIC start
+OTE motor
+JSR Check
+ENDRUNG
+ENDROUTINE
+ROUTINE Check
+RUNG
+XIC motor
+OTL done
+ENDRUNG
+ENDROUTINE
+ENDTASK
+").unwrap();
+    Command::cargo_bin("LogTextCompiler").unwrap()
+        .current_dir(&directory)
+        .args(["-s", "cell.lt"])
+        .assert()
+        .success();
+
+    let output = fs::read_to_string(directory.join("cell.out")).unwrap();
+    match run_compiled_python(&output, "MainTask__Main()\nprint(motor, done)\n") {
+        Some(printed) => assert_eq!("True True\n", printed),
+        // Without an interpreter, at least check that both writes are declared global
+        None => {
+            assert!(output.contains("def MainTask__Main():\n\tglobal motor\n"));
+            assert!(output.contains("def MainTask__Check():\n\tglobal done\n"));
+        }
+    }
+}
+
+#[test]
+fn test_build_tag_arrays() {
+    let directory = temp_dir("tag_arrays");
+    fs::write(directory.join("lights.lt"), "TAG[4] lights = FALSE
+TASK<PERIOD=100> MainTask
+ROUTINE Main
+RUNG
+XIO lights.0
+OTL lights.2
+ENDRUNG
+ENDROUTINE
+ENDTASK
+").unwrap();
+    Command::cargo_bin("LogTextCompiler").unwrap()
+        .current_dir(&directory)
+        .args(["-s", "lights.lt"])
+        .assert()
+        .success();
+
+    let output = fs::read_to_string(directory.join("lights.out")).unwrap();
+    assert!(output.contains("\nlights = [False] * 4\n"));
+    assert!(output.contains("\trung_0_entry &= not lights[0]\n\tif rung_0_entry:\n\t\tlights[2] = True\n"));
+
+    Command::cargo_bin("LogTextCompiler").unwrap()
+        .current_dir(&directory)
+        .args(["-s", "lights.lt", "--legacy-tag-declarations"])
+        .assert()
+        .success();
+    assert!(fs::read_to_string(directory.join("lights.out")).unwrap().contains("\nTAG_ARRAY 4 lights FALSE\n"));
+}
+
+#[test]
+fn test_build_stats_history() {
+    let directory = temp_dir("stats_history");
+    let source = format!("{}/{}", env!("CARGO_MANIFEST_DIR"), EXAMPLE);
+
+    // Builds running at the same time must each append a whole line
+    let builds: Vec<_> = (0..8).map(|build| {
+        std::process::Command::new(env!("CARGO_BIN_EXE_LogTextCompiler"))
+            .current_dir(&directory)
+            .args(["-s", &source, "-o", &format!("{}.out", build), "--stats-history", "stats.jsonl", "--reproducible"])
+            .env_remove("SOURCE_DATE_EPOCH")
+            .spawn()
+            .unwrap()
+    }).collect();
+    for mut build in builds {
+        assert!(build.wait().unwrap().success());
+    }
+
+    let history = fs::read_to_string(directory.join("stats.jsonl")).unwrap();
+    assert_eq!(8, history.lines().count());
+    for line in history.lines() {
+        let entry: serde_json::Value = serde_json::from_str(line).unwrap();
+        assert!(entry.get("timestamp").is_none());
+        assert!(entry["source_hash"].is_string());
+        assert!(entry["metrics"]["rungs"].as_u64().unwrap() > 0);
+        assert!(entry["metrics"]["generated_bytes"].as_u64().unwrap() > 0);
+    }
+}
+
+#[test]
+fn test_build_stats_budget() {
+    let directory = temp_dir("stats_budget");
+    fs::write(directory.join("budget.toml"), "[error]\nrungs = 1\n").unwrap();
+
+    let output = Command::cargo_bin("LogTextCompiler").unwrap()
+        .current_dir(&directory)
+        .args(["-s", &format!("{}/{}", env!("CARGO_MANIFEST_DIR"), EXAMPLE), "-o", "example.out", "--stats-budget", "budget.toml"])
+        .assert()
+        .failure()
+        .get_output()
+        .clone();
+
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("Stats budget: rungs is "), "{}", stderr);
+    assert!(stderr.contains("over the budget of 1 (no previous release)"), "{}", stderr);
+    assert!(!directory.join("example.out").exists());
+
+    fs::write(directory.join("budget.toml"), "[error]\nlines = 1\n").unwrap();
+    Command::cargo_bin("LogTextCompiler").unwrap()
+        .current_dir(&directory)
+        .args(["-s", &format!("{}/{}", env!("CARGO_MANIFEST_DIR"), EXAMPLE), "-o", "example.out", "--stats-budget", "budget.toml"])
+        .assert()
+        .code(1)
+        .stderr("Error: budget.toml: Invalid stats budget: unknown metric lines\n");
+}
+
+#[test]
+fn test_build_source_date_epoch() {
+    let directory = temp_dir("source_date_epoch");
+    fs::write(directory.join("header.txt"), "Built {timestamp} ({date})").unwrap();
+
+    let build = |out: &str| {
+        Command::cargo_bin("LogTextCompiler").unwrap()
+            .current_dir(&directory)
+            .env("SOURCE_DATE_EPOCH", "1709647629")
+            .args(["-s", &format!("{}/{}", env!("CARGO_MANIFEST_DIR"), EXAMPLE), "-o", out, "--reproducible",
+                   "--header-template", "header.txt", "--stats-history", "stats.jsonl"])
+            .assert()
+            .success();
+        fs::read_to_string(directory.join(out)).unwrap()
+    };
+
+    // The pinned time is written out, identically on every build
+    let first = build("first.out");
+    assert!(first.contains("\n# Built 2024-03-05T14:07:09Z (2024-03-05)\n"), "{}", first);
+    assert_eq!(first, build("second.out"));
+
+    let history = fs::read_to_string(directory.join("stats.jsonl")).unwrap();
+    let lines = history.lines().collect::<Vec<&str>>();
+    assert_eq!(2, lines.len());
+    assert_eq!(lines[0], lines[1]);
+    assert!(lines[0].contains(",\"timestamp\":\"2024-03-05T14:07:09Z\",\"source_hash\":"), "{}", lines[0]);
+}
+
+#[test]
+fn test_build_python_timers() {
+    let directory = temp_dir("python_timers");
+    fs::write(directory.join("cell.lt"), "TAG start = TRUE
+TAG motor = FALSE
+TAG stopped = FALSE
+TIMER delay = 50
+TIMER coast = 50
+TASK<PERIOD=100> MainTask
+ROUTINE Main
+RUNG
+XIC start
+TON delay
+TOF coast
+ENDRUNG
+RUNG
+XIC delay.DN
+OTE motor
+ENDRUNG
+RUNG
+XIO coast.DN
+OTE stopped
+ENDRUNG
+ENDROUTINE
+ENDTASK
+").unwrap();
+    Command::cargo_bin("LogTextCompiler").unwrap()
+        .current_dir(&directory)
+        .args(["-s", "cell.lt"])
+        .assert()
+        .success();
+
+    // Scan once, wait past the presets and scan again, then let the rung go false for longer than the presets
+    let output = fs::read_to_string(directory.join("cell.out")).unwrap();
+    let scans = "MainTask__Main()\nprint(motor, stopped)\nimport time\ntime.sleep(0.1)\nMainTask__Main()\nprint(motor, stopped)\n\
+                 start = False\nMainTask__Main()\ntime.sleep(0.1)\nMainTask__Main()\nprint(motor, stopped)\n";
+    match run_compiled_python(&output, scans) {
+        Some(printed) => assert_eq!("False False\nTrue False\nFalse True\n", printed),
+        None => assert!(output.contains("delay = {\"PRE\": 50, \"ACC\": 0, \"DN\": False, \"START\": None}\n"))
+    }
+}
+
+#[test]
+fn test_build_python_counters() {
+    let directory = temp_dir("python_counters");
+    fs::write(directory.join("cell.lt"), "TAG sensor = TRUE
+TAG reset = FALSE
+TAG full = FALSE
+COUNTER parts = 2
+TASK<PERIOD=100> MainTask
+ROUTINE Main
+RUNG
+XIC sensor
+CTU parts
+ENDRUNG
+RUNG
+XIC parts.DN
+OTE full
+ENDRUNG
+RUNG
+XIC reset
+RES parts
+ENDRUNG
+ENDROUTINE
+ENDTASK
+").unwrap();
+    Command::cargo_bin("LogTextCompiler").unwrap()
+        .current_dir(&directory)
+        .args(["-s", "cell.lt"])
+        .assert()
+        .success();
+
+    // Holding the sensor on only counts once, and counting again takes another rising edge
+    let output = fs::read_to_string(directory.join("cell.out")).unwrap();
+    let scans = "MainTask__Main()\nprint(parts[\"ACC\"], full)\nMainTask__Main()\nprint(parts[\"ACC\"], full)\n\
+                 sensor = False\nMainTask__Main()\nsensor = True\nMainTask__Main()\nprint(parts[\"ACC\"], full)\n\
+                 reset = True\nMainTask__Main()\nprint(parts[\"ACC\"], parts[\"DN\"])\n";
+    match run_compiled_python(&output, scans) {
+        Some(printed) => assert_eq!("1 False\n1 False\n2 True\n0 False\n", printed),
+        None => assert!(output.contains("parts = {\"PRE\": 2, \"ACC\": 0, \"DN\": False, \"CU\": False, \"CD\": False}\n"))
+    }
+}
+
+#[test]
+fn test_build_python_one_shot() {
+    let directory = temp_dir("python_one_shot");
+    fs::write(directory.join("cell.lt"), "TAG start = TRUE
+TAG pulse = FALSE
+TAG motor = FALSE
+TASK<PERIOD=100> MainTask
+ROUTINE Main
+RUNG
+XIC start
+ONS pulse
+OTE motor
+ENDRUNG
+ENDROUTINE
+ENDTASK
+").unwrap();
+    Command::cargo_bin("LogTextCompiler").unwrap()
+        .current_dir(&directory)
+        .args(["-s", "cell.lt"])
+        .assert()
+        .success();
+
+    // The rung is only true on the first of two scans with start on, until start goes off and on again
+    let output = fs::read_to_string(directory.join("cell.out")).unwrap();
+    let scans = "MainTask__Main()\nprint(motor)\nMainTask__Main()\nprint(motor)\n\
+                 start = False\nMainTask__Main()\nstart = True\nMainTask__Main()\nprint(motor)\n";
+    match run_compiled_python(&output, scans) {
+        Some(printed) => assert_eq!("True\nFalse\nTrue\n", printed),
+        None => assert!(output.contains("\tpulse = rung_0_ons_0\n"))
+    }
+}
+
+#[test]
+fn test_build_python_jumps() {
+    let directory = temp_dir("python_jumps");
+    fs::write(directory.join("cell.lt"), "TAG stop = FALSE
+TAG motor = FALSE
+INT count = 0
+TASK<PERIOD=100> MainTask
+ROUTINE Main
+RUNG
+XIC stop
+JMP skip
+ENDRUNG
+RUNG
+ADD count 1 count
+OTE motor
+ENDRUNG
+RUNG
+LBL skip
+ENDRUNG
+ENDROUTINE
+ENDTASK
+").unwrap();
+    Command::cargo_bin("LogTextCompiler").unwrap()
+        .current_dir(&directory)
+        .args(["-s", "cell.lt"])
+        .assert()
+        .success();
+
+    // While stop is on, the rung between the JMP and its label isn't run at all
+    let output = fs::read_to_string(directory.join("cell.out")).unwrap();
+    match run_compiled_python(&output, "MainTask__Main()\nprint(count, motor)\nstop = True\nmotor = False\nMainTask__Main()\nprint(count, motor)\n") {
+        Some(printed) => assert_eq!("1 True\n1 False\n", printed),
+        None => assert!(output.contains("\t\tif step_label == \"skip\":\n"))
+    }
+}
+
+#[test]
+fn test_build_python_tag_index() {
+    let directory = temp_dir("python_tag_index");
+    fs::write(directory.join("cell.lt"), "TAG[3] lights = FALSE
+INT cursor = 5
+TASK<PERIOD=100> MainTask
+ROUTINE Main
+RUNG
+OTL lights.cursor
+ENDRUNG
+ENDROUTINE
+ENDTASK
+").unwrap();
+
+    let modes = [("raise", "Index cursor of lights is out of bounds",
+                  "\t\tif not 0 <= cursor <= 2: raise IndexError(\"Index cursor of lights is out of bounds\")\n\t\tlights[cursor] = True\n"),
+                 ("clamp", "[False, False, True]", "\t\tlights[min(max(cursor, 0), 2)] = True\n")];
+    for (mode, expected, guard) in modes {
+        Command::cargo_bin("LogTextCompiler").unwrap()
+            .current_dir(&directory)
+            .args(["-s", "cell.lt", "--index-bounds", mode])
+            .assert()
+            .success();
+
+        let output = fs::read_to_string(directory.join("cell.out")).unwrap();
+        match run_compiled_python(&output, "try:\n\tMainTask__Main()\n\tprint(lights)\nexcept IndexError as error:\n\tprint(error)\n") {
+            Some(printed) => assert_eq!(format!("{}\n", expected), printed),
+            None => assert!(output.contains(guard), "{}", output)
+        }
+    }
+}
+
+#[test]
+fn test_build_cache() {
+    let directory = temp_dir("cache");
+    fs::write(directory.join("cell.lt"), "TAG motor = FALSE
+TASK<PERIOD=25> MainTask
+ROUTINE Main
+RUNG
+OTE motor
+ENDRUNG
+ENDROUTINE
+ENDTASK
+").unwrap();
+    let build = |args: &[&str]| {
+        let output = Command::cargo_bin("LogTextCompiler").unwrap()
+            .current_dir(&directory)
+            .args(["-s", "cell.lt", "--cache-dir", "cache", "--scheduler-tick", "10"])
+            .args(args)
+            .assert()
+            .success()
+            .get_output()
+            .clone();
+        (String::from_utf8(output.stderr).unwrap(), fs::read_to_string(directory.join("cell.out")).unwrap())
+    };
+    let warning = "Warning: Period 25 is not a multiple of the scheduler tick 10. The effective period will be 30\n";
+
+    let (stderr, compiled) = build(&[]);
+    assert_eq!(warning, stderr);
+    let entries = fs::read_dir(directory.join("cache")).unwrap().map(|entry| entry.unwrap().path()).collect::<Vec<_>>();
+    assert_eq!(1, entries.len());
+    let key = entries[0].file_name().unwrap().to_string_lossy().to_string();
+
+    // The same build again is served from the cache, warnings included
+    fs::remove_file(directory.join("cell.out")).unwrap();
+    let (stderr, cached) = build(&[]);
+    assert_eq!(format!("Note: cell.out is taken from cache entry {}\n{}", key, warning), stderr);
+    assert_eq!(compiled, cached);
+
+    // Other options give other output
+    let (stderr, _) = build(&["--sort-tags", "name"]);
+    assert_eq!(warning, stderr);
+    assert_eq!(2, fs::read_dir(directory.join("cache")).unwrap().count());
+
+    // A damaged entry is compiled again rather than served, and replaced
+    fs::write(entries[0].join("output"), "# Compiled by LogTextCompiler").unwrap();
+    let (stderr, recompiled) = build(&[]);
+    assert_eq!(format!("Note: cache entry {} is ignored because its output doesn't match its hash\n{}", key, warning), stderr);
+    assert_eq!(compiled, recompiled);
+    let (stderr, _) = build(&[]);
+    assert!(stderr.starts_with("Note: cell.out is taken from cache entry"), "{}", stderr);
+
+    // Release builds can keep the cache out entirely
+    let (stderr, _) = build(&["--cache-mode", "off"]);
+    assert_eq!(warning, stderr);
+}
+
+#[test]
+fn test_build_conflicting_flags() {
+    // Every conflict is reported in one usage error
+    let output = Command::cargo_bin("LogTextCompiler").unwrap()
+        .args(["-s", EXAMPLE, "--check", "--plan", "--stats", "-o", "conflicting.out"])
+        .assert()
+        .code(2)
+        .get_output()
+        .clone();
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.starts_with("Note: --check writes no output, so --out has no effect\nerror: Conflicting options:\n"), "{}", stderr);
+    assert!(stderr.contains("\n    --check compiles without planning any output, while --plan plans output without compiling\n"));
+    assert!(stderr.contains("\n    --check stops before the output is generated, so --stats would have nothing to measure\n"));
+    assert!(stderr.contains("\n    --plan doesn't compile the program, so --stats would have nothing to measure\n"));
+    assert!(!std::path::Path::new("conflicting.out").exists());
+
+    // Redundant flags only get a note
+    let output = Command::cargo_bin("LogTextCompiler").unwrap()
+        .args(["build", "-s", EXAMPLE, "--check", "--sort-tags", "name"])
+        .assert()
+        .success()
+        .get_output()
+        .clone();
+    assert_eq!("Note: --check writes no output, so --sort-tags has no effect\n", String::from_utf8(output.stderr).unwrap());
+}